@@ -0,0 +1,16 @@
+use std::io;
+use std::process;
+
+use go_template::lsp::Server;
+use go_template::Template;
+
+fn main() {
+    let mut server = Server::new(Template::default());
+    let code = server
+        .run(&mut io::stdin().lock(), &mut io::stdout().lock())
+        .unwrap_or_else(|e| {
+            eprintln!("gtmpl-lsp: {}", e);
+            1
+        });
+    process::exit(code);
+}
@@ -0,0 +1,12 @@
+use std::io;
+use std::process;
+
+fn main() {
+    let code = go_template::cli::run(
+        std::env::args_os().skip(1),
+        &mut io::stdin().lock(),
+        &mut io::stdout().lock(),
+        &mut io::stderr().lock(),
+    );
+    process::exit(code);
+}
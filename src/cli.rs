@@ -0,0 +1,889 @@
+//! The `gtmpl` command line renderer.
+//!
+//! The binary only forwards its arguments and standard streams to `run`, so
+//! the whole command line behaviour can be used and tested as a library.
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::data::Data;
+use crate::document::{Document, Table};
+use crate::error::CliError;
+use crate::exec::Context;
+use crate::lint::{self, Finding};
+use crate::schema::{self, Schema};
+use crate::template::Template;
+use crate::watch::{Changes, Watcher};
+
+/// Exit code for a successful run.
+pub const EXIT_OK: i32 = 0;
+/// Exit code if rendering the template failed.
+pub const EXIT_EXEC: i32 = 1;
+/// Exit code of `gtmpl lint` if there are findings.
+pub const EXIT_FINDINGS: i32 = 1;
+/// Exit code for invalid command line arguments.
+pub const EXIT_USAGE: i32 = 2;
+/// Exit code if reading templates or data or writing the output failed.
+pub const EXIT_INPUT: i32 = 3;
+/// Exit code if a template could not be parsed.
+pub const EXIT_PARSE: i32 = 4;
+
+const USAGE: &str = "\
+Usage: gtmpl [OPTIONS] TEMPLATE
+       gtmpl lint [--json] [--schema FILE] TEMPLATE...
+       gtmpl schema [--define FILE]... [-o FILE] TEMPLATE
+       gtmpl watch [OPTIONS] TEMPLATE
+
+Renders the Go template in the file TEMPLATE (- for stdin).
+
+Options:
+  -d, --data FILE         Data for the template (.json, .yaml, .yml or .toml,
+                          - for stdin)
+  -f, --format FORMAT     Format of the data: json, yaml or toml
+      --define FILE       Parse FILE as additional template named after the
+                          file name (repeatable)
+      --set PATH=VALUE    Set a value in the data, e.g. --set user.name=foo.
+                          VALUE is parsed as JSON and taken as string if that
+                          fails (repeatable)
+  -o, --output FILE       Write the output to FILE instead of stdout
+  -h, --help              Print this help
+  -V, --version           Print the version
+
+Exit codes: 0 success, 1 render error, 2 usage error, 3 input or output error,
+4 parse error.
+";
+
+const WATCH_USAGE: &str = "\
+Usage: gtmpl watch [OPTIONS] TEMPLATE
+
+Renders the Go template in the file TEMPLATE like gtmpl and renders it again
+whenever TEMPLATE, a template in a --dir or the data changes. Errors are
+printed and the last output is kept until the next successful render.
+
+Options:
+      --dir DIR           Parse all files in DIR and its subdirectories with
+                          the extension of TEMPLATE as additional templates
+                          named after the file name (repeatable)
+      --interval MS       Check for changes every MS milliseconds
+                          (default 500)
+
+All options of gtmpl are supported, but no file can be read from stdin.
+";
+
+const DEFAULT_INTERVAL: u64 = 500;
+
+const SCHEMA_USAGE: &str = "\
+Usage: gtmpl schema [--define FILE]... [-o FILE] TEMPLATE
+
+Prints the JSON Schema of the data the Go template in the file TEMPLATE
+(- for stdin) reads.
+
+Options:
+      --define FILE       Parse FILE as additional template named after the
+                          file name (repeatable)
+  -o, --output FILE       Write the schema to FILE instead of stdout
+  -h, --help              Print this help
+
+Exit codes: 0 success, 2 usage error, 3 input or output error, 4 parse error.
+";
+
+const LINT_USAGE: &str = "\
+Usage: gtmpl lint [--json] [--schema FILE] TEMPLATE...
+
+Checks the Go templates in the files TEMPLATE for undefined and unused
+templates, unused and shadowed variables, unknown functions and constant
+conditions.
+
+Options:
+      --json              Print the findings as JSON array
+      --schema FILE       Also check the data the templates read against the
+                          JSON Schema in FILE
+  -h, --help              Print this help
+
+Exit codes: 0 no findings, 1 findings, 2 usage error, 3 input error,
+4 parse error.
+";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+
+    fn from_path(path: &str) -> Option<Format> {
+        Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(Format::from_name)
+    }
+
+    fn parse(self, s: &str) -> Result<Document, CliError> {
+        let doc = match self {
+            Format::Json => Document::from_json_str(s),
+            Format::Yaml => Document::from_yaml_str(s),
+            Format::Toml => Document::from_toml_str(s),
+        };
+        doc.map_err(|e| CliError::Input(e.to_string()))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    template: Option<String>,
+    data: Option<String>,
+    format: Option<Format>,
+    defines: Vec<String>,
+    sets: Vec<(String, String)>,
+    output: Option<String>,
+    dirs: Vec<String>,
+    interval: Option<u64>,
+    help: bool,
+    version: bool,
+}
+
+impl Options {
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, CliError> {
+        let mut opts = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.find('=') {
+                Some(i) if arg.starts_with("--") => (&arg[..i], Some(arg[i + 1..].to_owned())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = |name: &str| {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| CliError::Usage(format!("{} requires a value", name)))
+            };
+            match flag {
+                "-h" | "--help" => opts.help = true,
+                "-V" | "--version" => opts.version = true,
+                "-d" | "--data" => opts.data = Some(value(flag)?),
+                "-o" | "--output" => opts.output = Some(value(flag)?),
+                "--define" => opts.defines.push(value(flag)?),
+                "--dir" => opts.dirs.push(value(flag)?),
+                "--interval" => {
+                    let ms = value(flag)?;
+                    let ms = ms
+                        .parse()
+                        .map_err(|_| CliError::Usage(format!("invalid interval {}", ms)))?;
+                    opts.interval = Some(ms);
+                }
+                "-f" | "--format" => {
+                    let name = value(flag)?;
+                    let format = Format::from_name(&name)
+                        .ok_or_else(|| CliError::Usage(format!("unknown format {}", name)))?;
+                    opts.format = Some(format);
+                }
+                "--set" => {
+                    let set = value(flag)?;
+                    let (path, val) = set.split_once('=').ok_or_else(|| {
+                        CliError::Usage(format!("--set {} needs PATH=VALUE", set))
+                    })?;
+                    opts.sets.push((path.to_owned(), val.to_owned()));
+                }
+                "-" => opts.set_template(arg.clone())?,
+                _ if arg.starts_with('-') => {
+                    return Err(CliError::Usage(format!("unknown option {}", arg)))
+                }
+                _ => opts.set_template(arg.clone())?,
+            }
+        }
+        Ok(opts)
+    }
+
+    fn set_template(&mut self, template: String) -> Result<(), CliError> {
+        if self.template.is_some() {
+            return Err(CliError::Usage(
+                "only one TEMPLATE can be rendered, use --define for partials".into(),
+            ));
+        }
+        self.template = Some(template);
+        Ok(())
+    }
+
+    // The files TEMPLATE, --data and --define read, "-" for stdin.
+    fn inputs(&self) -> impl Iterator<Item = &str> {
+        self.template
+            .as_deref()
+            .into_iter()
+            .chain(self.data.as_deref())
+            .chain(self.defines.iter().map(String::as_str))
+    }
+}
+
+/// Runs the command line renderer with `args` (without the program name).
+/// Returns the process exit code.
+pub fn run<I>(args: I, stdin: &mut dyn Read, stdout: &mut dyn Write, stderr: &mut dyn Write) -> i32
+where
+    I: IntoIterator,
+    I::Item: Into<OsString>,
+{
+    let args = args
+        .into_iter()
+        .map(|arg| {
+            arg.into().into_string().map_err(|arg| {
+                CliError::Usage(format!("invalid UTF-8 in {}", arg.to_string_lossy()))
+            })
+        })
+        .collect::<Result<Vec<String>, CliError>>();
+    let result = args.and_then(|args| {
+        let mut args = args.into_iter().peekable();
+        match args.peek().map(String::as_str) {
+            Some("lint") => lint(args.skip(1), stdin, stdout),
+            Some("schema") => schema(args.skip(1), stdin, stdout).map(|_| EXIT_OK),
+            Some("watch") => watch(args.skip(1), stdout, stderr),
+            _ => render(args, stdin, stdout).map(|_| EXIT_OK),
+        }
+    });
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            let _ = writeln!(stderr, "gtmpl: {}", e);
+            if let CliError::Usage(_) = e {
+                let _ = writeln!(stderr, "Try 'gtmpl --help' for more information.");
+            }
+            e.exit_code()
+        }
+    }
+}
+
+fn render<I: IntoIterator<Item = String>>(
+    args: I,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> Result<(), CliError> {
+    let opts = Options::parse(args)?;
+    if opts.help {
+        return write_output(stdout, USAGE);
+    }
+    if opts.version {
+        return write_output(stdout, &format!("gtmpl {}\n", env!("CARGO_PKG_VERSION")));
+    }
+    let path = opts
+        .template
+        .as_deref()
+        .ok_or_else(|| CliError::Usage("missing TEMPLATE".into()))?;
+    if !opts.dirs.is_empty() || opts.interval.is_some() {
+        return Err(CliError::Usage(
+            "--dir and --interval are only supported by gtmpl watch".into(),
+        ));
+    }
+    read_stdin_once(opts.inputs())?;
+
+    let tmpl = parse_templates(&opts, path, Template::with_name(template_name(path)), stdin)?;
+    let ctx = Context::with_dot(load_data(&opts, stdin)?);
+    let output = tmpl
+        .render(&ctx)
+        .map_err(|e| CliError::Exec(e.to_string()))?;
+    match opts.output {
+        Some(ref out) => write_file(out, &output),
+        None => write_output(stdout, &output),
+    }
+}
+
+fn schema<I: IntoIterator<Item = String>>(
+    args: I,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> Result<(), CliError> {
+    let opts = Options::parse(args)?;
+    if opts.help {
+        return write_output(stdout, SCHEMA_USAGE);
+    }
+    let path = opts
+        .template
+        .as_deref()
+        .ok_or_else(|| CliError::Usage("missing TEMPLATE".into()))?;
+    read_stdin_once(opts.inputs())?;
+    // Functions are not needed to read the fields.
+    let tmpl = Template {
+        skip_func_check: true,
+        ..Template::with_name(template_name(path))
+    };
+    let tmpl = parse_templates(&opts, path, tmpl, stdin)?;
+    let json = tmpl.infer_schema().to_json();
+    match opts.output {
+        Some(ref out) => write_file(out, &json),
+        None => write_output(stdout, &json),
+    }
+}
+
+fn watch<I: IntoIterator<Item = String>>(
+    args: I,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> Result<i32, CliError> {
+    let opts = Options::parse(args)?;
+    if opts.help {
+        write_output(stdout, WATCH_USAGE)?;
+        return Ok(EXIT_OK);
+    }
+    let mut watcher = watcher(&opts)?;
+    let interval = Duration::from_millis(opts.interval.unwrap_or(DEFAULT_INTERVAL));
+    let mut result = Ok(());
+    // Only stops if stdout is gone, everything else is reported and retried.
+    watcher.watch(interval, |tmpl, changes| {
+        result = on_change(tmpl, changes, &opts, stdout, stderr);
+        result.is_ok()
+    });
+    result.map(|_| EXIT_OK)
+}
+
+fn watcher(opts: &Options) -> Result<Watcher, CliError> {
+    let path = opts
+        .template
+        .as_deref()
+        .ok_or_else(|| CliError::Usage("missing TEMPLATE".into()))?;
+    if opts.inputs().any(|p| p == "-") {
+        return Err(CliError::Usage(
+            "gtmpl watch can not read from stdin".into(),
+        ));
+    }
+
+    let mut watcher = Watcher::new(Template::with_name(template_name(path)));
+    watcher.add_template_file(path);
+    for define in &opts.defines {
+        watcher.add_template_file(define);
+    }
+    let ext = Path::new(path).extension().and_then(|e| e.to_str());
+    for dir in &opts.dirs {
+        watcher.add_dir(dir, ext);
+    }
+    if let Some(ref data) = opts.data {
+        watcher.add_file(data);
+    }
+    Ok(watcher)
+}
+
+// Reports the errors of a poll and renders again if there are none. Only
+// fails if writing to stdout fails.
+fn on_change(
+    tmpl: &Template,
+    changes: &Changes,
+    opts: &Options,
+    stdout: &mut dyn Write,
+    stderr: &mut dyn Write,
+) -> Result<(), CliError> {
+    for (path, e) in &changes.errors {
+        let _ = writeln!(stderr, "gtmpl: {}: {}", path.display(), e);
+    }
+    if !changes.errors.is_empty() {
+        return Ok(());
+    }
+    let output = load_data(opts, &mut io::empty()).and_then(|data| {
+        tmpl.render(&Context::with_dot(data))
+            .map_err(|e| CliError::Exec(e.to_string()))
+    });
+    match (output, &opts.output) {
+        (Ok(output), Some(out)) => match write_file(out, &output) {
+            Ok(()) => {
+                let _ = writeln!(stderr, "gtmpl: wrote {}", out);
+            }
+            Err(e) => {
+                let _ = writeln!(stderr, "gtmpl: {}", e);
+            }
+        },
+        (Ok(output), None) => write_output(stdout, &output)?,
+        (Err(e), _) => {
+            let _ = writeln!(stderr, "gtmpl: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn lint<I: IntoIterator<Item = String>>(
+    args: I,
+    stdin: &mut dyn Read,
+    stdout: &mut dyn Write,
+) -> Result<i32, CliError> {
+    let mut json = false;
+    let mut schema = None;
+    let mut paths = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--schema" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| CliError::Usage("--schema requires a value".into()))?;
+                schema = Some(path);
+            }
+            "-h" | "--help" => {
+                write_output(stdout, LINT_USAGE)?;
+                return Ok(EXIT_OK);
+            }
+            "-" => paths.push(arg),
+            _ if arg.starts_with('-') => {
+                return Err(CliError::Usage(format!("unknown option {}", arg)))
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.is_empty() {
+        return Err(CliError::Usage("missing TEMPLATE".into()));
+    }
+    read_stdin_once(paths.iter().chain(&schema).map(String::as_str))?;
+    let schema = match schema {
+        Some(path) => {
+            let text = read_input(&path, stdin)?;
+            let schema = Schema::from_json_str(&text)
+                .map_err(|e| CliError::Input(format!("{}: {}", path, e)))?;
+            Some(schema)
+        }
+        None => None,
+    };
+
+    // Remember which file defined each template to report file names.
+    let mut files = std::collections::HashMap::new();
+    let mut tmpl = Template {
+        skip_func_check: true,
+        ..Default::default()
+    };
+    for path in &paths {
+        let text = read_input(path, stdin)?;
+        tmpl.add_template(template_name(path), text)
+            .map_err(|e| CliError::Parse(format!("{}: {}", path, e)))?;
+        for name in tmpl.tree_set.keys() {
+            files.entry(name.clone()).or_insert_with(|| path.clone());
+        }
+    }
+    let entries: Vec<String> = paths.iter().map(|p| template_name(p)).collect();
+    let entries: Vec<&str> = entries.iter().map(String::as_str).collect();
+    let mut findings = lint::lint(&tmpl, &entries);
+    if let Some(schema) = schema {
+        for entry in &entries {
+            findings.extend(schema::check(&tmpl, entry, &schema));
+        }
+        lint::sort(&mut findings);
+        findings.dedup();
+    }
+
+    let file = |f: &Finding| files.get(&f.template).cloned().unwrap_or_default();
+    let output = if json {
+        let findings: Vec<_> = findings
+            .iter()
+            .map(|f| {
+                serde_json::json!({
+                    "file": file(f),
+                    "template": f.template,
+                    "line": f.line,
+                    "column": f.column,
+                    "kind": f.kind.code(),
+                    "message": f.message,
+                })
+            })
+            .collect();
+        format!("{}\n", serde_json::Value::Array(findings))
+    } else {
+        findings
+            .iter()
+            .map(|f| {
+                format!(
+                    "{}:{}:{}: {} [{}]\n",
+                    file(f),
+                    f.line,
+                    f.column,
+                    f.message,
+                    f.kind
+                )
+            })
+            .collect()
+    };
+    write_output(stdout, &output)?;
+    Ok(if findings.is_empty() {
+        EXIT_OK
+    } else {
+        EXIT_FINDINGS
+    })
+}
+
+// Parses the `--define` files and then `path` into `tmpl`.
+fn parse_templates(
+    opts: &Options,
+    path: &str,
+    mut tmpl: Template,
+    stdin: &mut dyn Read,
+) -> Result<Template, CliError> {
+    for define in &opts.defines {
+        let text = read_input(define, stdin)?;
+        tmpl.add_template(template_name(define), text)
+            .map_err(|e| CliError::Parse(e.to_string()))?;
+    }
+    let text = read_input(path, stdin)?;
+    tmpl.parse(text)
+        .map_err(|e| CliError::Parse(e.to_string()))?;
+    Ok(tmpl)
+}
+
+fn load_data(opts: &Options, stdin: &mut dyn Read) -> Result<Data, CliError> {
+    let mut doc = match opts.data {
+        Some(ref path) => {
+            let format = opts
+                .format
+                .or_else(|| Format::from_path(path))
+                .or(if path == "-" {
+                    Some(Format::Json)
+                } else {
+                    None
+                })
+                .ok_or_else(|| {
+                    CliError::Usage(format!("unknown data format for {}, use --format", path))
+                })?;
+            let text = read_input(path, stdin)?;
+            format
+                .parse(&text)
+                .map_err(|e| CliError::Input(format!("{}: {}", path, e)))?
+        }
+        None => Document::Table(Table::default()),
+    };
+    for (path, val) in &opts.sets {
+        let val = Document::from_json_str(val).unwrap_or_else(|_| Document::String(val.clone()));
+        match doc {
+            Document::Table(ref mut t) => t.set_path(path, val),
+            _ => Err("data is not a table".into()),
+        }
+        .map_err(|e| CliError::Usage(format!("--set {}: {}", path, e)))?;
+    }
+    Ok(doc.into_data())
+}
+
+fn template_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(path)
+        .to_owned()
+}
+
+// Fails if more than one of `paths` is "-", stdin is empty after the first.
+fn read_stdin_once<'a, I: IntoIterator<Item = &'a str>>(paths: I) -> Result<(), CliError> {
+    if paths.into_iter().filter(|&p| p == "-").count() > 1 {
+        return Err(CliError::Usage("stdin (-) can only be read once".into()));
+    }
+    Ok(())
+}
+
+fn read_input(path: &str, stdin: &mut dyn Read) -> Result<String, CliError> {
+    let mut text = String::new();
+    let read = if path == "-" {
+        stdin.read_to_string(&mut text).map(|_| ())
+    } else {
+        fs::read_to_string(path).map(|t| text = t)
+    };
+    read.map_err(|e| CliError::Input(format!("{}: {}", path, e)))?;
+    Ok(text)
+}
+
+fn write_file(path: &str, s: &str) -> Result<(), CliError> {
+    fs::write(path, s).map_err(|e| CliError::Input(format!("{}: {}", path, e)))
+}
+
+fn write_output(out: &mut dyn Write, s: &str) -> Result<(), CliError> {
+    out.write_all(s.as_bytes())
+        .and_then(|_| out.flush())
+        .map_err(|e| CliError::Input(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests_mocked {
+    use super::*;
+    use std::path::PathBuf;
+
+    struct Run {
+        code: i32,
+        stdout: String,
+        stderr: String,
+    }
+
+    fn gtmpl(args: &[&str], stdin: &str) -> Run {
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+        let code = run(
+            args.iter().map(|s| s.to_string()),
+            &mut stdin.as_bytes(),
+            &mut stdout,
+            &mut stderr,
+        );
+        Run {
+            code,
+            stdout: String::from_utf8(stdout).unwrap(),
+            stderr: String::from_utf8(stderr).unwrap(),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gtmpl-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(dir: &Path, name: &str, content: &str) -> String {
+        let path = dir.join(name);
+        fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn test_render() {
+        let dir = temp_dir("render");
+        let main = write(
+            &dir,
+            "main.tmpl",
+            r#"{{ template "head.tmpl" . }} {{ .b.c }}"#,
+        );
+        let head = write(&dir, "head.tmpl", "{{ .a }}");
+        let data = write(&dir, "data.yaml", "a: 1\nb: {c: x}\n");
+
+        let run = gtmpl(
+            &[&main, "--define", &head, "-d", &data, "--set", "b.c=y"],
+            "",
+        );
+        assert_eq!(run.stderr, "");
+        assert_eq!((run.code, run.stdout.as_str()), (EXIT_OK, "1 y"));
+
+        let out = dir.join("out.txt");
+        let run = gtmpl(
+            &[
+                "--data=-",
+                "--set",
+                "a=2",
+                "-o",
+                out.to_str().unwrap(),
+                &main,
+                "--define",
+                &head,
+            ],
+            r#"{"b": {"c": true}}"#,
+        );
+        assert_eq!((run.code, run.stdout.as_str()), (EXIT_OK, ""));
+        assert_eq!(fs::read_to_string(&out).unwrap(), "2 true");
+
+        let run = gtmpl(&["-", "--set", "x=\"q\""], "{{ .x }}");
+        assert_eq!((run.code, run.stdout.as_str()), (EXIT_OK, "q"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        let dir = temp_dir("exit");
+        let bad = write(&dir, "bad.tmpl", "{{ if }}");
+        let run = gtmpl(&[&bad], "");
+        assert_eq!(run.code, EXIT_PARSE);
+        assert!(
+            run.stderr.starts_with("gtmpl: template: bad.tmpl:"),
+            "{}",
+            run.stderr
+        );
+
+        let run = gtmpl(&["-"], "{{ .a.b }}");
+        assert_eq!(run.code, EXIT_EXEC);
+
+        let missing = dir.join("missing.tmpl");
+        let run = gtmpl(&[missing.to_str().unwrap()], "");
+        assert_eq!(run.code, EXIT_INPUT);
+
+        let run = gtmpl(&["-", "-d", "-"], "");
+        assert_eq!(run.code, EXIT_USAGE);
+        let run = gtmpl(&["-d", "-", "--define", "-", &bad], "");
+        assert_eq!(run.code, EXIT_USAGE);
+        assert!(
+            run.stderr.contains("can only be read once"),
+            "{}",
+            run.stderr
+        );
+        let run = gtmpl(&["lint", "-", "-"], "");
+        assert_eq!(run.code, EXIT_USAGE);
+        let run = gtmpl(&["schema", "-", "--define", "-"], "");
+        assert_eq!(run.code, EXIT_USAGE);
+        let run = gtmpl(&["--nope"], "");
+        assert_eq!(run.code, EXIT_USAGE);
+        let run = gtmpl(&["-", "-d", "data.txt"], "");
+        assert_eq!(run.code, EXIT_USAGE);
+        let run = gtmpl(&["--data", "-", "--format", "toml"], "");
+        assert_eq!(run.code, EXIT_USAGE);
+
+        let run = gtmpl(&["--help"], "");
+        assert_eq!(run.code, EXIT_OK);
+        assert!(run.stdout.starts_with("Usage: gtmpl"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_args() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+        let code = run(
+            vec![OsString::from_vec(b"t\xffl".to_vec())],
+            &mut io::empty(),
+            &mut stdout,
+            &mut stderr,
+        );
+        assert_eq!(code, EXIT_USAGE);
+        let stderr = String::from_utf8(stderr).unwrap();
+        assert!(
+            stderr.starts_with("gtmpl: invalid UTF-8 in t\u{fffd}l"),
+            "{}",
+            stderr
+        );
+    }
+
+    #[test]
+    fn test_lint() {
+        let dir = temp_dir("lint");
+        let main = write(
+            &dir,
+            "main.tmpl",
+            "{{ template \"nav\" . }}\n{{ if true }}{{ end }}",
+        );
+        let nav = write(
+            &dir,
+            "nav.tmpl",
+            "{{ define \"nav\" }}{{ $x := . }}{{ end }}",
+        );
+        let run = gtmpl(&["lint", &main, &nav], "");
+        assert_eq!(run.code, EXIT_FINDINGS);
+        assert_eq!(
+            run.stdout,
+            format!(
+                "{}:2:7: condition of if is always true [constant-condition]\n\
+                 {}:1:22: $x is declared but never used [unused-variable]\n",
+                main, nav
+            )
+        );
+
+        let schema = write(
+            &dir,
+            "schema.json",
+            r#"{"type": "object", "properties": {"name": {"type": "string"}}}"#,
+        );
+        let run = gtmpl(&["lint", "--schema", &schema, &nav], "");
+        assert_eq!(run.code, EXIT_FINDINGS);
+        let run = gtmpl(&["lint", "--schema", &schema, "-"], "{{ .name.first }}");
+        assert_eq!(
+            run.stdout,
+            "-:1:9: can't read field first of string [type-mismatch]\n"
+        );
+        let run = gtmpl(&["lint", "--schema", &nav, "-"], "");
+        assert_eq!(run.code, EXIT_INPUT);
+
+        let run = gtmpl(&["lint", "--json", &nav], "");
+        assert_eq!(run.code, EXIT_FINDINGS);
+        let json: serde_json::Value = serde_json::from_str(&run.stdout).unwrap();
+        assert_eq!(json[0]["kind"], "unused-define");
+        assert_eq!(json[0]["file"], nav.as_str());
+        assert_eq!(json[1]["kind"], "unused-variable");
+
+        let run = gtmpl(
+            &["lint", "-"],
+            "{{ template \"x\" }}{{ define \"x\" }}{{ end }}",
+        );
+        assert_eq!((run.code, run.stdout.as_str()), (EXIT_OK, ""));
+        let run = gtmpl(&["lint", "-"], "{{ if }}");
+        assert_eq!(run.code, EXIT_PARSE);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_schema() {
+        let dir = temp_dir("schema");
+        let user = write(&dir, "user.tmpl", "{{ .name | upper }}");
+        let run = gtmpl(
+            &["schema", "--define", &user, "-"],
+            r#"{{ range .users }}{{ template "user.tmpl" . }}{{ end }}"#,
+        );
+        assert_eq!(run.code, EXIT_OK, "{}", run.stderr);
+        let json: serde_json::Value = serde_json::from_str(&run.stdout).unwrap();
+        assert_eq!(
+            json["properties"]["users"]["items"]["properties"]["name"],
+            serde_json::json!({})
+        );
+        assert_eq!(gtmpl(&["schema", "-"], "{{ if }}").code, EXIT_PARSE);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_watch() {
+        let dir = temp_dir("watch-cli");
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        let main = write(&dir, "main.tmpl", r#"{{ template "nav.tmpl" . }}!"#);
+        let nav = write(&dir, "partials/nav.tmpl", "{{ .a }}");
+        let data = write(&dir, "data.json", r#"{"a": 1}"#);
+        let out = dir.join("out.txt");
+        let partials = dir.join("partials");
+        let args = [
+            main.as_str(),
+            "--dir",
+            partials.to_str().unwrap(),
+            "-d",
+            &data,
+            "-o",
+            out.to_str().unwrap(),
+        ];
+        let opts = Options::parse(args.iter().map(|s| s.to_string())).unwrap();
+        let mut watcher = watcher(&opts).unwrap();
+        let changed = |watcher: &mut Watcher| {
+            let changes = watcher.poll();
+            let mut stderr = vec![];
+            on_change(
+                watcher.template(),
+                &changes,
+                &opts,
+                &mut vec![],
+                &mut stderr,
+            )
+            .unwrap();
+            String::from_utf8(stderr).unwrap()
+        };
+
+        assert_eq!(
+            changed(&mut watcher),
+            format!("gtmpl: wrote {}\n", out.display())
+        );
+        assert_eq!(fs::read_to_string(&out).unwrap(), "1!");
+        write(&dir, "data.json", r#"{"a": 22}"#);
+        changed(&mut watcher);
+        assert_eq!(fs::read_to_string(&out).unwrap(), "22!");
+
+        write(&dir, "partials/nav.tmpl", "{{ .a ");
+        let stderr = changed(&mut watcher);
+        assert!(
+            stderr.starts_with(&format!("gtmpl: {}: ", nav)),
+            "{}",
+            stderr
+        );
+        assert_eq!(fs::read_to_string(&out).unwrap(), "22!");
+        write(&dir, "data.json", r#"{"b": 1}"#);
+        write(&dir, "partials/nav.tmpl", "{{ .a.b }}");
+        let stderr = changed(&mut watcher);
+        assert_eq!(stderr, "gtmpl: only maps and objects have fields\n");
+        assert_eq!(fs::read_to_string(&out).unwrap(), "22!");
+
+        assert_eq!(gtmpl(&["watch", "-"], "").code, EXIT_USAGE);
+        assert_eq!(gtmpl(&["watch", &main, "-d", "-"], "").code, EXIT_USAGE);
+        assert_eq!(
+            gtmpl(&["watch", &main, "--interval", "x"], "").code,
+            EXIT_USAGE
+        );
+        assert_eq!(gtmpl(&[&main, "--dir", "x"], "").code, EXIT_USAGE);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,731 @@
+//! Compiles the parse trees of a template into a flat program for the executor.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::exec::{AsyncFunc, Limits};
+use crate::funcs::BUILTINS;
+use crate::node::*;
+use crate::parse::Tree;
+use crate::template::{Sandbox, Template, Tracked};
+
+use gtmpl_value::{Func, Value};
+
+/// A template compiled for rendering, see `Template::compile`.
+///
+/// Control structures are flattened into jumps, functions are resolved to
+/// pointers and variables to slots, so rendering no longer matches on nodes or
+/// looks names up. The program is a snapshot: changes to the template after
+/// compiling do not affect it.
+///
+/// ## Example
+///
+/// ```rust
+/// use go_template::{Context, Template};
+///
+/// let mut tmpl = Template::default();
+/// tmpl.parse("{{ range $i, $v := . }}{{ $i }}={{ $v }} {{ end }}").unwrap();
+/// let program = tmpl.compile();
+/// assert_eq!(&program.render(&Context::from(vec![1, 2])).unwrap(), "0=1 1=2 ");
+/// assert_eq!(&program.render(&Context::from(vec![3])).unwrap(), "0=3 ");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub(crate) name: String,
+    pub(crate) entry: Option<usize>,
+    pub(crate) trees: Vec<Code>,
+    pub(crate) names: HashMap<String, usize>,
+    pub(crate) limits: Limits,
+    pub(crate) templates_allowed: bool,
+    pub(crate) data_funcs_allowed: bool,
+}
+
+impl Program {
+    /// The name of the template the program starts with.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// The ops of a single tree and the number of variable slots they use. Slot 0
+// holds `$`.
+#[derive(Clone, Debug)]
+pub(crate) struct Code {
+    pub name: String,
+    pub ops: Vec<Op>,
+    // The line and column of each op.
+    pub sites: Vec<(usize, usize)>,
+    pub slots: usize,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Op {
+    // Enters a list node.
+    List,
+    Text(String),
+    // Evaluates the pipeline and prints it unless it declares variables.
+    Action(Pipe),
+    // Evaluates the pipeline and jumps to `otherwise` if it is false. A `with`
+    // pushes the value as dot. `end` is the first op after the else list.
+    Branch {
+        pipe: Pipe,
+        with: bool,
+        otherwise: usize,
+        end: usize,
+    },
+    // Leaves the body of a `with`.
+    PopDot,
+    Jump(usize),
+    // Evaluates the pipeline and starts iterating over it. `end` is the first
+    // op after the else list.
+    Range {
+        pipe: Pipe,
+        end: usize,
+    },
+    // Starts the next iteration, or ends the loop and jumps to `end`.
+    Next {
+        key: Option<usize>,
+        value: Option<usize>,
+        end: usize,
+    },
+    // Ends an iteration and jumps back to its `Next`.
+    Loop(usize),
+    Template {
+        callee: Callee,
+        pipe: Option<Pipe>,
+    },
+    // Starts a try body. If it fails the program continues at `catch` with
+    // the error message in `slot`.
+    Try {
+        catch: usize,
+        slot: Option<usize>,
+    },
+    // Ends a try body that succeeded and jumps past the catch list.
+    EndTry(usize),
+    // Starts writing into a buffer instead of the output.
+    Capture,
+    // Stores the buffer as a string in the slot.
+    EndCapture(usize),
+    Return,
+    Unknown(Box<Nodes>),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Callee {
+    Static { name: String, tree: Option<usize> },
+    Dynamic(Pipe),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Pipe {
+    pub cmds: Vec<Cmd>,
+    // The slots the value is stored in.
+    pub decl: Vec<usize>,
+    // The source of a pipeline without commands, for the error.
+    pub empty: Option<Box<PipeNode>>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Cmd {
+    Field {
+        fields: Vec<String>,
+        args: Vec<Arg>,
+    },
+    Variable {
+        var: Var,
+        args: Vec<Arg>,
+        node: Box<Nodes>,
+    },
+    Pipe(Pipe),
+    Chain {
+        chain: Chain,
+        args: Vec<Arg>,
+    },
+    BadChain(Box<ChainNode>),
+    Func {
+        func: Function,
+        args: Vec<Arg>,
+    },
+    Const {
+        value: Value,
+        node: Box<Nodes>,
+        has_args: bool,
+    },
+    Dot {
+        node: Box<Nodes>,
+        has_args: bool,
+    },
+    Invalid {
+        node: Box<Nodes>,
+        has_args: bool,
+    },
+    Empty(Box<CommandNode>),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Arg {
+    Dot,
+    Field(Vec<String>),
+    Variable(Var),
+    Pipe(Pipe),
+    Func(Function),
+    Chain(Chain),
+    BadChain(Box<ChainNode>),
+    Const(Value),
+    Invalid(Box<Nodes>),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Var {
+    pub slot: usize,
+    pub fields: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Chain {
+    pub base: Box<Arg>,
+    pub fields: Vec<String>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Function {
+    pub name: String,
+    pub func: Option<Func>,
+    pub async_func: Option<AsyncFunc>,
+    pub allowed: bool,
+    pub builtin: Builtin,
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("func", &self.func)
+            .field("async_func", &self.async_func.is_some())
+            .field("allowed", &self.allowed)
+            .field("builtin", &self.builtin)
+            .finish()
+    }
+}
+
+// Builtins the executor treats specially.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Builtin {
+    Len,
+    Index,
+    Other,
+}
+
+/// Remembers which functions of a template are still the builtins they were
+/// registered as. After `Template::funcs` was changed directly, all functions
+/// are called like custom ones.
+#[derive(Clone, Debug, Default)]
+pub struct Builtins {
+    names: HashMap<String, Builtin>,
+    // The generation of the functions the names are known for.
+    generation: u64,
+}
+
+impl Builtins {
+    pub(crate) fn new(funcs: &Tracked<HashMap<String, Func>>) -> Builtins {
+        let names = BUILTINS
+            .iter()
+            .filter_map(|&(name, _)| {
+                let builtin = match name {
+                    "len" => Builtin::Len,
+                    "index" => Builtin::Index,
+                    _ => return None,
+                };
+                Some((name.to_owned(), builtin))
+            })
+            .collect();
+        Builtins {
+            names,
+            generation: funcs.generation(),
+        }
+    }
+
+    // Forgets `name` after its function was replaced, which changed the
+    // generation of the functions from `before` to `after`.
+    pub(crate) fn replaced(&mut self, name: &str, before: u64, after: u64) {
+        self.names.remove(name);
+        if self.generation == before {
+            self.generation = after;
+        }
+    }
+
+    fn get(&self, name: &str, funcs: &Tracked<HashMap<String, Func>>) -> Builtin {
+        match self.names.get(name) {
+            Some(&builtin) if self.generation == funcs.generation() => builtin,
+            _ => Builtin::Other,
+        }
+    }
+}
+
+/// The program a template was compiled to last, see `Template::program`.
+#[derive(Default)]
+pub struct Compiled(Mutex<Option<(Arc<Program>, Source)>>);
+
+// What a program was compiled from besides the name and limits it keeps.
+#[derive(Clone)]
+struct Source {
+    sandbox: Option<Sandbox>,
+    generations: [u64; 3],
+}
+
+impl Clone for Compiled {
+    fn clone(&self) -> Compiled {
+        let cached = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        Compiled(Mutex::new(cached.clone()))
+    }
+}
+
+impl Template {
+    /// Returns the compiled program. It is compiled once and reused until any
+    /// of `name`, `funcs`, `async_funcs`, `limits`, `sandbox` or `tree_set`
+    /// changes.
+    pub fn program(&self) -> Arc<Program> {
+        let generations = [
+            self.funcs.generation(),
+            self.async_funcs.generation(),
+            self.tree_set.generation(),
+        ];
+        let mut cached = self
+            .compiled
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some((ref program, ref source)) = *cached {
+            if program.name == self.name
+                && program.limits == self.limits
+                && source.generations == generations
+                && source.sandbox == self.sandbox
+            {
+                return Arc::clone(program);
+            }
+        }
+        let program = Arc::new(self.compile());
+        let source = Source {
+            sandbox: self.sandbox.clone(),
+            generations,
+        };
+        *cached = Some((Arc::clone(&program), source));
+        program
+    }
+
+    /// Compiles the template for rendering. The render methods of `Template`
+    /// reuse a cached program, see `program`.
+    pub fn compile(&self) -> Program {
+        let mut roots: Vec<(&String, &Tree)> = self
+            .tree_set
+            .iter()
+            .filter(|(_, tree)| tree.root.is_some())
+            .collect();
+        roots.sort_by(|a, b| a.0.cmp(b.0));
+        let names: HashMap<String, usize> = roots
+            .iter()
+            .enumerate()
+            .map(|(i, &(name, _))| (name.clone(), i))
+            .collect();
+        let trees = roots
+            .iter()
+            .map(|&(_, tree)| Compiler::new(self, &names).tree(tree))
+            .collect();
+        Program {
+            name: self.name.clone(),
+            entry: names.get(&self.name).copied(),
+            trees,
+            names,
+            limits: self.limits.clone(),
+            templates_allowed: self.sandbox.as_ref().is_none_or(|s| s.templates),
+            data_funcs_allowed: self.sandbox.as_ref().is_none_or(|s| s.data_funcs),
+        }
+    }
+}
+
+// Variables already carry the slots assigned by the parser.
+struct Compiler<'t> {
+    template: &'t Template,
+    names: &'t HashMap<String, usize>,
+    ops: Vec<Op>,
+    // The source position of each op.
+    pos: Vec<Pos>,
+}
+
+impl<'t> Compiler<'t> {
+    fn new(template: &'t Template, names: &'t HashMap<String, usize>) -> Compiler<'t> {
+        Compiler {
+            template,
+            names,
+            ops: vec![],
+            pos: vec![],
+        }
+    }
+
+    fn tree(mut self, tree: &Tree) -> Code {
+        if let Some(ref root) = tree.root {
+            self.node(root);
+        }
+        self.emit(Op::Return, 0);
+        Code {
+            name: tree.name().to_owned(),
+            sites: self.pos.iter().map(|&pos| tree.line_col(pos)).collect(),
+            ops: self.ops,
+            slots: tree.slots(),
+        }
+    }
+
+    // Appends an op and returns its index.
+    fn emit(&mut self, op: Op, pos: Pos) -> usize {
+        self.ops.push(op);
+        self.pos.push(pos);
+        self.ops.len() - 1
+    }
+
+    fn nodes(&mut self, nodes: &[Nodes]) {
+        for node in nodes {
+            self.node(node);
+        }
+    }
+
+    fn node(&mut self, node: &Nodes) {
+        let pos = node.pos();
+        match *node {
+            Nodes::List(ref n) => {
+                self.emit(Op::List, pos);
+                self.nodes(&n.nodes);
+            }
+            Nodes::Text(ref n) => {
+                self.emit(Op::Text(n.text.clone()), pos);
+            }
+            Nodes::Action(ref n) => {
+                let pipe = self.pipe(&n.pipe);
+                self.emit(Op::Action(pipe), pos);
+            }
+            Nodes::If(ref n) => self.branch(n, false),
+            Nodes::With(ref n) => self.branch(n, true),
+            Nodes::Range(ref n) => self.range(n),
+            Nodes::Try(ref n) => self.try_block(n),
+            Nodes::Capture(ref n) => {
+                self.emit(Op::Capture, pos);
+                self.nodes(&n.list.nodes);
+                self.emit(Op::EndCapture(n.var.slot), pos);
+            }
+            Nodes::Template(ref n) => {
+                let callee = match n.name {
+                    PipeOrString::String(ref name) => Callee::Static {
+                        name: name.clone(),
+                        tree: self.names.get(name).copied(),
+                    },
+                    PipeOrString::Pipe(ref pipe) => Callee::Dynamic(self.pipe(pipe)),
+                };
+                let pipe = n.pipe.as_ref().map(|pipe| self.pipe(pipe));
+                self.emit(Op::Template { callee, pipe }, pos);
+            }
+            _ => {
+                self.emit(Op::Unknown(Box::new(node.clone())), pos);
+            }
+        }
+    }
+
+    fn branch(&mut self, n: &BranchNode, with: bool) {
+        let pipe = self.pipe(&n.pipe);
+        let at = self.emit(
+            Op::Branch {
+                pipe,
+                with,
+                otherwise: 0,
+                end: 0,
+            },
+            n.pos(),
+        );
+        self.nodes(&n.list.nodes);
+        if with {
+            self.emit(Op::PopDot, n.pos());
+        }
+        let target = match n.else_list {
+            Some(ref else_list) => {
+                let jump = self.emit(Op::Jump(0), else_list.pos());
+                let target = self.ops.len();
+                self.nodes(&else_list.nodes);
+                self.ops[jump] = Op::Jump(self.ops.len());
+                target
+            }
+            None => self.ops.len(),
+        };
+        let after = self.ops.len();
+        if let Op::Branch {
+            ref mut otherwise,
+            ref mut end,
+            ..
+        } = self.ops[at]
+        {
+            *otherwise = target;
+            *end = after;
+        }
+    }
+
+    // The else list of a range runs after the loop.
+    fn range(&mut self, n: &RangeNode) {
+        let pipe = self.pipe(&n.pipe);
+        let (key, value) = match pipe.decl[..] {
+            [] => (None, None),
+            [value] => (None, Some(value)),
+            [.., key, value] => (Some(key), Some(value)),
+        };
+        let at = self.emit(Op::Range { pipe, end: 0 }, n.pos());
+        let next = self.emit(Op::Next { key, value, end: 0 }, n.pos());
+        self.nodes(&n.list.nodes);
+        self.emit(Op::Loop(next), n.pos());
+        let target = self.ops.len();
+        if let Op::Next { ref mut end, .. } = self.ops[next] {
+            *end = target;
+        }
+        if let Some(ref else_list) = n.else_list {
+            self.nodes(&else_list.nodes);
+        }
+        let after = self.ops.len();
+        if let Op::Range { ref mut end, .. } = self.ops[at] {
+            *end = after;
+        }
+    }
+
+    fn try_block(&mut self, n: &TryNode) {
+        let slot = n
+            .catch
+            .as_ref()
+            .and_then(|c| c.var.as_ref())
+            .map(|v| v.slot);
+        let at = self.emit(Op::Try { catch: 0, slot }, n.pos());
+        self.nodes(&n.list.nodes);
+        let done = self.emit(Op::EndTry(0), n.pos());
+        let target = self.ops.len();
+        if let Some(ref catch_list) = n.catch_list {
+            self.nodes(&catch_list.nodes);
+        }
+        self.ops[done] = Op::EndTry(self.ops.len());
+        if let Op::Try { ref mut catch, .. } = self.ops[at] {
+            *catch = target;
+        }
+    }
+
+    fn pipe(&mut self, pipe: &PipeNode) -> Pipe {
+        Pipe {
+            cmds: pipe.cmds.iter().map(|cmd| self.command(cmd)).collect(),
+            decl: pipe.decl.iter().map(|var| var.slot).collect(),
+            empty: if pipe.cmds.is_empty() {
+                Some(Box::new(pipe.clone()))
+            } else {
+                None
+            },
+        }
+    }
+
+    fn command(&mut self, cmd: &CommandNode) -> Cmd {
+        let (first, rest) = match cmd.args.split_first() {
+            Some(split) => split,
+            None => return Cmd::Empty(Box::new(cmd.clone())),
+        };
+        let has_args = !rest.is_empty();
+        match *first {
+            Nodes::Field(ref n) => Cmd::Field {
+                fields: n.ident.clone(),
+                args: self.args(rest),
+            },
+            Nodes::Variable(ref n) => Cmd::Variable {
+                var: self.variable(n),
+                args: self.args(rest),
+                node: Box::new(first.clone()),
+            },
+            Nodes::Pipe(ref n) => Cmd::Pipe(self.pipe(n)),
+            Nodes::Chain(ref n) if chain_is_valid(n) => Cmd::Chain {
+                chain: self.chain(n),
+                args: self.args(rest),
+            },
+            Nodes::Chain(ref n) => Cmd::BadChain(Box::new(n.clone())),
+            Nodes::Identifier(ref n) => Cmd::Func {
+                func: self.function(&n.ident),
+                args: self.args(rest),
+            },
+            Nodes::Bool(ref n) => self.constant(&n.value, first, has_args),
+            Nodes::Number(ref n) => self.constant(&n.value, first, has_args),
+            Nodes::String(ref n) => self.constant(&n.value, first, has_args),
+            Nodes::Dot(_) => Cmd::Dot {
+                node: Box::new(first.clone()),
+                has_args,
+            },
+            _ => Cmd::Invalid {
+                node: Box::new(first.clone()),
+                has_args,
+            },
+        }
+    }
+
+    fn constant(&self, value: &Value, node: &Nodes, has_args: bool) -> Cmd {
+        Cmd::Const {
+            value: value.clone(),
+            node: Box::new(node.clone()),
+            has_args,
+        }
+    }
+
+    fn args(&mut self, args: &[Nodes]) -> Vec<Arg> {
+        args.iter().map(|arg| self.arg(arg)).collect()
+    }
+
+    fn arg(&mut self, node: &Nodes) -> Arg {
+        match *node {
+            Nodes::Dot(_) => Arg::Dot,
+            Nodes::Field(ref n) => Arg::Field(n.ident.clone()),
+            Nodes::Variable(ref n) => Arg::Variable(self.variable(n)),
+            Nodes::Pipe(ref n) => Arg::Pipe(self.pipe(n)),
+            Nodes::Identifier(ref n) => Arg::Func(self.function(&n.ident)),
+            Nodes::Chain(ref n) if chain_is_valid(n) => Arg::Chain(self.chain(n)),
+            Nodes::Chain(ref n) => Arg::BadChain(Box::new(n.clone())),
+            Nodes::String(ref n) => Arg::Const(n.value.clone()),
+            Nodes::Bool(ref n) => Arg::Const(n.value.clone()),
+            Nodes::Number(ref n) => Arg::Const(n.value.clone()),
+            _ => Arg::Invalid(Box::new(node.clone())),
+        }
+    }
+
+    fn variable(&self, var: &VariableNode) -> Var {
+        Var {
+            slot: var.slot,
+            fields: var.ident[1..].to_vec(),
+        }
+    }
+
+    fn chain(&mut self, chain: &ChainNode) -> Chain {
+        Chain {
+            base: Box::new(self.arg(&chain.node)),
+            fields: chain.field.clone(),
+        }
+    }
+
+    fn function(&self, name: &str) -> Function {
+        Function {
+            name: name.to_owned(),
+            func: self.template.funcs.get(name).copied(),
+            async_func: self.template.async_funcs.get(name).cloned(),
+            allowed: self
+                .template
+                .sandbox
+                .as_ref()
+                .is_none_or(|s| s.allows_func(name)),
+            builtin: self.template.builtins.get(name, &self.template.funcs),
+        }
+    }
+}
+
+fn chain_is_valid(chain: &ChainNode) -> bool {
+    !chain.field.is_empty() && !matches!(*chain.node, Nodes::Nil(_))
+}
+
+#[cfg(test)]
+mod tests_mocked {
+    use super::*;
+    use crate::exec::Context;
+    use crate::funcs;
+    use gtmpl_value::{FuncError, Value};
+
+    #[test]
+    fn test_compile() {
+        let mut t = Template::default();
+        t.parse(r#"{{ define "item" }}[{{ . }}]{{ end }}{{ range . }}{{ template "item" . }}{{ else }}!{{ end }}"#)
+            .unwrap();
+        let program = t.compile();
+        assert_eq!(program.trees.len(), 2);
+        assert_eq!(
+            program.render(&Context::from(vec![1, 2])).unwrap(),
+            "[1][2]!"
+        );
+
+        // The program keeps working after the template changed.
+        t.parse(r#"{{ define "item" }}<{{ . }}>{{ end }}"#).unwrap();
+        assert_eq!(program.render(&Context::from(vec![3])).unwrap(), "[3]!");
+    }
+
+    #[test]
+    fn test_compile_vars() {
+        let mut t = Template::default();
+        t.parse(
+            r#"{{ $x := 1 }}{{ range $i, $v := . }}{{ $x = $v }}{{ $y := $i }}{{ $y }}{{ end }}{{ $x }}{{ with $x := "w" }}{{ $x }}{{ end }}{{ $x }}"#,
+        )
+        .unwrap();
+        let program = t.compile();
+        assert_eq!(program.trees[0].slots, 6);
+        assert_eq!(program.render(&Context::from(vec![7, 8])).unwrap(), "018w8");
+    }
+
+    #[test]
+    fn test_builtins() {
+        fn first(_: &[Value]) -> Result<Value, FuncError> {
+            Ok("first".into())
+        }
+
+        let mut t = Template::default();
+        t.add_func("size", funcs::len);
+        assert_eq!(t.builtins.get("len", &t.funcs), Builtin::Len);
+        assert_eq!(t.builtins.get("index", &t.funcs), Builtin::Index);
+        assert_eq!(t.builtins.get("size", &t.funcs), Builtin::Other);
+
+        t.parse("{{ index . 1 }}").unwrap();
+        assert_eq!(t.render(&Context::from(vec![1, 2])).unwrap(), "2");
+        t.add_func("index", first);
+        assert_eq!(t.builtins.get("index", &t.funcs), Builtin::Other);
+        assert_eq!(t.render(&Context::from(vec![1, 2])).unwrap(), "first");
+
+        // Direct changes can't be told apart, nothing counts as builtin.
+        let mut t = Template::default();
+        t.funcs.insert("index".to_owned(), first);
+        assert_eq!(t.builtins.get("len", &t.funcs), Builtin::Other);
+        t.parse("{{ index . 1 }}").unwrap();
+        assert_eq!(t.render(&Context::from(vec![1, 2])).unwrap(), "first");
+    }
+
+    #[test]
+    fn test_program_cached() {
+        let mut t = Template::default();
+        t.parse(
+            r#"{{ define "item" }}[{{ . }}]{{ end }}{{ range . }}{{ template "item" . }}{{ end }}"#,
+        )
+        .unwrap();
+        let program = t.program();
+        assert!(Arc::ptr_eq(&program, &t.program()));
+        assert_eq!(t.render(&Context::from(vec![1])).unwrap(), "[1]");
+        assert!(Arc::ptr_eq(&program, &t.program()));
+
+        t.limits.max_steps = Some(100);
+        let limited = t.program();
+        assert!(!Arc::ptr_eq(&program, &limited));
+        assert_eq!(limited.limits, t.limits);
+
+        t.sandbox = Some(Sandbox::default());
+        assert!(!Arc::ptr_eq(&limited, &t.program()));
+
+        t.sandbox = None;
+        t.parse(
+            r#"{{ define "item" }}<{{ . }}>{{ end }}{{ range . }}{{ template "item" . }}{{ end }}"#,
+        )
+        .unwrap();
+        assert_eq!(t.render(&Context::from(vec![2])).unwrap(), "<2>");
+        let parsed = t.program();
+        t.add_func("double", |args| Ok((args[0].to_string() + "!").into()));
+        assert!(!Arc::ptr_eq(&parsed, &t.program()));
+
+        // Changing the public fields directly is noticed as well.
+        t.parse(r#"{{ range . }}{{ double . }}{{ end }}"#).unwrap();
+        assert_eq!(t.render(&Context::from(vec!["a"])).unwrap(), "a!");
+        t.funcs.insert("double".to_owned(), |args| {
+            Ok((args[0].to_string() + "?").into())
+        });
+        assert_eq!(t.render(&Context::from(vec!["a"])).unwrap(), "a?");
+        t.tree_set.remove("");
+        assert!(t.render(&Context::from(vec!["a"])).is_err());
+
+        let copy = t.clone();
+        assert!(Arc::ptr_eq(&t.program(), &copy.program()));
+    }
+}
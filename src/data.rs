@@ -0,0 +1,151 @@
+//! Lazily evaluated template data.
+//!
+//! Instead of converting a whole data graph into a `Value` before rendering, a
+//! type can implement `TemplateData` and hand out fields, items and lengths as
+//! the template asks for them.
+use std::fmt;
+use std::sync::Arc;
+
+use gtmpl_value::Value;
+
+use crate::utils::is_true;
+
+/// A data source queried by the executor on demand.
+///
+/// Only `field` and `to_value` are required. `to_value` is used whenever the
+/// data leaves the executor, e.g. when it is printed or passed to a function.
+///
+/// ## Example
+///
+/// ```rust
+/// use go_template::{Context, Data, Template, TemplateData, Value};
+///
+/// struct User;
+///
+/// impl TemplateData for User {
+///     fn field(&self, name: &str) -> Option<Data> {
+///         match name {
+///             "Name" => Some(Value::from("Alice").into()),
+///             _ => None,
+///         }
+///     }
+///
+///     fn to_value(&self) -> Value {
+///         Value::from("<user>")
+///     }
+/// }
+///
+/// let mut tmpl = Template::default();
+/// tmpl.parse("Hello {{ .Name }}!").unwrap();
+/// let output = tmpl.render(&Context::from_data(User));
+/// assert_eq!(&output.unwrap(), "Hello Alice!");
+/// ```
+pub trait TemplateData: Send + Sync {
+    /// Returns the field `name` or `None` if there is no such field.
+    fn field(&self, name: &str) -> Option<Data>;
+
+    /// Returns the item for `key` as used by the `index` builtin.
+    fn index(&self, key: &Value) -> Option<Data> {
+        match *key {
+            Value::String(ref s) => self.field(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of items as used by the `len` builtin.
+    fn len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns `true` if `len` is known to be zero.
+    fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// Returns the `(key, item)` pairs for `range`. `None` if the data can not
+    /// be iterated. The iterator keeps the data alive, the executor takes one
+    /// item per iteration of the loop.
+    fn iter(self: Arc<Self>) -> Option<Box<dyn Iterator<Item = (Value, Data)> + Send>> {
+        None
+    }
+
+    /// Truth value for `if` and `with`. Defaults to not being empty.
+    fn is_true(&self) -> bool {
+        !self.is_empty()
+    }
+
+    /// Materialises the data into a `Value`.
+    fn to_value(&self) -> Value;
+
+    /// Names the data in error messages, which don't materialise it.
+    fn type_name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// Data handled by the executor, either a plain `Value` or a lazy data source.
+#[derive(Clone)]
+pub enum Data {
+    Value(Value),
+    Lazy(Arc<dyn TemplateData>),
+}
+
+impl Data {
+    /// Wraps a lazy data source.
+    pub fn lazy<D: TemplateData + 'static>(data: D) -> Data {
+        Data::Lazy(Arc::new(data))
+    }
+
+    /// Returns the data as `Value`, materialising lazy data.
+    pub fn to_value(&self) -> Value {
+        match *self {
+            Data::Value(ref v) => v.clone(),
+            Data::Lazy(ref d) => d.to_value(),
+        }
+    }
+
+    /// Converts the data into a `Value`, materialising lazy data.
+    pub fn into_value(self) -> Value {
+        match self {
+            Data::Value(v) => v,
+            Data::Lazy(d) => d.to_value(),
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        match *self {
+            Data::Value(ref v) => is_true(v),
+            Data::Lazy(ref d) => d.is_true(),
+        }
+    }
+}
+
+impl Default for Data {
+    fn default() -> Data {
+        Data::Value(Value::NoValue)
+    }
+}
+
+impl From<Value> for Data {
+    fn from(value: Value) -> Data {
+        Data::Value(value)
+    }
+}
+
+impl fmt::Display for Data {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Data::Value(ref v) => write!(f, "{}", v),
+            Data::Lazy(ref d) => write!(f, "{}", d.to_value()),
+        }
+    }
+}
+
+impl fmt::Debug for Data {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Data::Value(ref v) => write!(f, "Value({:?})", v),
+            Data::Lazy(_) => write!(f, "Lazy(..)"),
+        }
+    }
+}
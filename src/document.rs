@@ -0,0 +1,394 @@
+//! Data loaded from JSON, YAML or TOML documents.
+//!
+//! Documents keep the key order of the source and the distinction between
+//! signed, unsigned and floating point numbers. They are handed to the
+//! executor as lazy `TemplateData`, so `range` visits keys in document order.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use ::serde::de::{self, Deserialize, Deserializer, EnumAccess, MapAccess, SeqAccess, Visitor};
+use gtmpl_value::Value;
+
+use crate::data::{Data, TemplateData};
+use crate::error::DocumentError;
+
+// Key toml uses to pass datetimes through serde.
+const TOML_DATETIME: &str = "$__toml_private_datetime";
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Document {
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    String(String),
+    Array(Vec<Arc<Document>>),
+    Table(Table),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Table {
+    entries: Vec<(String, Arc<Document>)>,
+    index: HashMap<String, usize>,
+}
+
+impl Table {
+    pub fn get(&self, key: &str) -> Option<&Arc<Document>> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    #[cfg(feature = "cli")]
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Document> {
+        let i = *self.index.get(key)?;
+        Some(Arc::make_mut(&mut self.entries[i].1))
+    }
+
+    /// Inserts `value` at `key`. An existing key keeps its position.
+    pub fn insert(&mut self, key: String, value: Document) {
+        let value = Arc::new(value);
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    /// Sets `value` at a dotted `path` like `a.b.c`, creating missing tables.
+    #[cfg(feature = "cli")]
+    pub fn set_path(&mut self, path: &str, value: Document) -> Result<(), String> {
+        let (key, rest) = match path.find('.') {
+            Some(i) => (&path[..i], Some(&path[i + 1..])),
+            None => (path, None),
+        };
+        if key.is_empty() {
+            return Err(format!("invalid path {}", path));
+        }
+        let rest = match rest {
+            Some(rest) => rest,
+            None => {
+                self.insert(key.to_owned(), value);
+                return Ok(());
+            }
+        };
+        if self.get(key).is_none() {
+            self.insert(key.to_owned(), Document::Table(Table::default()));
+        }
+        match self.get_mut(key) {
+            Some(Document::Table(t)) => t.set_path(rest, value),
+            _ => Err(format!("{} is not a table", key)),
+        }
+    }
+}
+
+impl Document {
+    #[cfg(feature = "json")]
+    pub fn from_json_str(s: &str) -> Result<Document, DocumentError> {
+        serde_json::from_str(s).map_err(Into::into)
+    }
+
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml_str(s: &str) -> Result<Document, DocumentError> {
+        serde_yaml::from_str(s).map_err(Into::into)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(s: &str) -> Result<Document, DocumentError> {
+        toml::from_str(s).map_err(Into::into)
+    }
+
+    pub fn into_data(self) -> Data {
+        match self {
+            Document::Array(_) | Document::Table(_) => Data::Lazy(Arc::new(self)),
+            _ => Data::Value(self.to_value()),
+        }
+    }
+}
+
+fn child(doc: &Arc<Document>) -> Data {
+    match **doc {
+        Document::Array(_) | Document::Table(_) => Data::Lazy(doc.clone()),
+        _ => Data::Value(doc.to_value()),
+    }
+}
+
+impl TemplateData for Document {
+    // Like maps a missing key yields no value.
+    fn field(&self, name: &str) -> Option<Data> {
+        match *self {
+            Document::Table(ref t) => Some(t.get(name).map(child).unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    fn index(&self, key: &Value) -> Option<Data> {
+        match (self, key) {
+            (Document::Array(a), Value::Number(n)) => {
+                n.as_u64().and_then(|i| a.get(i as usize)).map(child)
+            }
+            (Document::Table(t), Value::Number(n)) => {
+                Some(t.get(&n.to_string()).map(child).unwrap_or_default())
+            }
+            (Document::Table(t), Value::String(s)) => Some(t.get(s).map(child).unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    fn len(&self) -> Option<usize> {
+        match *self {
+            Document::String(ref s) => Some(s.len()),
+            Document::Array(ref a) => Some(a.len()),
+            Document::Table(ref t) => Some(t.entries.len()),
+            _ => None,
+        }
+    }
+
+    fn iter(self: Arc<Self>) -> Option<Box<dyn Iterator<Item = (Value, Data)> + Send>> {
+        let len = match *self {
+            Document::Array(ref a) => a.len(),
+            Document::Table(ref t) => t.entries.len(),
+            _ => return None,
+        };
+        Some(Box::new((0..len).map(move |i| match *self {
+            Document::Table(ref t) => {
+                let (ref k, ref v) = t.entries[i];
+                (Value::from(k.as_str()), child(v))
+            }
+            Document::Array(ref a) => (Value::from(i), child(&a[i])),
+            _ => unreachable!(),
+        })))
+    }
+
+    fn is_true(&self) -> bool {
+        match *self {
+            Document::Null => false,
+            Document::Bool(b) => b,
+            Document::Int(i) => i != 0,
+            Document::UInt(u) => u != 0,
+            Document::Float(f) => f != 0.0,
+            _ => !self.is_empty(),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match *self {
+            Document::Null => Value::Nil,
+            Document::Bool(b) => Value::Bool(b),
+            Document::Int(i) => Value::from(i),
+            Document::UInt(u) => Value::from(u),
+            Document::Float(f) => Value::from(f),
+            Document::String(ref s) => Value::String(s.clone()),
+            Document::Array(ref a) => Value::Array(a.iter().map(|v| v.to_value()).collect()),
+            Document::Table(ref t) => Value::Map(
+                t.entries
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.to_value()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Document {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Document, D::Error> {
+        deserializer.deserialize_any(DocumentVisitor)
+    }
+}
+
+struct DocumentVisitor;
+
+impl<'de> Visitor<'de> for DocumentVisitor {
+    type Value = Document;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a document value")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Document, E> {
+        Ok(Document::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Document, E> {
+        Ok(Document::Int(v))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Document, E> {
+        Ok(Document::UInt(v))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Document, E> {
+        Ok(Document::Float(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Document, E> {
+        Ok(Document::String(v.to_owned()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Document, E> {
+        Ok(Document::String(v))
+    }
+
+    fn visit_unit<E>(self) -> Result<Document, E> {
+        Ok(Document::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Document, E> {
+        Ok(Document::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Document, D::Error> {
+        Document::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Document, A::Error> {
+        let mut a = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(v) = seq.next_element::<Document>()? {
+            a.push(Arc::new(v));
+        }
+        Ok(Document::Array(a))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Document, A::Error> {
+        let mut t = Table::default();
+        while let Some(Key(k)) = map.next_key()? {
+            if k == TOML_DATETIME {
+                let datetime: String = map.next_value()?;
+                return Ok(Document::String(datetime));
+            }
+            t.insert(k, map.next_value()?);
+        }
+        Ok(Document::Table(t))
+    }
+
+    // YAML reports tagged values as enums.
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Document, A::Error> {
+        let (tag, _): (String, _) = data.variant()?;
+        Err(de::Error::custom(format!("unsupported tag !{}", tag)))
+    }
+}
+
+// Map keys are stored as strings. Scalar keys are converted, other keys are
+// rejected.
+struct Key(String);
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        deserializer.deserialize_any(KeyVisitor)
+    }
+}
+
+struct KeyVisitor;
+
+impl<'de> Visitor<'de> for KeyVisitor {
+    type Value = Key;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a string, number or bool as map key")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<Key, E> {
+        Ok(Key(v.to_string()))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<Key, E> {
+        Ok(Key(v.to_string()))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<Key, E> {
+        Ok(Key(v.to_string()))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<Key, E> {
+        Ok(Key(v.to_string()))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Key, E> {
+        Ok(Key(v.to_owned()))
+    }
+
+    fn visit_string<E>(self, v: String) -> Result<Key, E> {
+        Ok(Key(v))
+    }
+}
+
+#[cfg(test)]
+mod tests_mocked {
+    use crate::{Context, Template};
+
+    fn render(tmpl: &str, ctx: Context) -> String {
+        let mut t = Template::default();
+        t.parse(tmpl).unwrap();
+        t.render(&ctx).unwrap()
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json() {
+        use super::*;
+
+        let ctx = Context::from_json_str(
+            r#"{"z": 1, "a": [18446744073709551615, -9223372036854775808, 0.25], "m": {"k": "v"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            render(
+                r#"{{ range $k, $v := . }}{{ $k }} {{ end }}{{ range .a }}{{ . }} {{ end }}{{ .m.k }}{{ .m.x }}{{ len .a }}"#,
+                ctx
+            ),
+            "z a m 18446744073709551615 -9223372036854775808 0.25 v<no value>3"
+        );
+
+        let doc = Document::from_json_str(r#"[1, 1.0, -1]"#).unwrap();
+        assert_eq!(
+            doc,
+            Document::Array(vec![
+                Arc::new(Document::UInt(1)),
+                Arc::new(Document::Float(1.0)),
+                Arc::new(Document::Int(-1)),
+            ])
+        );
+        assert!(Context::from_json_str(r#"{"a": }"#).is_err());
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_yaml() {
+        let ctx = Context::from_yaml_str("b: [x, y]\na: true\n1: one\n").unwrap();
+        assert_eq!(
+            render(
+                r#"{{ range $k, $v := . }}{{ $k }};{{ end }}{{ if .a }}{{ index .b 1 }} {{ index . 1 }}{{ end }}"#,
+                ctx
+            ),
+            "b;a;1;y one"
+        );
+
+        let err = Context::from_yaml_str("a: !custom 1\n").err().unwrap();
+        assert!(
+            err.to_string().contains("unsupported tag !custom"),
+            "{}",
+            err
+        );
+        let err = Context::from_yaml_str("? [a, b]\n: c\n").err().unwrap();
+        assert!(err.to_string().contains("map key"), "{}", err);
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_toml() {
+        let ctx = Context::from_toml_str(
+            "title = \"t\"\nwhen = 1979-05-27T07:32:00Z\n[owner]\nname = \"n\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            render(
+                r#"{{ range $k, $v := . }}{{ $k }} {{ end }}{{ .when }} {{ .owner.name }}"#,
+                ctx
+            ),
+            "title when owner 1979-05-27T07:32:00Z n"
+        );
+        assert!(Context::from_toml_str("a = ").is_err());
+    }
+}
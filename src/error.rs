@@ -18,7 +18,7 @@ impl fmt::Display for ErrorContext {
 #[derive(Error, Debug)]
 pub enum ParseError {
     #[error("unexpected {0} in define clause")]
-    UnexpectedInDefineClause(Nodes),
+    UnexpectedInDefineClause(Box<Nodes>),
     #[error("unexpected end")]
     UnexpectedEnd,
     #[error("template: {0}:{1}")]
@@ -75,6 +75,8 @@ pub enum PrintError {
     WithAfterIndex,
     #[error("precision after index (e.g. %[3].2d)")]
     PrecisionAfterIndex,
+    #[error("width or precision {0} is too large")]
+    TooLarge(usize),
 }
 
 #[derive(Error, Debug)]
@@ -83,10 +85,12 @@ pub enum ExecError {
     IncompleteTemplate(String),
     #[error("{0}")]
     IOError(#[from] std::io::Error),
+    #[error("{0}")]
+    FmtError(#[from] fmt::Error),
     #[error("unknown node: {0}")]
-    UnknownNode(Nodes),
+    UnknownNode(Box<Nodes>),
     #[error("expected if or with node, got {0}")]
-    ExpectedIfOrWith(Nodes),
+    ExpectedIfOrWith(Box<Nodes>),
     #[error("unable to convert output to uft-8: {0}")]
     Utf8ConversionFailed(FromUtf8Error),
     #[error("empty var stack")]
@@ -101,12 +105,30 @@ pub enum ExecError {
     TemplateNotDefined(String),
     #[error("exceeded max template depth")]
     MaxTemplateDepth,
+    #[error("exceeded max output of {0} bytes")]
+    MaxOutputBytes(usize),
+    #[error("exceeded max of {0} range iterations")]
+    MaxRangeIterations(usize),
+    #[error("exceeded max of {0} function calls")]
+    MaxFuncCalls(usize),
+    #[error("exceeded max of {0} evaluation steps")]
+    MaxSteps(usize),
+    #[error("rendering was cancelled")]
+    Cancelled,
+    #[error("rendering exceeded its deadline")]
+    DeadlineExceeded,
+    #[error("function {0} is not allowed")]
+    FuncNotAllowed(String),
+    #[error("calling template {0} is not allowed")]
+    TemplateCallNotAllowed(String),
+    #[error("calling functions from the data is not allowed")]
+    DataFuncNotAllowed,
     #[error("error evaluating pipe: {0}")]
     ErrorEvaluatingPipe(PipeNode),
     #[error("no arguments for command node: {0}")]
     NoArgsForCommandNode(CommandNode),
     #[error("cannot evaluate command: {0}")]
-    CannotEvaluateCommand(Nodes),
+    CannotEvaluateCommand(Box<Nodes>),
     #[error("field chain without fields :/")]
     FieldChainWithoutFields,
     #[error("{0} has arguments but cannot be invoked as function")]
@@ -116,13 +138,13 @@ pub enum ExecError {
     #[error("indirection through explicit nul in {0}")]
     NullInChain(ChainNode),
     #[error("cannot handle {0} as argument")]
-    InvalidArgument(Nodes),
+    InvalidArgument(Box<Nodes>),
     #[error("{0} is not a defined function")]
     UndefinedFunction(String),
     #[error(transparent)]
     FuncError(#[from] FuncError),
     #[error("can't give argument to non-function {0}")]
-    ArgumentForNonFunction(Nodes),
+    ArgumentForNonFunction(Box<Nodes>),
     #[error("only maps and objects have fields")]
     OnlyMapsAndObjectsHaveFields,
     #[error("no field {0} for {1}")]
@@ -131,6 +153,43 @@ pub enum ExecError {
     VariableNotFound(String),
 }
 
+impl ExecError {
+    /// Whether the error must end rendering: exceeded limits, cancellation,
+    /// sandbox violations and failing writers. Other errors only concern the
+    /// failing action.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            *self,
+            ExecError::IOError(_)
+                | ExecError::FmtError(_)
+                | ExecError::MaxTemplateDepth
+                | ExecError::MaxOutputBytes(_)
+                | ExecError::MaxRangeIterations(_)
+                | ExecError::MaxFuncCalls(_)
+                | ExecError::MaxSteps(_)
+                | ExecError::Cancelled
+                | ExecError::DeadlineExceeded
+                | ExecError::FuncNotAllowed(_)
+                | ExecError::TemplateCallNotAllowed(_)
+                | ExecError::DataFuncNotAllowed
+        )
+    }
+}
+
+/// An error of a single action with its location, see `Template::render_lenient`.
+#[derive(Error, Debug)]
+#[error("{template}:{line}:{column}: {error}")]
+pub struct ActionError {
+    /// Name of the template tree the action is in.
+    pub template: String,
+    /// 1-based line in the parsed source.
+    pub line: usize,
+    /// 1-based column in the parsed source.
+    pub column: usize,
+    #[source]
+    pub error: ExecError,
+}
+
 #[derive(Error, Debug)]
 pub enum TemplateError {
     #[error(transparent)]
@@ -138,3 +197,63 @@ pub enum TemplateError {
     #[error(transparent)]
     ParseError(#[from] ParseError),
 }
+
+#[cfg(feature = "serde")]
+#[derive(Error, Debug)]
+pub enum SerdeError {
+    #[error("{0}")]
+    Custom(String),
+    #[error("map keys must be strings, numbers or bools, got {0}")]
+    InvalidKey(Value),
+    #[error("functions can not be deserialized")]
+    Function,
+}
+
+#[cfg(any(feature = "json", feature = "yaml", feature = "toml"))]
+#[derive(Error, Debug)]
+pub enum DocumentError {
+    #[cfg(feature = "json")]
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "yaml")]
+    #[error("invalid YAML: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[cfg(feature = "toml")]
+    #[error("invalid TOML: {0}")]
+    Toml(#[from] toml::de::Error),
+}
+
+#[cfg(feature = "json")]
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid schema at {0}: {1}")]
+    Invalid(String, String),
+}
+
+#[cfg(feature = "cli")]
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("{0}")]
+    Usage(String),
+    #[error("{0}")]
+    Input(String),
+    #[error("{0}")]
+    Parse(String),
+    #[error("{0}")]
+    Exec(String),
+}
+
+#[cfg(feature = "cli")]
+impl CliError {
+    pub fn exit_code(&self) -> i32 {
+        use crate::cli::*;
+        match *self {
+            CliError::Usage(_) => EXIT_USAGE,
+            CliError::Input(_) => EXIT_INPUT,
+            CliError::Parse(_) => EXIT_PARSE,
+            CliError::Exec(_) => EXIT_EXEC,
+        }
+    }
+}
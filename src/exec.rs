@@ -1,36 +1,412 @@
-use std::collections::VecDeque;
-use std::io::Write;
-
-use crate::error::ExecError;
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Instant;
+use std::{fmt, mem, slice};
+
+use crate::compile::{Arg, Builtin, Callee, Cmd, Code, Function, Op, Pipe, Program, Var};
+use crate::data::{Data, TemplateData};
+#[cfg(feature = "serde")]
+use crate::error::SerdeError;
+use crate::error::{ActionError, ExecError};
+use crate::funcs;
 use crate::node::*;
 use crate::template::Template;
 use crate::utils::is_true;
+#[cfg(any(feature = "json", feature = "yaml", feature = "toml"))]
+use crate::{document::Document, error::DocumentError};
 
-use gtmpl_value::{Func, Value};
+use gtmpl_value::{FuncError, Value};
 
 const MAX_TEMPLATE_DEPTH: usize = 100_000;
-#[derive(Debug)]
-struct Variable {
-    name: String,
-    value: Value,
+
+/// Resource limits for a single render. `None` means unlimited.
+///
+/// ## Example
+///
+/// ```rust
+/// use go_template::error::ExecError;
+/// use go_template::{Context, Limits, Template};
+///
+/// let mut tmpl = Template::default();
+/// tmpl.limits = Limits::default().with_max_range_iterations(100);
+/// tmpl.parse("{{ range . }}{{ range $ }}x{{ end }}{{ end }}").unwrap();
+/// let err = tmpl.render(&Context::from(vec![0; 20])).unwrap_err();
+/// assert!(matches!(err, ExecError::MaxRangeIterations(100)));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Maximum number of bytes written to the output.
+    pub max_output_bytes: Option<usize>,
+    /// Maximum number of `range` iterations summed over all loops.
+    pub max_range_iterations: Option<usize>,
+    /// Maximum number of function and method calls.
+    pub max_func_calls: Option<usize>,
+    /// Maximum number of evaluation steps, i.e. walked nodes and evaluated
+    /// commands.
+    pub max_steps: Option<usize>,
+    /// Maximum nesting of `{{template}}` calls.
+    pub max_template_depth: usize,
 }
 
-struct State<'a, 'b, T: Write> {
-    template: &'a Template,
-    writer: &'b mut T,
-    node: Option<&'a Nodes>,
-    vars: VecDeque<VecDeque<Variable>>,
-    depth: usize,
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_output_bytes: None,
+            max_range_iterations: None,
+            max_func_calls: None,
+            max_steps: None,
+            max_template_depth: MAX_TEMPLATE_DEPTH,
+        }
+    }
 }
 
+impl Limits {
+    pub fn with_max_output_bytes(mut self, max: usize) -> Limits {
+        self.max_output_bytes = Some(max);
+        self
+    }
+
+    pub fn with_max_range_iterations(mut self, max: usize) -> Limits {
+        self.max_range_iterations = Some(max);
+        self
+    }
+
+    pub fn with_max_func_calls(mut self, max: usize) -> Limits {
+        self.max_func_calls = Some(max);
+        self
+    }
+
+    pub fn with_max_steps(mut self, max: usize) -> Limits {
+        self.max_steps = Some(max);
+        self
+    }
+
+    pub fn with_max_template_depth(mut self, max: usize) -> Limits {
+        self.max_template_depth = max;
+        self
+    }
+}
+
+// What a render used so far.
+#[derive(Clone, Default)]
+struct Usage {
+    output_bytes: usize,
+    range_iterations: usize,
+    func_calls: usize,
+    steps: usize,
+}
+
+// Receives the output of a render.
+trait Sink {
+    fn write_str(&mut self, s: &str) -> Result<(), ExecError>;
+}
+
+struct IoSink<'w, W: Write>(&'w mut W);
+
+impl<'w, W: Write> Sink for IoSink<'w, W> {
+    fn write_str(&mut self, s: &str) -> Result<(), ExecError> {
+        self.0.write_all(s.as_bytes()).map_err(ExecError::IOError)
+    }
+}
+
+struct FmtSink<'w, W: fmt::Write>(&'w mut W);
+
+impl<'w, W: fmt::Write> Sink for FmtSink<'w, W> {
+    fn write_str(&mut self, s: &str) -> Result<(), ExecError> {
+        self.0.write_str(s).map_err(ExecError::FmtError)
+    }
+}
+
+impl Sink for String {
+    fn write_str(&mut self, s: &str) -> Result<(), ExecError> {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+/// Writes bytes asynchronously. A runtime agnostic counterpart of
+/// `io::Write`, adapters for the writers of an async runtime are a few lines.
+pub trait AsyncWrite {
+    /// Attempts to write bytes from `buf`, returns the number of bytes
+    /// written. Registers the waker of `cx` if the writer is not ready.
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>>;
+}
+
+impl AsyncWrite for Vec<u8> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin + ?Sized> AsyncWrite for &mut W {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self.get_mut()).poll_write(cx, buf)
+    }
+}
+
+async fn write_all<W: AsyncWrite + Unpin + ?Sized>(
+    writer: &mut W,
+    mut buf: &[u8],
+) -> Result<(), ExecError> {
+    while !buf.is_empty() {
+        let n = poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, buf)).await?;
+        if n == 0 {
+            return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+        }
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+/// The future returned by an async template function.
+pub type FuncFuture = Pin<Box<dyn Future<Output = Result<Value, FuncError>> + Send>>;
+
+/// A template function that returns a future, see `Template::add_async_func`.
+pub type AsyncFunc = Arc<dyn Fn(Vec<Value>) -> FuncFuture + Send + Sync>;
+
+// Counts the bytes written and refuses writes beyond the limit, so the output
+// never exceeds it. Keeps the error of the sink, `fmt::Write` can't carry it.
+struct Counter<'w, S: Sink + ?Sized> {
+    sink: &'w mut S,
+    written: &'w mut usize,
+    max: Option<usize>,
+    error: Option<ExecError>,
+}
+
+impl<'w, S: Sink + ?Sized> fmt::Write for Counter<'w, S> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if let Some(max) = self.max {
+            if *self.written + s.len() > max {
+                self.error = Some(ExecError::MaxOutputBytes(max));
+                return Err(fmt::Error);
+            }
+        }
+        if let Err(err) = self.sink.write_str(s) {
+            self.error = Some(err);
+            return Err(fmt::Error);
+        }
+        *self.written += s.len();
+        Ok(())
+    }
+}
+
+// A value seen by the executor. The data of the context outlives the render
+// and is borrowed, values produced while rendering are shared. Field chains,
+// ranges and variables thus pass pointers around instead of copying data.
+#[derive(Clone)]
+enum Val<'a> {
+    Ref(&'a Value),
+    Own(Arc<Value>),
+    Lazy(Arc<dyn TemplateData>),
+}
+
+impl<'a> Val<'a> {
+    fn new(data: &'a Data) -> Val<'a> {
+        match *data {
+            Data::Value(ref v) => Val::Ref(v),
+            Data::Lazy(ref d) => Val::Lazy(Arc::clone(d)),
+        }
+    }
+
+    fn value(&self) -> Option<&Value> {
+        match *self {
+            Val::Ref(v) => Some(v),
+            Val::Own(ref v) => Some(v),
+            Val::Lazy(_) => None,
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match *self {
+            Val::Ref(v) => v.clone(),
+            Val::Own(ref v) => (**v).clone(),
+            Val::Lazy(ref d) => d.to_value(),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Val::Ref(v) => v.clone(),
+            Val::Own(v) => Arc::try_unwrap(v).unwrap_or_else(|v| (*v).clone()),
+            Val::Lazy(d) => d.to_value(),
+        }
+    }
+
+    fn is_true(&self) -> bool {
+        match *self {
+            Val::Ref(v) => is_true(v),
+            Val::Own(ref v) => is_true(v),
+            Val::Lazy(ref d) => d.is_true(),
+        }
+    }
+
+    fn field(&self, name: &str) -> Result<Val<'a>, ExecError> {
+        match *self {
+            Val::Ref(v) => field(v, name).map(Val::Ref),
+            Val::Own(ref v) => field(v, name).map(|f| Val::from(f.clone())),
+            Val::Lazy(ref d) => d
+                .field(name)
+                .map(Val::from)
+                .ok_or_else(|| ExecError::NoFiledFor(name.to_string(), describe(&**d))),
+        }
+    }
+
+    // The item for `key` as returned by the `index` builtin.
+    fn item(&self, key: &Value) -> Result<Val<'a>, ExecError> {
+        match *self {
+            Val::Ref(v) => Ok(Val::Ref(funcs::get_item(v, key)?)),
+            Val::Own(ref v) => Ok(Val::from(funcs::get_item(v, key)?.clone())),
+            Val::Lazy(ref d) => d.index(key).map(Val::from).ok_or_else(|| {
+                FuncError::Generic(format!("unable to get {} in {}", key, describe(&**d))).into()
+            }),
+        }
+    }
+}
+
+impl<'a> From<Value> for Val<'a> {
+    fn from(value: Value) -> Val<'a> {
+        Val::Own(Arc::new(value))
+    }
+}
+
+impl<'a> From<Data> for Val<'a> {
+    fn from(data: Data) -> Val<'a> {
+        match data {
+            Data::Value(v) => Val::from(v),
+            Data::Lazy(d) => Val::Lazy(d),
+        }
+    }
+}
+
+impl<'a> fmt::Display for Val<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Val::Ref(v) => write!(f, "{}", v),
+            Val::Own(ref v) => write!(f, "{}", v),
+            Val::Lazy(ref d) => write!(f, "{}", d.to_value()),
+        }
+    }
+}
+
+fn field<'v>(v: &'v Value, name: &str) -> Result<&'v Value, ExecError> {
+    match *v {
+        Value::Object(ref o) => o
+            .get(name)
+            .ok_or_else(|| ExecError::NoFiledFor(name.to_string(), v.clone())),
+        Value::Map(ref o) => Ok(o.get(name).unwrap_or(&Value::NoValue)),
+        _ => Err(ExecError::OnlyMapsAndObjectsHaveFields),
+    }
+}
+
+// The items of a range that are still to come.
+type Items<'a> = Box<dyn Iterator<Item = (Value, Val<'a>)> + Send + 'a>;
+
+// A running template call. Calls are kept on a stack on the heap instead of
+// recursing, so deeply recursive templates end with `MaxTemplateDepth` instead
+// of overflowing the native stack.
+struct Call<'a> {
+    tree: usize,
+    pc: usize,
+    vars: Vec<Val<'a>>,
+    dots: Vec<Val<'a>>,
+    loops: Vec<Items<'a>>,
+}
+
+impl<'a> Call<'a> {
+    fn new(code: &Code, tree: usize, dot: Val<'a>) -> Call<'a> {
+        // Slot 0 holds `$`.
+        let mut vars = Vec::with_capacity(code.slots);
+        vars.push(dot.clone());
+        vars.resize(code.slots, Val::Ref(&Value::NoValue));
+        Call {
+            tree,
+            pc: 0,
+            vars,
+            dots: vec![dot],
+            loops: vec![],
+        }
+    }
+}
+
+struct State<'a, S: Sink> {
+    program: &'a Program,
+    scope: &'a Context,
+    sink: S,
+    calls: Vec<Call<'a>>,
+    usage: Usage,
+    // Results of the calls made by the current op, only kept when rendering
+    // asynchronously. See `Program::execute_async`.
+    log: Option<Log<'a>>,
+    pending: Option<FuncFuture>,
+    handlers: Vec<Handler>,
+    // Output held back by running try bodies and captures, the innermost
+    // last. Output goes to the sink if there are none.
+    bufs: Vec<String>,
+}
+
+// A running try body, see `Op::Try`. Keeps what to unwind to if it fails.
+struct Handler {
+    calls: usize,
+    dots: usize,
+    loops: usize,
+    catch: usize,
+    slot: Option<usize>,
+    bufs: usize,
+    output_bytes: usize,
+}
+
+// An op that awaits a function is run again once the future is ready. The
+// calls it made before are answered from the log, so no function runs twice.
+#[derive(Default)]
+struct Log<'a> {
+    vals: Vec<Val<'a>>,
+    pos: usize,
+}
+
+// What an op may have changed before it suspended.
+struct Snapshot<'a> {
+    pc: usize,
+    vars: Vec<Val<'a>>,
+    usage: Usage,
+}
+
+/// A function bound to a single render. Unlike `Func` it may capture state and
+/// receives the `Context` the template is rendered with.
+pub type ContextFunc =
+    Box<dyn Fn(&Context, &[Value]) -> Result<Value, FuncError> + Send + Sync + 'static>;
+
 /// A Context for the template. Passed to the template exectution.
 pub struct Context {
-    dot: Value,
+    dot: Data,
+    funcs: HashMap<String, ContextFunc>,
+    extensions: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    cancel: Option<Arc<AtomicBool>>,
+    deadline: Option<Instant>,
 }
 
 impl Context {
     pub fn empty() -> Context {
-        Context { dot: Value::Nil }
+        Context::from(Value::Nil)
     }
 
     pub fn from<T>(value: T) -> Context
@@ -38,185 +414,764 @@ impl Context {
         T: Into<Value>,
     {
         let serialized: Value = value.into();
-        Context { dot: serialized }
+        Context::with_dot(Data::Value(serialized))
+    }
+
+    /// Creates a context from any `T: Serialize`, see `serde_value::to_value`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use std::collections::BTreeMap;
+    ///
+    /// use go_template::{Context, Template};
+    ///
+    /// let mut data = BTreeMap::new();
+    /// data.insert("name", Some("World"));
+    /// data.insert("missing", None);
+    ///
+    /// let mut tmpl = Template::default();
+    /// tmpl.parse("Hello {{ .name }}! {{ .missing }}").unwrap();
+    /// let output = tmpl.render(&Context::from_serialize(&data).unwrap());
+    /// assert_eq!(&output.unwrap(), "Hello World! nil");
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn from_serialize<T>(value: &T) -> Result<Context, SerdeError>
+    where
+        T: serde::Serialize + ?Sized,
+    {
+        Ok(Context::from(crate::serde_value::to_value(value)?))
+    }
+
+    /// Creates a context from a JSON document. Objects keep their key order.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use go_template::{Context, Template};
+    ///
+    /// let ctx = Context::from_json_str(r#"{"b": 1, "a": 2.5}"#).unwrap();
+    /// let mut tmpl = Template::default();
+    /// tmpl.parse("{{ range $k, $v := . }}{{ $k }}={{ $v }} {{ end }}").unwrap();
+    /// assert_eq!(&tmpl.render(&ctx).unwrap(), "b=1 a=2.5 ");
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn from_json_str(s: &str) -> Result<Context, DocumentError> {
+        Ok(Context::with_dot(Document::from_json_str(s)?.into_data()))
+    }
+
+    /// Creates a context from a YAML document. Mappings keep their key order.
+    /// Tagged values and mappings with sequences or mappings as keys are
+    /// rejected.
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml_str(s: &str) -> Result<Context, DocumentError> {
+        Ok(Context::with_dot(Document::from_yaml_str(s)?.into_data()))
+    }
+
+    /// Creates a context from a TOML document. Tables keep their key order and
+    /// datetimes are passed as strings.
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(s: &str) -> Result<Context, DocumentError> {
+        Ok(Context::with_dot(Document::from_toml_str(s)?.into_data()))
+    }
+
+    /// Creates a context from a lazy data source. Only the parts of `data` that
+    /// the template accesses are materialised, see `TemplateData`.
+    pub fn from_data<D: TemplateData + 'static>(data: D) -> Context {
+        Context::with_dot(Data::Lazy(Arc::new(data)))
+    }
+
+    pub(crate) fn with_dot(dot: Data) -> Context {
+        Context {
+            dot,
+            funcs: HashMap::new(),
+            extensions: HashMap::new(),
+            cancel: None,
+            deadline: None,
+        }
+    }
+
+    /// Adds a function for this render only. It takes precedence over a template
+    /// function with the same `name`. The template must know the name when it is
+    /// parsed, see `Template::declare_func`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use go_template::{Context, Template, Value};
+    ///
+    /// struct Locale(&'static str);
+    ///
+    /// let mut tmpl = Template::default();
+    /// tmpl.declare_func("t");
+    /// tmpl.parse(r#"{{ t "hello" }}"#).unwrap();
+    ///
+    /// let mut ctx = Context::empty();
+    /// ctx.insert_ext(Locale("de"));
+    /// ctx.add_func("t", |ctx, _args| match ctx.ext::<Locale>() {
+    ///     Some(Locale("de")) => Ok(Value::from("hallo")),
+    ///     _ => Ok(Value::from("hello")),
+    /// });
+    /// assert_eq!(&tmpl.render(&ctx).unwrap(), "hallo");
+    /// ```
+    pub fn add_func<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&Context, &[Value]) -> Result<Value, FuncError> + Send + Sync + 'static,
+    {
+        self.funcs.insert(name.to_owned(), Box::new(func));
+    }
+
+    /// Stores a typed extension for functions to read during this render.
+    /// Returns the previous extension of the same type.
+    pub fn insert_ext<E: Any + Send + Sync>(&mut self, ext: E) -> Option<E> {
+        self.extensions
+            .insert(TypeId::of::<E>(), Box::new(ext))
+            .and_then(|prev| prev.downcast().ok())
+            .map(|prev| *prev)
+    }
+
+    /// Returns the extension of type `E` if present.
+    pub fn ext<E: Any + Send + Sync>(&self) -> Option<&E> {
+        self.extensions
+            .get(&TypeId::of::<E>())
+            .and_then(|ext| ext.downcast_ref())
+    }
+
+    /// Aborts rendering with `ExecError::Cancelled` once `flag` is set, e.g.
+    /// from another thread. The flag is checked before each node and range
+    /// iteration.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// use go_template::error::ExecError;
+    /// use go_template::{Context, Template};
+    ///
+    /// let mut tmpl = Template::default();
+    /// tmpl.parse("{{ . }}").unwrap();
+    /// let flag = Arc::new(AtomicBool::new(false));
+    /// let mut ctx = Context::from(1);
+    /// ctx.set_cancel(flag.clone());
+    /// assert_eq!(&tmpl.render(&ctx).unwrap(), "1");
+    /// flag.store(true, Ordering::Relaxed);
+    /// assert!(matches!(tmpl.render(&ctx), Err(ExecError::Cancelled)));
+    /// ```
+    pub fn set_cancel(&mut self, flag: Arc<AtomicBool>) {
+        self.cancel = Some(flag);
+    }
+
+    /// Aborts rendering with `ExecError::DeadlineExceeded` once `deadline`
+    /// passed. It is checked before each node and range iteration.
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
+    fn interrupted(&self) -> Result<(), ExecError> {
+        if let Some(ref cancel) = self.cancel {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(ExecError::Cancelled);
+            }
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(ExecError::DeadlineExceeded),
+            _ => Ok(()),
+        }
     }
 }
 
 impl<'b> Template {
     pub fn execute<T: Write>(&self, writer: &'b mut T, data: &Context) -> Result<(), ExecError> {
-        let mut vars: VecDeque<VecDeque<Variable>> = VecDeque::new();
-        let mut dot = VecDeque::new();
-        dot.push_back(Variable {
-            name: "$".to_owned(),
-            value: data.dot.clone(),
-        });
-        vars.push_back(dot);
+        self.program().execute(writer, data)
+    }
 
-        let mut state = State {
-            template: self,
-            writer,
-            node: None,
-            vars,
-            depth: 0,
-        };
+    /// Renders into a `fmt::Write`, e.g. a `String` or a `fmt::Formatter`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use go_template::{Context, Template};
+    ///
+    /// let mut tmpl = Template::default();
+    /// tmpl.parse("Hello {{ . }}!").unwrap();
+    /// let mut out = String::from("> ");
+    /// tmpl.render_to_fmt(&mut out, &Context::from("World")).unwrap();
+    /// assert_eq!(&out, "> Hello World!");
+    /// ```
+    pub fn render_to_fmt<T: fmt::Write>(
+        &self,
+        writer: &'b mut T,
+        data: &Context,
+    ) -> Result<(), ExecError> {
+        self.program().render_to_fmt(writer, data)
+    }
 
-        let root = self
-            .tree_set
-            .get(&self.name)
-            .and_then(|tree| tree.root.as_ref())
-            .ok_or_else(|| ExecError::IncompleteTemplate(self.name.clone()))?;
-        state.walk(data, root)?;
+    pub fn render(&self, data: &Context) -> Result<String, ExecError> {
+        self.program().render(data)
+    }
 
-        Ok(())
+    /// Renders without stopping at failing actions. Each failing action is
+    /// recorded with its location and replaced by `placeholder`, failing `if`,
+    /// `with` and `range` blocks are skipped as a whole. Fatal errors, see
+    /// `ExecError::is_fatal`, end rendering and are recorded last.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use go_template::{Context, Template};
+    ///
+    /// let mut tmpl = Template::default();
+    /// tmpl.parse("a={{ .a }} b={{ .b.c }} {{ if .d.e }}x{{ end }}!").unwrap();
+    /// let ctx = Context::from(gtmpl_value::Value::Map(
+    ///     vec![("a".to_owned(), 1.into())].into_iter().collect(),
+    /// ));
+    /// let rendered = tmpl.render_lenient(&ctx, "?").unwrap();
+    /// assert_eq!(&rendered.output, "a=1 b=? ?!");
+    /// assert_eq!(rendered.errors.len(), 2);
+    /// assert_eq!(rendered.errors[0].column, 17);
+    /// ```
+    pub fn render_lenient(&self, data: &Context, placeholder: &str) -> Result<Rendered, ExecError> {
+        self.program().render_lenient(data, placeholder)
     }
 
-    pub fn render(&self, data: &Context) -> Result<String, ExecError> {
-        let mut w: Vec<u8> = vec![];
-        self.execute(&mut w, data)?;
-        String::from_utf8(w).map_err(ExecError::Utf8ConversionFailed)
+    /// Renders into an `AsyncWrite` and awaits the functions added with
+    /// `add_async_func`. Output is written after each node. Doesn't depend on
+    /// a runtime, any executor can drive the future.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use std::future::Future;
+    /// use std::sync::Arc;
+    /// use std::task::{Context as TaskContext, Poll, Wake, Waker};
+    ///
+    /// use go_template::{Context, Template};
+    /// use gtmpl_value::Value;
+    ///
+    /// // A minimal executor for the example, use the one of your runtime.
+    /// fn block_on<F: Future>(fut: F) -> F::Output {
+    ///     struct Unpark(std::thread::Thread);
+    ///     impl Wake for Unpark {
+    ///         fn wake(self: Arc<Self>) {
+    ///             self.0.unpark();
+    ///         }
+    ///     }
+    ///     let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    ///     let mut fut = Box::pin(fut);
+    ///     loop {
+    ///         match fut.as_mut().poll(&mut TaskContext::from_waker(&waker)) {
+    ///             Poll::Ready(out) => return out,
+    ///             Poll::Pending => std::thread::park(),
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut tmpl = Template::default();
+    /// tmpl.add_async_func("lookup", |args: Vec<Value>| async move {
+    ///     Ok(Value::from(format!("user {}", args[0])))
+    /// });
+    /// tmpl.parse("Hello {{ lookup . }}!").unwrap();
+    /// let mut out: Vec<u8> = vec![];
+    /// block_on(tmpl.execute_async(&mut out, &Context::from(7))).unwrap();
+    /// assert_eq!(String::from_utf8(out).unwrap(), "Hello user 7!");
+    /// ```
+    pub async fn execute_async<W: AsyncWrite + Unpin + ?Sized>(
+        &self,
+        writer: &mut W,
+        data: &Context,
+    ) -> Result<(), ExecError> {
+        self.program().execute_async(writer, data).await
     }
 }
 
-impl<'a, 'b, T: Write> State<'a, 'b, T> {
-    fn set_kth_last_var_value(&mut self, k: usize, value: Value) -> Result<(), ExecError> {
-        if let Some(last_vars) = self.vars.back_mut() {
-            let i = last_vars.len() - k;
-            if let Some(kth_last_var) = last_vars.get_mut(i) {
-                kth_last_var.value = value;
+impl<'b> Program {
+    pub fn execute<T: Write>(&self, writer: &'b mut T, data: &Context) -> Result<(), ExecError> {
+        self.state(IoSink(writer), data)?.run()
+    }
+
+    /// Renders into a `fmt::Write`, see `Template::render_to_fmt`.
+    pub fn render_to_fmt<T: fmt::Write>(
+        &self,
+        writer: &'b mut T,
+        data: &Context,
+    ) -> Result<(), ExecError> {
+        self.state(FmtSink(writer), data)?.run()
+    }
+
+    pub fn render(&self, data: &Context) -> Result<String, ExecError> {
+        let mut state = self.state(String::new(), data)?;
+        state.run()?;
+        Ok(state.sink)
+    }
+
+    /// Renders into an `AsyncWrite`, see `Template::execute_async`.
+    ///
+    /// An op that calls an async function is suspended while the future runs
+    /// and is then run again from its start, the results of the calls it
+    /// already made are replayed. Ops change the state only after their
+    /// pipelines are evaluated, so nothing but the variables, the usage and
+    /// the position needs to be restored.
+    pub async fn execute_async<W: AsyncWrite + Unpin + ?Sized>(
+        &self,
+        writer: &mut W,
+        data: &Context,
+    ) -> Result<(), ExecError> {
+        let mut state = self.state(String::new(), data)?;
+        state.log = Some(Log::default());
+        loop {
+            let snapshot = state.snapshot();
+            let running = match state.advance() {
+                Ok(running) => running,
+                Err(err) => match state.pending.take() {
+                    Some(pending) => {
+                        match pending.await {
+                            Ok(val) => state.resume(snapshot, Val::from(val)),
+                            Err(err) => {
+                                state.recover(err.into())?;
+                                state.log = Some(Log::default());
+                            }
+                        }
+                        continue;
+                    }
+                    None => return Err(err),
+                },
+            };
+            state.log = Some(Log::default());
+            if !state.sink.is_empty() {
+                write_all(writer, state.sink.as_bytes()).await?;
+                state.sink.clear();
+            }
+            if !running {
                 return Ok(());
             }
-            return Err(ExecError::VarContextToSmall(k));
         }
-        Err(ExecError::EmptyStack)
     }
 
-    fn var_value(&self, key: &str) -> Result<Value, ExecError> {
-        for context in self.vars.iter().rev() {
-            for var in context.iter().rev() {
-                if var.name == key {
-                    return Ok(var.value.clone());
+    /// Renders without stopping at failing actions, see
+    /// `Template::render_lenient`. Fails only if there is nothing to render.
+    pub fn render_lenient(&self, data: &Context, placeholder: &str) -> Result<Rendered, ExecError> {
+        let mut state = self.state(String::new(), data)?;
+        let mut errors = vec![];
+        loop {
+            let err = match state.advance() {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(err) => err,
+            };
+            let fatal = err.is_fatal();
+            errors.push(state.action_error(err));
+            if fatal {
+                break;
+            }
+            state.skip();
+            if let Err(err) = state.write(format_args!("{}", placeholder)) {
+                errors.push(state.action_error(err));
+                break;
+            }
+        }
+        Ok(Rendered {
+            output: state.sink,
+            errors,
+        })
+    }
+
+    /// Renders lazily: each call to `next` runs the program until at least
+    /// `size` bytes of output are ready. The last chunk may be shorter. After
+    /// an error the iterator yields it and ends, output that was not yielded
+    /// yet is dropped.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use go_template::{Context, Template};
+    ///
+    /// let mut tmpl = Template::default();
+    /// tmpl.parse("{{ range . }}{{ . }}{{ end }}").unwrap();
+    /// let program = tmpl.compile();
+    /// let ctx = Context::from(vec!["ab", "cd", "ef"]);
+    /// let chunks: Result<Vec<_>, _> = program.chunks(&ctx, 3).collect();
+    /// assert_eq!(chunks.unwrap(), vec!["abcd", "ef"]);
+    /// ```
+    pub fn chunks<'a>(&'a self, data: &'a Context, size: usize) -> Chunks<'a> {
+        Chunks {
+            state: Some(self.state(String::new(), data)),
+            size,
+        }
+    }
+
+    fn state<'a, S: Sink>(&'a self, sink: S, data: &'a Context) -> Result<State<'a, S>, ExecError> {
+        let entry = self
+            .entry
+            .ok_or_else(|| ExecError::IncompleteTemplate(self.name.clone()))?;
+        Ok(State {
+            program: self,
+            scope: data,
+            sink,
+            calls: vec![Call::new(&self.trees[entry], entry, Val::new(&data.dot))],
+            usage: Usage::default(),
+            log: None,
+            pending: None,
+            handlers: vec![],
+            bufs: vec![],
+        })
+    }
+}
+
+/// The output and the errors of `render_lenient`.
+#[derive(Debug)]
+pub struct Rendered {
+    pub output: String,
+    /// The errors in the order they occurred.
+    pub errors: Vec<ActionError>,
+}
+
+/// The output of a program in chunks, see `Program::chunks`.
+pub struct Chunks<'a> {
+    state: Option<Result<State<'a, String>, ExecError>>,
+    size: usize,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Result<String, ExecError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut state = match self.state.take()? {
+            Ok(state) => state,
+            Err(err) => return Some(Err(err)),
+        };
+        loop {
+            match state.advance() {
+                Ok(true) if state.sink.len() < self.size => {}
+                Ok(true) => {
+                    let chunk = mem::take(&mut state.sink);
+                    self.state = Some(Ok(state));
+                    return Some(Ok(chunk));
                 }
+                Ok(false) if state.sink.is_empty() => return None,
+                Ok(false) => return Some(Ok(state.sink)),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl<'a, S: Sink> State<'a, S> {
+    fn write(&mut self, args: fmt::Arguments) -> Result<(), ExecError> {
+        let sink: &mut dyn Sink = match self.bufs.last_mut() {
+            Some(buf) => buf,
+            None => &mut self.sink,
+        };
+        let mut counter = Counter {
+            sink,
+            written: &mut self.usage.output_bytes,
+            max: self.program.limits.max_output_bytes,
+            error: None,
+        };
+        match fmt::write(&mut counter, args) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(counter.error.unwrap_or(ExecError::FmtError(err))),
+        }
+    }
+
+    fn step(&mut self) -> Result<(), ExecError> {
+        self.usage.steps += 1;
+        match self.program.limits.max_steps {
+            Some(max) if self.usage.steps > max => Err(ExecError::MaxSteps(max)),
+            _ => Ok(()),
+        }
+    }
+
+    fn count_call(&mut self) -> Result<(), ExecError> {
+        self.usage.func_calls += 1;
+        match self.program.limits.max_func_calls {
+            Some(max) if self.usage.func_calls > max => Err(ExecError::MaxFuncCalls(max)),
+            _ => Ok(()),
+        }
+    }
+
+    fn call(&mut self) -> &mut Call<'a> {
+        self.calls.last_mut().expect("no running call")
+    }
+
+    fn var_value(&self, var: &Var) -> Val<'a> {
+        let call = self.calls.last().expect("no running call");
+        call.vars[var.slot].clone()
+    }
+
+    fn snapshot(&self) -> Option<Snapshot<'a>> {
+        self.calls.last().map(|call| Snapshot {
+            pc: call.pc,
+            vars: call.vars.clone(),
+            usage: self.usage.clone(),
+        })
+    }
+
+    // Rewinds the op that suspended and logs the result it waited for.
+    fn resume(&mut self, snapshot: Option<Snapshot<'a>>, val: Val<'a>) {
+        if let Some(snapshot) = snapshot {
+            let call = self.call();
+            call.pc = snapshot.pc;
+            call.vars = snapshot.vars;
+            self.usage = snapshot.usage;
+        }
+        if let Some(ref mut log) = self.log {
+            log.vals.push(val);
+            log.pos = 0;
+        }
+    }
+
+    // Makes a function call. When rendering asynchronously the result is
+    // taken from the log if the op is run again, else it is logged.
+    fn logged<F>(&mut self, f: F) -> Result<Val<'a>, ExecError>
+    where
+        F: FnOnce(&mut Self) -> Result<Val<'a>, ExecError>,
+    {
+        if let Some(ref mut log) = self.log {
+            if let Some(val) = log.vals.get(log.pos) {
+                log.pos += 1;
+                return Ok(val.clone());
+            }
+        }
+        let val = f(self)?;
+        if let Some(ref mut log) = self.log {
+            log.vals.push(val.clone());
+            log.pos += 1;
+        }
+        Ok(val)
+    }
+
+    // Keeps the future of an async function and unwinds the op with an error
+    // that `execute_async` never returns.
+    fn suspend(&mut self, future: FuncFuture) -> ExecError {
+        self.pending = Some(future);
+        ExecError::FuncError(FuncError::Generic("suspended".to_string()))
+    }
+
+    // Locates an error of the op that just ran.
+    fn action_error(&self, error: ExecError) -> ActionError {
+        let (template, (line, column)) = match self.calls.last() {
+            Some(call) => {
+                let code = &self.program.trees[call.tree];
+                (code.name.clone(), code.sites[call.pc - 1])
             }
+            None => (self.program.name.clone(), (1, 1)),
+        };
+        ActionError {
+            template,
+            line,
+            column,
+            error,
         }
-        Err(ExecError::VariableNotFound(key.to_string()))
     }
 
-    fn walk_list(&mut self, ctx: &Context, node: &'a ListNode) -> Result<(), ExecError> {
-        for n in &node.nodes {
-            self.walk(ctx, n)?;
+    // Continues after a block whose pipeline failed.
+    fn skip(&mut self) {
+        let program = self.program;
+        let call = self.call();
+        match program.trees[call.tree].ops[call.pc - 1] {
+            Op::Branch { end, .. } | Op::Range { end, .. } => call.pc = end,
+            _ => {}
         }
+    }
+
+    fn run(&mut self) -> Result<(), ExecError> {
+        while self.advance()? {}
         Ok(())
     }
 
-    // Top level walk function. Steps through the major parts for the template strcuture and
-    // writes to the output.
-    fn walk(&mut self, ctx: &Context, node: &'a Nodes) -> Result<(), ExecError> {
-        self.node = Some(node);
-        match *node {
-            Nodes::Action(ref n) => {
-                let val = self.eval_pipeline(ctx, &n.pipe)?;
-                if n.pipe.decl.is_empty() {
+    // Runs the next op. Returns `false` once the stack is empty. Errors in a
+    // try body continue with its catch list unless they are fatal.
+    fn advance(&mut self) -> Result<bool, ExecError> {
+        match self.advance_op() {
+            Err(err) if self.pending.is_none() => self.recover(err).map(|()| true),
+            res => res,
+        }
+    }
+
+    // Continues with the catch list of the innermost try body.
+    fn recover(&mut self, err: ExecError) -> Result<(), ExecError> {
+        if err.is_fatal() {
+            return Err(err);
+        }
+        match self.handlers.pop() {
+            Some(handler) => {
+                self.catch(handler, err);
+                Ok(())
+            }
+            None => Err(err),
+        }
+    }
+
+    fn catch(&mut self, handler: Handler, err: ExecError) {
+        self.calls.truncate(handler.calls);
+        self.bufs.truncate(handler.bufs);
+        self.usage.output_bytes = handler.output_bytes;
+        let call = self.call();
+        call.dots.truncate(handler.dots);
+        call.loops.truncate(handler.loops);
+        call.pc = handler.catch;
+        if let Some(slot) = handler.slot {
+            call.vars[slot] = Val::from(Value::from(err.to_string()));
+        }
+    }
+
+    fn advance_op(&mut self) -> Result<bool, ExecError> {
+        let program = self.program;
+        let call = match self.calls.last_mut() {
+            Some(call) => call,
+            None => return Ok(false),
+        };
+        let op = &program.trees[call.tree].ops[call.pc];
+        call.pc += 1;
+        match *op {
+            Op::Jump(pc) => call.pc = pc,
+            Op::PopDot => {
+                call.dots.pop();
+            }
+            Op::Loop(pc) => {
+                call.dots.pop();
+                call.pc = pc;
+            }
+            Op::Next { key, value, end } => self.next(key, value, end)?,
+            Op::Try { catch, slot } => {
+                let handler = Handler {
+                    dots: call.dots.len(),
+                    loops: call.loops.len(),
+                    calls: self.calls.len(),
+                    catch,
+                    slot,
+                    bufs: self.bufs.len(),
+                    output_bytes: self.usage.output_bytes,
+                };
+                self.handlers.push(handler);
+                self.bufs.push(String::new());
+            }
+            Op::EndTry(pc) => {
+                call.pc = pc;
+                self.handlers.pop();
+                let buf = self.bufs.pop().expect("output of a try body");
+                match self.bufs.last_mut() {
+                    Some(outer) => outer.push_str(&buf),
+                    None => self.sink.write_str(&buf)?,
+                }
+            }
+            Op::Capture => self.bufs.push(String::new()),
+            Op::EndCapture(slot) => {
+                let buf = self.bufs.pop().expect("output of a capture");
+                call.vars[slot] = Val::from(Value::from(buf));
+            }
+            Op::Return => {
+                self.calls.pop();
+            }
+            _ => {
+                let dot = call.dots.last().expect("a call always has a dot").clone();
+                self.exec(&dot, op)?;
+            }
+        }
+        Ok(true)
+    }
+
+    // Executes an op taken from a node. Output is written right away.
+    fn exec(&mut self, dot: &Val<'a>, op: &'a Op) -> Result<(), ExecError> {
+        self.scope.interrupted()?;
+        self.step()?;
+        match *op {
+            Op::List => Ok(()),
+            Op::Text(ref text) => self.write(format_args!("{}", text)),
+            Op::Action(ref pipe) => {
+                let val = self.eval_pipeline(dot, pipe)?;
+                if pipe.decl.is_empty() {
                     self.print_value(&val)?;
                 }
                 Ok(())
             }
-            Nodes::If(_) | Nodes::With(_) => self.walk_if_or_with(node, ctx),
-            Nodes::Range(ref n) => self.walk_range(ctx, n),
-            Nodes::List(ref n) => self.walk_list(ctx, n),
-            Nodes::Text(ref n) => write!(self.writer, "{}", n).map_err(ExecError::IOError),
-            Nodes::Template(ref n) => self.walk_template(ctx, n),
-            _ => Err(ExecError::UnknownNode(node.clone())),
+            Op::Branch {
+                ref pipe,
+                with,
+                otherwise,
+                ..
+            } => {
+                let val = self.eval_pipeline(dot, pipe)?;
+                let call = self.call();
+                if !val.is_true() {
+                    call.pc = otherwise;
+                } else if with {
+                    call.dots.push(val);
+                }
+                Ok(())
+            }
+            Op::Range { ref pipe, .. } => self.walk_range(dot, pipe),
+            Op::Template {
+                ref callee,
+                ref pipe,
+            } => self.walk_template(dot, callee, pipe),
+            Op::Unknown(ref node) => Err(ExecError::UnknownNode(node.clone())),
+            Op::Jump(_)
+            | Op::PopDot
+            | Op::Next { .. }
+            | Op::Loop(_)
+            | Op::Try { .. }
+            | Op::EndTry(_)
+            | Op::Capture
+            | Op::EndCapture(_)
+            | Op::Return => {
+                unreachable!("control ops are run by run")
+            }
         }
     }
 
-    fn walk_template(&mut self, ctx: &Context, template: &TemplateNode) -> Result<(), ExecError> {
-        let name = match template.name {
-            PipeOrString::String(ref name) => name.to_owned(),
-            PipeOrString::Pipe(ref pipe) => {
-                if let Value::String(s) = self.eval_pipeline(ctx, pipe)? {
-                    s
+    fn walk_template(
+        &mut self,
+        dot: &Val<'a>,
+        callee: &'a Callee,
+        pipe: &'a Option<Pipe>,
+    ) -> Result<(), ExecError> {
+        let program = self.program;
+        let (name, tree) = match *callee {
+            Callee::Static { ref name, tree } => (Cow::Borrowed(name.as_str()), tree),
+            Callee::Dynamic(ref pipe) => {
+                if let Some(Value::String(s)) = self.eval_pipeline(dot, pipe)?.value() {
+                    (Cow::Owned(s.clone()), program.names.get(s).copied())
                 } else {
                     return Err(ExecError::PipelineMustYieldString);
                 }
             }
         };
-        if self.depth >= MAX_TEMPLATE_DEPTH {
-            return Err(ExecError::MaxTemplateDepth);
+        if !program.templates_allowed {
+            return Err(ExecError::TemplateCallNotAllowed(name.into_owned()));
         }
-        let tree = self.template.tree_set.get(&name);
-        if let Some(tree) = tree {
-            if let Some(ref root) = tree.root {
-                let mut vars = VecDeque::new();
-                let mut dot = VecDeque::new();
-                let value = if let Some(ref pipe) = template.pipe {
-                    self.eval_pipeline(ctx, pipe)?
-                } else {
-                    Value::NoValue
-                };
-                dot.push_back(Variable {
-                    name: "$".to_owned(),
-                    value: value.clone(),
-                });
-                vars.push_back(dot);
-                let mut new_state = State {
-                    template: self.template,
-                    writer: self.writer,
-                    node: None,
-                    vars,
-                    depth: self.depth + 1,
-                };
-                return new_state.walk(&Context::from(value), root);
-            }
+        if self.calls.len() > program.limits.max_template_depth {
+            return Err(ExecError::MaxTemplateDepth);
         }
-        Err(ExecError::TemplateNotDefined(name))
+        let tree = tree.ok_or_else(|| ExecError::TemplateNotDefined(name.into_owned()))?;
+        let value = if let Some(ref pipe) = *pipe {
+            self.eval_pipeline(dot, pipe)?
+        } else {
+            Val::Ref(&Value::NoValue)
+        };
+        self.calls
+            .push(Call::new(&program.trees[tree], tree, value));
+        Ok(())
     }
 
-    fn eval_pipeline(&mut self, ctx: &Context, pipe: &PipeNode) -> Result<Value, ExecError> {
-        let mut val: Option<Value> = None;
+    fn eval_pipeline(&mut self, dot: &Val<'a>, pipe: &'a Pipe) -> Result<Val<'a>, ExecError> {
+        let mut val: Option<Val<'a>> = None;
         for cmd in &pipe.cmds {
-            val = Some(self.eval_command(ctx, cmd, &val)?);
-            // TODO
-        }
-        let val = val.ok_or_else(|| ExecError::ErrorEvaluatingPipe(pipe.clone()))?;
-        for var in &pipe.decl {
-            if pipe.is_assign == true {
-                let mut idx2 = -1;
-                let mut idx1 = -1;
-                for (k, v) in self.vars.iter().enumerate() {
-                    for (k2, v2) in v.iter().enumerate() {
-                        if v2.name == var.ident[0] {
-                            idx2 = k2 as i32;
-                            idx1 = k as i32;
-                        }
-                    }
-                }
-                // println!("val assign is   {:?}", self.vars);
-                self.vars[idx1 as usize].remove(idx2 as usize);
-                self.vars[idx1 as usize].insert(
-                    idx2 as usize,
-                    Variable {
-                        name: var.ident[0].clone(),
-                        value: val.clone(),
-                    },
-                );
-                // println!(
-                //     "val assign is{} {:?}, {:?}",
-                //     var.ident[0],
-                //     val.clone(),
-                //     self.vars
-                // );
-            } else {
-                // println!("val no assign is{} {:?}", var.ident[0].clone(), val.clone());
-
-                self.vars
-                    .back_mut()
-                    .map(|v| {
-                        v.push_back(Variable {
-                            name: var.ident[0].clone(),
-                            value: val.clone(),
-                        })
-                    })
-                    .ok_or(ExecError::EmptyStack)?;
+            val = Some(self.eval_command(dot, cmd, &val)?);
+        }
+        let val = match val {
+            Some(val) => val,
+            None => {
+                let node = pipe.empty.as_deref().expect("source of an empty pipeline");
+                return Err(ExecError::ErrorEvaluatingPipe(node.clone()));
+            }
+        };
+        if !pipe.decl.is_empty() {
+            let call = self.call();
+            for &slot in &pipe.decl {
+                call.vars[slot] = val.clone();
             }
         }
         Ok(val)
@@ -224,263 +1179,331 @@ impl<'a, 'b, T: Write> State<'a, 'b, T> {
 
     fn eval_command(
         &mut self,
-        ctx: &Context,
-        cmd: &CommandNode,
-        val: &Option<Value>,
-    ) -> Result<Value, ExecError> {
-        let first_word = &cmd
-            .args
-            .first()
-            .ok_or_else(|| ExecError::NoArgsForCommandNode(cmd.clone()))?;
-
-        match *(*first_word) {
-            Nodes::Field(ref n) => return self.eval_field_node(ctx, n, &cmd.args, val),
-            Nodes::Variable(ref n) => return self.eval_variable_node(n, &cmd.args, val),
-            Nodes::Pipe(ref n) => return self.eval_pipeline(ctx, n),
-            Nodes::Chain(ref n) => return self.eval_chain_node(ctx, n, &cmd.args, val),
-            Nodes::Identifier(ref n) => return self.eval_function(ctx, n, &cmd.args, val),
-            _ => {}
-        }
-        not_a_function(&cmd.args, val)?;
-        match *(*first_word) {
-            Nodes::Bool(ref n) => Ok(n.value.clone()),
-            Nodes::Dot(_) => Ok(ctx.dot.clone()),
-            Nodes::Number(ref n) => Ok(n.value.clone()),
-            Nodes::String(ref n) => Ok(n.value.clone()),
-            _ => Err(ExecError::CannotEvaluateCommand((*first_word).clone())),
+        dot: &Val<'a>,
+        cmd: &'a Cmd,
+        val: &Option<Val<'a>>,
+    ) -> Result<Val<'a>, ExecError> {
+        self.step()?;
+        match *cmd {
+            Cmd::Field {
+                ref fields,
+                ref args,
+            } => self.eval_field_chain(dot, dot, fields, args, val),
+            Cmd::Variable {
+                ref var,
+                ref args,
+                ref node,
+            } => {
+                let recv = self.var_value(var);
+                if var.fields.is_empty() {
+                    not_a_function(node, !args.is_empty(), val)?;
+                    return Ok(recv);
+                }
+                self.eval_field_chain(dot, &recv, &var.fields, args, val)
+            }
+            Cmd::Pipe(ref pipe) => self.eval_pipeline(dot, pipe),
+            Cmd::Chain {
+                ref chain,
+                ref args,
+            } => {
+                let recv = self.eval_arg(dot, &chain.base)?;
+                self.eval_field_chain(dot, &recv, &chain.fields, args, val)
+            }
+            Cmd::BadChain(ref chain) => Err(chain_error(chain)),
+            Cmd::Func { ref func, ref args } => self.eval_function(dot, func, args, val),
+            Cmd::Const {
+                ref value,
+                ref node,
+                has_args,
+            } => {
+                not_a_function(node, has_args, val)?;
+                Ok(Val::Ref(value))
+            }
+            Cmd::Dot { ref node, has_args } => {
+                not_a_function(node, has_args, val)?;
+                Ok(dot.clone())
+            }
+            Cmd::Invalid { ref node, has_args } => {
+                not_a_function(node, has_args, val)?;
+                Err(ExecError::CannotEvaluateCommand(node.clone()))
+            }
+            Cmd::Empty(ref cmd) => Err(ExecError::NoArgsForCommandNode((**cmd).clone())),
         }
     }
 
     fn eval_function(
         &mut self,
-        ctx: &Context,
-        ident: &IdentifierNode,
-        args: &[Nodes],
-        fin: &Option<Value>,
-    ) -> Result<Value, ExecError> {
-        let name = &ident.ident;
-        let function = self
-            .template
-            .funcs
-            .get(name.as_str())
-            .ok_or_else(|| ExecError::UndefinedFunction(name.to_string()))?;
-        self.eval_call(ctx, *function, args, fin)
-    }
-
-    fn eval_call(
-        &mut self,
-        ctx: &Context,
-        function: Func,
-        args: &[Nodes],
-        fin: &Option<Value>,
-    ) -> Result<Value, ExecError> {
-        let mut arg_vals = vec![];
-        if !args.is_empty() {
-            for arg in &args[1..] {
-                let val = self.eval_arg(ctx, arg)?;
-                arg_vals.push(val);
+        dot: &Val<'a>,
+        func: &'a Function,
+        args: &'a [Arg],
+        fin: &Option<Val<'a>>,
+    ) -> Result<Val<'a>, ExecError> {
+        if !func.allowed {
+            return Err(ExecError::FuncNotAllowed(func.name.clone()));
+        }
+        let scope = self.scope;
+        if let Some(function) = scope.funcs.get(func.name.as_str()) {
+            let arg_vals = self.eval_args(dot, args, fin)?;
+            self.count_call()?;
+            return self.logged(|_| with_values(arg_vals, |values| function(scope, values)));
+        }
+        let function = func
+            .func
+            .ok_or_else(|| ExecError::UndefinedFunction(func.name.clone()))?;
+        let arg_vals = self.eval_args(dot, args, fin)?;
+        self.count_call()?;
+        self.logged(|state| {
+            if let (Some(ref async_func), Some(_)) = (&func.async_func, &state.log) {
+                return Err(state.suspend(async_func(into_values(arg_vals))));
+            }
+            match func.builtin {
+                Builtin::Len if arg_vals.len() == 1 => {
+                    if let Val::Lazy(ref data) = arg_vals[0] {
+                        return lazy_len(&**data);
+                    }
+                }
+                Builtin::Index if arg_vals.len() > 1 => return index(&arg_vals),
+                _ => {}
             }
+            with_values(arg_vals, function)
+        })
+    }
+
+    fn eval_args(
+        &mut self,
+        dot: &Val<'a>,
+        args: &'a [Arg],
+        fin: &Option<Val<'a>>,
+    ) -> Result<Vec<Val<'a>>, ExecError> {
+        let mut arg_vals = Vec::with_capacity(args.len() + 1);
+        for arg in args {
+            let val = self.eval_arg(dot, arg)?;
+            arg_vals.push(val);
         }
         if let Some(ref f) = *fin {
             arg_vals.push(f.clone());
         }
-        // println!("{:?}", arg_vals);
-
-        function(&arg_vals).map_err(Into::into)
+        Ok(arg_vals)
     }
 
-    fn eval_chain_node(
-        &mut self,
-        ctx: &Context,
-        chain: &ChainNode,
-        args: &[Nodes],
-        fin: &Option<Value>,
-    ) -> Result<Value, ExecError> {
-        if chain.field.is_empty() {
-            return Err(ExecError::NoFieldsInEvalChainNode);
-        }
-        if let Nodes::Nil(_) = *chain.node {
-            return Err(ExecError::NullInChain(chain.clone()));
-        }
-        let pipe = self.eval_arg(ctx, &*chain.node)?;
-        self.eval_field_chain(&pipe, &chain.field, args, fin)
-    }
-
-    fn eval_arg(&mut self, ctx: &Context, node: &Nodes) -> Result<Value, ExecError> {
-        match *node {
-            Nodes::Dot(_) => Ok(ctx.dot.clone()),
-            //Nodes::Nil
-            Nodes::Field(ref n) => self.eval_field_node(ctx, n, &[], &None), // args?
-            Nodes::Variable(ref n) => self.eval_variable_node(n, &[], &None),
-            Nodes::Pipe(ref n) => self.eval_pipeline(ctx, n),
-            // Nodes::Identifier
-            Nodes::Identifier(ref n) => self.eval_function(ctx, n, &[], &None),
-            Nodes::Chain(ref n) => self.eval_chain_node(ctx, n, &[], &None),
-            Nodes::String(ref n) => Ok(n.value.clone()),
-            Nodes::Bool(ref n) => Ok(n.value.clone()),
-            Nodes::Number(ref n) => Ok(n.value.clone()),
-            _ => Err(ExecError::InvalidArgument(node.clone())),
-        }
-    }
-
-    fn eval_field_node(
-        &mut self,
-        ctx: &Context,
-        field: &FieldNode,
-        args: &[Nodes],
-        fin: &Option<Value>,
-    ) -> Result<Value, ExecError> {
-        self.eval_field_chain(&ctx.dot, &field.ident, args, fin)
+    fn eval_arg(&mut self, dot: &Val<'a>, arg: &'a Arg) -> Result<Val<'a>, ExecError> {
+        match *arg {
+            Arg::Dot => Ok(dot.clone()),
+            Arg::Field(ref fields) => self.eval_field_chain(dot, dot, fields, &[], &None),
+            Arg::Variable(ref var) => {
+                let recv = self.var_value(var);
+                if var.fields.is_empty() {
+                    return Ok(recv);
+                }
+                self.eval_field_chain(dot, &recv, &var.fields, &[], &None)
+            }
+            Arg::Pipe(ref pipe) => self.eval_pipeline(dot, pipe),
+            Arg::Func(ref func) => self.eval_function(dot, func, &[], &None),
+            Arg::Chain(ref chain) => {
+                let recv = self.eval_arg(dot, &chain.base)?;
+                self.eval_field_chain(dot, &recv, &chain.fields, &[], &None)
+            }
+            Arg::BadChain(ref chain) => Err(chain_error(chain)),
+            Arg::Const(ref value) => Ok(Val::Ref(value)),
+            Arg::Invalid(ref node) => Err(ExecError::InvalidArgument(node.clone())),
+        }
     }
 
     fn eval_field_chain(
         &mut self,
-        receiver: &Value,
+        dot: &Val<'a>,
+        receiver: &Val<'a>,
         ident: &[String],
-        args: &[Nodes],
-        fin: &Option<Value>,
-    ) -> Result<Value, ExecError> {
-        let n = ident.len();
-        if n < 1 {
-            return Err(ExecError::FieldChainWithoutFields);
-        }
-        // TODO clean shit up
-        let mut r: Value = Value::from(0);
-        for (i, id) in ident.iter().enumerate().take(n - 1) {
-            r = self.eval_field(if i == 0 { receiver } else { &r }, id, &[], &None)?;
+        args: &'a [Arg],
+        fin: &Option<Val<'a>>,
+    ) -> Result<Val<'a>, ExecError> {
+        let (last, init) = ident
+            .split_last()
+            .ok_or(ExecError::FieldChainWithoutFields)?;
+        let mut recv = receiver.clone();
+        for id in init {
+            recv = self.eval_field(dot, &recv, id, &[], &None)?;
         }
-        self.eval_field(if n == 1 { receiver } else { &r }, &ident[n - 1], args, fin)
+        self.eval_field(dot, &recv, last, args, fin)
     }
 
+    // Looks up `field_name` in `receiver`. Fields holding a function behave like
+    // methods: they are invoked with the receiver as first argument followed by
+    // the evaluated arguments and the final pipeline value.
     fn eval_field(
         &mut self,
-        receiver: &Value,
+        dot: &Val<'a>,
+        receiver: &Val<'a>,
         field_name: &str,
-        args: &[Nodes],
-        fin: &Option<Value>,
-    ) -> Result<Value, ExecError> {
-        let has_args = args.len() > 1 || fin.is_some();
+        args: &'a [Arg],
+        fin: &Option<Val<'a>>,
+    ) -> Result<Val<'a>, ExecError> {
+        let ret = receiver.field(field_name)?;
+        if let Some(Value::Function(ref f)) = ret.value() {
+            if !self.program.data_funcs_allowed {
+                return Err(ExecError::DataFuncNotAllowed);
+            }
+            let args = self.eval_args(dot, args, fin)?;
+            // Lazy receivers are only materialised once the arguments are known.
+            let mut arg_vals = vec![receiver.to_value()];
+            arg_vals.extend(into_values(args));
+            self.count_call()?;
+            let f = f.f;
+            return self.logged(|_| f(&arg_vals).map(Val::from).map_err(Into::into));
+        }
+        let has_args = !args.is_empty() || fin.is_some();
         if has_args {
             return Err(ExecError::NotAFunctionButArguments(field_name.to_string()));
         }
-        let ret = match *receiver {
-            Value::Object(ref o) => o
-                .get(field_name)
-                .cloned()
-                .ok_or_else(|| ExecError::NoFiledFor(field_name.to_string(), receiver.clone())),
-            Value::Map(ref o) => Ok(o.get(field_name).cloned().unwrap_or(Value::NoValue)),
-            _ => Err(ExecError::OnlyMapsAndObjectsHaveFields),
-        };
-        if let Ok(Value::Function(ref f)) = ret {
-            return (f.f)(&[receiver.clone()]).map_err(Into::into);
-        }
-        ret
+        Ok(ret)
     }
 
-    fn eval_variable_node(
+    // Starts the next iteration of the innermost range: sets the range
+    // variables and pushes the item as dot. Ends the loop if there are no
+    // items left.
+    fn next(
         &mut self,
-        variable: &VariableNode,
-        args: &[Nodes],
-        fin: &Option<Value>,
-    ) -> Result<Value, ExecError> {
-        let val = self.var_value(&variable.ident[0])?;
-        if variable.ident.len() == 1 {
-            not_a_function(args, fin)?;
-            return Ok(val);
-        }
-        self.eval_field_chain(&val, &variable.ident[1..], args, fin)
-    }
-
-    // Walks an `if` or `with` node. They behave the same, except that `with` sets dot.
-    fn walk_if_or_with(&mut self, node: &'a Nodes, ctx: &Context) -> Result<(), ExecError> {
-        let pipe = match *node {
-            Nodes::If(ref n) | Nodes::With(ref n) => &n.pipe,
-            _ => return Err(ExecError::ExpectedIfOrWith(node.clone())),
-        };
-        let val = self.eval_pipeline(ctx, pipe)?;
-        let truth = is_true(&val);
-        if truth {
-            match *node {
-                Nodes::If(ref n) => self.walk_list(ctx, &n.list)?,
-                Nodes::With(ref n) => {
-                    let ctx = Context { dot: val };
-                    self.walk_list(&ctx, &n.list)?;
-                }
-                _ => {}
+        key: Option<usize>,
+        value: Option<usize>,
+        end: usize,
+    ) -> Result<(), ExecError> {
+        let item = self.call().loops.last_mut().and_then(|items| items.next());
+        let (k, v) = match item {
+            Some(item) => item,
+            None => {
+                let call = self.call();
+                call.loops.pop();
+                call.pc = end;
+                return Ok(());
             }
-        } else {
-            match *node {
-                Nodes::If(ref n) | Nodes::With(ref n) => {
-                    if let Some(ref otherwise) = n.else_list {
-                        self.walk_list(ctx, otherwise)?;
-                    }
-                }
-                _ => {}
+        };
+        self.scope.interrupted()?;
+        self.usage.range_iterations += 1;
+        if let Some(max) = self.program.limits.max_range_iterations {
+            if self.usage.range_iterations > max {
+                return Err(ExecError::MaxRangeIterations(max));
             }
         }
-        Ok(())
-    }
-
-    fn one_iteration(
-        &mut self,
-        key: Value,
-        val: Value,
-        range: &'a RangeNode,
-    ) -> Result<(), ExecError> {
-        if !range.pipe.decl.is_empty() {
-            self.set_kth_last_var_value(1, val.clone())?;
+        let call = self.call();
+        if let Some(slot) = value {
+            call.vars[slot] = v.clone();
         }
-        if range.pipe.decl.len() > 1 {
-            self.set_kth_last_var_value(2, key)?;
+        if let Some(slot) = key {
+            call.vars[slot] = Val::from(k);
         }
-        let vars = VecDeque::new();
-        self.vars.push_back(vars);
-        let ctx = Context { dot: val };
-        self.walk_list(&ctx, &range.list)?;
-        self.vars.pop_back();
+        call.dots.push(v);
         Ok(())
     }
 
-    fn walk_range(&mut self, ctx: &Context, range: &'a RangeNode) -> Result<(), ExecError> {
-        let val = self.eval_pipeline(ctx, &range.pipe)?;
-        match val {
-            Value::Object(ref map) | Value::Map(ref map) => {
-                for (k, v) in map.clone() {
-                    self.one_iteration(Value::from(k), v, range)?;
+    // Borrowed collections are iterated in place. Collections produced while
+    // rendering are moved into the loop and only copied if a variable still
+    // holds them.
+    fn walk_range(&mut self, dot: &Val<'a>, pipe: &'a Pipe) -> Result<(), ExecError> {
+        let items: Items<'a> = match self.eval_pipeline(dot, pipe)? {
+            Val::Ref(v) => match *v {
+                Value::Object(ref map) | Value::Map(ref map) => {
+                    Box::new(map.iter().map(|(k, v)| (Value::from(k), Val::Ref(v))))
                 }
-            }
-            Value::Array(ref vec) => {
-                for (k, v) in vec.iter().enumerate() {
-                    self.one_iteration(Value::from(k), v.clone(), range)?;
+                Value::Array(ref vec) => Box::new(
+                    vec.iter()
+                        .enumerate()
+                        .map(|(k, v)| (Value::from(k), Val::Ref(v))),
+                ),
+                _ => return Err(ExecError::InvalidRange(v.clone())),
+            },
+            Val::Own(v) => match Arc::try_unwrap(v).unwrap_or_else(|v| (*v).clone()) {
+                Value::Object(map) | Value::Map(map) => {
+                    Box::new(map.into_iter().map(|(k, v)| (Value::from(k), Val::from(v))))
                 }
+                Value::Array(vec) => Box::new(
+                    vec.into_iter()
+                        .enumerate()
+                        .map(|(k, v)| (Value::from(k), Val::from(v))),
+                ),
+                v => return Err(ExecError::InvalidRange(v)),
+            },
+            Val::Lazy(data) => {
+                let iter = Arc::clone(&data)
+                    .iter()
+                    .ok_or_else(|| ExecError::InvalidRange(describe(&*data)))?;
+                Box::new(iter.map(|(k, v)| (k, Val::from(v))))
             }
-            _ => return Err(ExecError::InvalidRange(val)),
-        }
-        if let Some(ref else_list) = range.else_list {
-            self.walk_list(ctx, else_list)?;
-        }
+        };
+        self.call().loops.push(items);
         Ok(())
     }
 
-    fn print_value(&mut self, val: &Value) -> Result<(), ExecError> {
-        write!(self.writer, "{}", val).map_err(ExecError::IOError)?;
-        Ok(())
+    fn print_value(&mut self, val: &Val<'a>) -> Result<(), ExecError> {
+        self.write(format_args!("{}", val))
     }
 }
 
-fn not_a_function(args: &[Nodes], val: &Option<Value>) -> Result<(), ExecError> {
-    if args.len() > 1 || val.is_some() {
-        return Err(ExecError::ArgumentForNonFunction(args[0].clone()));
+fn not_a_function(node: &Nodes, has_args: bool, val: &Option<Val>) -> Result<(), ExecError> {
+    if has_args || val.is_some() {
+        return Err(ExecError::ArgumentForNonFunction(Box::new(node.clone())));
     }
     Ok(())
 }
 
+fn chain_error(chain: &ChainNode) -> ExecError {
+    if chain.field.is_empty() {
+        return ExecError::NoFieldsInEvalChainNode;
+    }
+    ExecError::NullInChain(chain.clone())
+}
+
+fn into_values(vals: Vec<Val>) -> Vec<Value> {
+    vals.into_iter().map(Val::into_value).collect()
+}
+
+// Calls `f` with the values of `args`. A single borrowed argument is passed
+// without copying it.
+fn with_values<'a, F>(args: Vec<Val<'a>>, f: F) -> Result<Val<'a>, ExecError>
+where
+    F: FnOnce(&[Value]) -> Result<Value, FuncError>,
+{
+    let res = match args[..] {
+        [ref arg] => match arg.value() {
+            Some(value) => f(slice::from_ref(value)),
+            None => f(&[arg.to_value()]),
+        },
+        _ => f(&into_values(args)),
+    };
+    res.map(Val::from).map_err(Into::into)
+}
+
+// Stands in for lazy data in errors instead of materialising it.
+fn describe(data: &dyn TemplateData) -> Value {
+    Value::from(format!("<{}>", data.type_name()))
+}
+
+// `len` on lazy data asks the data source instead of materialising it.
+fn lazy_len<'a>(data: &dyn TemplateData) -> Result<Val<'a>, ExecError> {
+    match data.len() {
+        Some(len) => Ok(Val::from(Value::from(len))),
+        None => Err(FuncError::Generic(format!("unable to call len on {}", describe(data))).into()),
+    }
+}
+
+// `index` walks the keys without copying the collections, lazy data is asked
+// through the data source.
+fn index<'a>(args: &[Val<'a>]) -> Result<Val<'a>, ExecError> {
+    let mut col = args[0].clone();
+    for key in &args[1..] {
+        col = match key.value() {
+            Some(key) => col.item(key)?,
+            None => col.item(&key.to_value())?,
+        };
+    }
+    Ok(col)
+}
+
 #[cfg(test)]
 mod tests_mocked {
     use super::*;
     use anyhow::anyhow;
     use gtmpl_derive::Gtmpl;
-    use gtmpl_value::FuncError;
+    use gtmpl_value::{Func, FuncError};
     use std::collections::HashMap;
 
     #[test]
@@ -648,6 +1671,61 @@ mod tests_mocked {
         assert_eq!(String::from_utf8(w).unwrap(), "43");
     }
 
+    #[test]
+    fn test_method_call() {
+        fn has_role(args: &[Value]) -> Result<Value, FuncError> {
+            if let (Some(Value::Object(ref o)), Some(role)) = (args.first(), args.get(1)) {
+                if let Some(Value::Array(ref roles)) = o.get("roles") {
+                    return Ok(roles.contains(role).into());
+                }
+            }
+            Err(anyhow!("has_role requires a user and a role, got: {:?}", args).into())
+        }
+
+        #[derive(Gtmpl, Clone)]
+        struct User {
+            roles: Vec<String>,
+            has_role: Func,
+        }
+        #[derive(Gtmpl)]
+        struct Page {
+            user: User,
+            role: String,
+        }
+        let page = || {
+            Context::from(Page {
+                user: User {
+                    roles: vec!["admin".to_owned()],
+                    has_role,
+                },
+                role: "editor".to_owned(),
+            })
+        };
+
+        let mut t = Template::default();
+        assert!(t
+            .parse(r#"{{ .user.has_role "admin" }} {{ .user.has_role .role }}"#)
+            .is_ok());
+        assert_eq!(t.render(&page()).unwrap(), "true false");
+
+        let mut t = Template::default();
+        assert!(t
+            .parse(r#"{{ "admin" | .user.has_role }} {{ with $u := .user }}{{ $u.has_role "x" }}{{ end }}"#)
+            .is_ok());
+        assert_eq!(t.render(&page()).unwrap(), "true false");
+
+        let mut t = Template::default();
+        assert!(t.parse(r#"{{ .user.has_role }}"#).is_ok());
+        assert!(t.render(&page()).is_err());
+
+        let mut t = Template::default();
+        assert!(t.parse(r#"{{ .user.roles "admin" }}"#).is_ok());
+        assert!(matches!(
+            t.render(&page()),
+            Err(ExecError::NotAFunctionButArguments(_))
+        ));
+    }
+
     #[test]
     fn test_function_ret_map() {
         fn map(_: &[Value]) -> Result<Value, FuncError> {
@@ -981,4 +2059,704 @@ mod tests_mocked {
         assert!(out.is_ok());
         assert_eq!(String::from_utf8(w).unwrap(), "bar");
     }
+
+    #[test]
+    fn test_context_func() {
+        fn greeting(_: &[Value]) -> Result<Value, FuncError> {
+            Ok(Value::from("hello"))
+        }
+        struct User(String);
+
+        let mut t = Template::default();
+        t.add_func("greeting", greeting);
+        t.declare_func("currentUser");
+        assert!(t
+            .parse(r#"{{ greeting }} {{ currentUser }}{{ range . }} {{ currentUser . }}{{ end }}"#)
+            .is_ok());
+
+        let mut data = Context::from(vec![1, 2]);
+        data.insert_ext(User("gopher".to_owned()));
+        data.add_func("currentUser", |ctx, args| {
+            let user = ctx.ext::<User>().map(|u| u.0.clone()).unwrap_or_default();
+            Ok(Value::from(format!("{}{}", user, args.len())))
+        });
+        assert_eq!(t.render(&data).unwrap(), "hello gopher0 gopher1 gopher1");
+
+        data.add_func("greeting", |_, _| Ok(Value::from("hallo")));
+        assert!(data.insert_ext(User("rustacean".to_owned())).is_some());
+        assert_eq!(
+            t.render(&data).unwrap(),
+            "hallo rustacean0 rustacean1 rustacean1"
+        );
+
+        let out = t.render(&Context::from(vec![1]));
+        assert!(matches!(out, Err(ExecError::FuncError(_))));
+    }
+
+    #[test]
+    fn test_lazy_data() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static MATERIALISED: AtomicUsize = AtomicUsize::new(0);
+
+        // A list of users where every user counts how often it is materialised.
+        struct Users(usize);
+        struct User(usize);
+
+        impl TemplateData for User {
+            fn field(&self, name: &str) -> Option<Data> {
+                match name {
+                    "Id" => Some(Value::from(self.0).into()),
+                    "Name" => Some(Value::from(format!("user{}", self.0)).into()),
+                    _ => None,
+                }
+            }
+            fn to_value(&self) -> Value {
+                MATERIALISED.fetch_add(1, Ordering::SeqCst);
+                Value::from(format!("user{}", self.0))
+            }
+        }
+
+        impl TemplateData for Users {
+            fn field(&self, _: &str) -> Option<Data> {
+                None
+            }
+            fn index(&self, key: &Value) -> Option<Data> {
+                match *key {
+                    Value::Number(ref n) => n
+                        .as_u64()
+                        .filter(|&i| (i as usize) < self.0)
+                        .map(|i| Data::lazy(User(i as usize))),
+                    _ => None,
+                }
+            }
+            fn len(&self) -> Option<usize> {
+                Some(self.0)
+            }
+            fn iter(self: Arc<Self>) -> Option<Box<dyn Iterator<Item = (Value, Data)> + Send>> {
+                Some(Box::new(
+                    (0..self.0).map(|i| (Value::from(i), Data::lazy(User(i)))),
+                ))
+            }
+            fn to_value(&self) -> Value {
+                MATERIALISED.fetch_add(self.0, Ordering::SeqCst);
+                Value::Array((0..self.0).map(|i| User(i).to_value()).collect())
+            }
+        }
+
+        let mut t = Template::default();
+        assert!(t
+            .parse(r#"{{ len . }} {{ range $i, $u := . }}{{ $i }}:{{ $u.Name }},{{ end }} {{ (index . 2).Name }}"#)
+            .is_ok());
+        let out = t.render(&Context::from_data(Users(3)));
+        assert_eq!(out.unwrap(), "3 0:user0,1:user1,2:user2, user2");
+        assert_eq!(MATERIALISED.load(Ordering::SeqCst), 0);
+
+        let mut t = Template::default();
+        assert!(t.parse(r#"{{ if . }}{{ index . 1 }}{{ end }}"#).is_ok());
+        let out = t.render(&Context::from_data(Users(2)));
+        assert_eq!(out.unwrap(), "user1");
+        assert_eq!(MATERIALISED.load(Ordering::SeqCst), 1);
+
+        let mut t = Template::default();
+        assert!(t.parse(r#"{{ .Missing }}"#).is_ok());
+        let out = t.render(&Context::from_data(User(1)));
+        assert!(matches!(out, Err(ExecError::NoFiledFor(..))));
+        assert!(t.parse(r#"{{ index . 5 }}"#).is_ok());
+        assert!(t.render(&Context::from_data(Users(2))).is_err());
+    }
+
+    #[test]
+    fn test_lazy_range() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Counts up forever, remembering how many items were taken.
+        struct Naturals(AtomicUsize);
+
+        impl TemplateData for Naturals {
+            fn field(&self, _: &str) -> Option<Data> {
+                None
+            }
+            fn iter(self: Arc<Self>) -> Option<Box<dyn Iterator<Item = (Value, Data)> + Send>> {
+                Some(Box::new((0..).map(move |i: usize| {
+                    self.0.fetch_add(1, Ordering::SeqCst);
+                    (Value::from(i), Value::from(i).into())
+                })))
+            }
+            fn to_value(&self) -> Value {
+                unreachable!()
+            }
+        }
+
+        let naturals = Arc::new(Naturals(AtomicUsize::new(0)));
+        let mut ctx = Context::with_dot(Data::Lazy(naturals.clone()));
+        let mut t = Template {
+            limits: Limits::default().with_max_range_iterations(5),
+            ..Default::default()
+        };
+        t.parse("{{ range . }}{{ . }}{{ end }}").unwrap();
+        let mut out = vec![];
+        let err = t.execute(&mut out, &ctx).unwrap_err();
+        assert!(matches!(err, ExecError::MaxRangeIterations(5)));
+        assert_eq!(String::from_utf8(out).unwrap(), "01234");
+        assert_eq!(naturals.0.load(Ordering::SeqCst), 6);
+
+        // Cancellation is noticed while iterating.
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancel = cancelled.clone();
+        ctx.set_cancel(cancelled);
+        ctx.add_func("stop", move |_, _| {
+            cancel.store(true, Ordering::SeqCst);
+            Ok(Value::NoValue)
+        });
+        t.limits = Limits::default();
+        t.declare_func("stop");
+        t.parse("{{ range . }}{{ if eq . 3 }}{{ stop }}{{ end }}{{ end }}")
+            .unwrap();
+        assert!(matches!(t.render(&ctx), Err(ExecError::Cancelled)));
+        assert!(naturals.0.load(Ordering::SeqCst) < 12);
+    }
+
+    #[test]
+    fn test_lazy_data_errors() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static MATERIALISED: AtomicUsize = AtomicUsize::new(0);
+
+        // Data without length, items or iteration.
+        struct Opaque;
+
+        impl TemplateData for Opaque {
+            fn field(&self, _: &str) -> Option<Data> {
+                None
+            }
+            fn to_value(&self) -> Value {
+                MATERIALISED.fetch_add(1, Ordering::SeqCst);
+                Value::from("opaque")
+            }
+        }
+
+        let render = |text: &str| {
+            let mut t = Template::default();
+            t.parse(text).unwrap();
+            t.render(&Context::from_data(Opaque)).unwrap_err()
+        };
+        match render("{{ .Missing }}") {
+            ExecError::NoFiledFor(name, Value::String(data)) => {
+                assert_eq!(name, "Missing");
+                assert!(data.contains("Opaque"), "{}", data);
+            }
+            err => panic!("unexpected error: {}", err),
+        }
+        let err = render(r#"{{ index . "a" }}"#).to_string();
+        assert!(
+            err.contains("unable to get a in <") && err.contains("Opaque"),
+            "{}",
+            err
+        );
+        let err = render("{{ len . }}").to_string();
+        assert!(err.contains("unable to call len on <"), "{}", err);
+        assert!(matches!(
+            render("{{ range . }}{{ end }}"),
+            ExecError::InvalidRange(_)
+        ));
+        assert_eq!(MATERIALISED.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_limits() {
+        fn render(limits: Limits, text: &str, ctx: Context) -> (Result<(), ExecError>, String) {
+            let mut t = Template {
+                limits,
+                ..Default::default()
+            };
+            t.parse(text).unwrap();
+            let mut w = vec![];
+            let res = t.execute(&mut w, &ctx);
+            (res, String::from_utf8(w).unwrap())
+        }
+
+        let (res, out) = render(
+            Limits::default().with_max_output_bytes(5),
+            "abc{{ . }}",
+            Context::from("def"),
+        );
+        assert!(matches!(res, Err(ExecError::MaxOutputBytes(5))));
+        assert_eq!(out, "abc");
+        let (res, out) = render(
+            Limits::default().with_max_output_bytes(6),
+            "abc{{ . }}",
+            Context::from("def"),
+        );
+        assert!(res.is_ok());
+        assert_eq!(out, "abcdef");
+
+        let items = || Context::from(vec![1, 2, 3]);
+        let text = "{{ range . }}{{ range $ }}{{ end }}{{ end }}";
+        let (res, _) = render(
+            Limits::default().with_max_range_iterations(12),
+            text,
+            items(),
+        );
+        assert!(res.is_ok());
+        let (res, _) = render(
+            Limits::default().with_max_range_iterations(11),
+            text,
+            items(),
+        );
+        assert!(matches!(res, Err(ExecError::MaxRangeIterations(11))));
+
+        let text = "{{ range . }}{{ len $ | printf \"%d\" }}{{ end }}";
+        let (res, _) = render(Limits::default().with_max_func_calls(6), text, items());
+        assert!(res.is_ok());
+        let (res, out) = render(Limits::default().with_max_func_calls(5), text, items());
+        assert!(matches!(res, Err(ExecError::MaxFuncCalls(5))));
+        assert_eq!(out, "33");
+
+        let (res, _) = render(Limits::default().with_max_steps(12), text, items());
+        assert!(res.is_ok());
+        let (res, _) = render(Limits::default().with_max_steps(11), text, items());
+        assert!(matches!(res, Err(ExecError::MaxSteps(11))));
+
+        let text = r#"{{ define "a" }}{{ template "a" }}{{ end }}{{ template "a" }}"#;
+        let (res, _) = render(Limits::default().with_max_template_depth(10), text, items());
+        assert!(matches!(res, Err(ExecError::MaxTemplateDepth)));
+    }
+
+    #[test]
+    fn test_cancel_and_deadline() {
+        use std::time::Duration;
+
+        let mut t = Template::default();
+        t.declare_func("stop");
+        t.parse("{{ range . }}{{ . }}{{ if eq . 2 }}{{ stop }}{{ end }}{{ end }}")
+            .unwrap();
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut ctx = Context::from(vec![1, 2, 3]);
+        ctx.set_cancel(flag.clone());
+        ctx.add_func("stop", move |_, _| {
+            flag.store(true, Ordering::Relaxed);
+            Ok(Value::from(""))
+        });
+        let mut w = vec![];
+        let res = t.execute(&mut w, &ctx);
+        assert!(matches!(res, Err(ExecError::Cancelled)));
+        assert_eq!(String::from_utf8(w).unwrap(), "12");
+
+        let mut ctx = Context::from(vec![1]);
+        ctx.set_deadline(Instant::now() + Duration::from_secs(60));
+        assert!(t.render(&ctx).is_ok());
+        ctx.set_deadline(Instant::now());
+        assert!(matches!(t.render(&ctx), Err(ExecError::DeadlineExceeded)));
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        let mut t = Template::default();
+        t.parse(
+            r#"{{ define "a" }}{{ if . }}{{ range . }}{{ template "a" $ }}{{ end }}{{ end }}{{ end }}{{ template "a" . }}"#,
+        )
+        .unwrap();
+        let res = t.render(&Context::from(vec![1]));
+        assert!(matches!(res, Err(ExecError::MaxTemplateDepth)));
+
+        let depth = 64;
+        let text = format!(
+            "{}x{}{{{{ {}1{} }}}}",
+            "{{ with . }}".repeat(depth),
+            "{{ end }}".repeat(depth),
+            "(print ".repeat(depth),
+            ")".repeat(depth),
+        );
+        let mut t = Template::default();
+        t.parse(text).unwrap();
+        assert_eq!(t.render(&Context::from(1)).unwrap(), "x1");
+    }
+
+    #[test]
+    fn test_zero_copy() {
+        fn addr(args: &[Value]) -> Result<Value, FuncError> {
+            Ok(Value::from(&args[0] as *const Value as usize))
+        }
+
+        let mut a = HashMap::new();
+        a.insert("b".to_owned(), Value::from(vec![1, 2, 3]));
+        let mut data = HashMap::new();
+        data.insert("a".to_owned(), Value::Map(a));
+        let ctx = Context::from(Value::Map(data));
+
+        let b = match ctx.dot {
+            Data::Value(Value::Map(ref data)) => match data["a"] {
+                Value::Map(ref a) => &a["b"],
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let mut expected = format!("{} ", b as *const Value as usize);
+        if let Value::Array(ref items) = *b {
+            for item in items {
+                expected += &format!("{},", item as *const Value as usize);
+            }
+        }
+
+        let mut t = Template::default();
+        t.add_func("addr", addr);
+        t.parse(r#"{{ addr .a.b }} {{ range $v := .a.b }}{{ addr $v }},{{ end }}"#)
+            .unwrap();
+        assert_eq!(t.render(&ctx).unwrap(), expected);
+
+        // Neither `with`, `range` nor template calls copy the data.
+        t.parse(concat!(
+            r#"{{ define "addr" }}{{ addr . }}{{ end }}"#,
+            r#"{{ with .a }}{{ template "addr" .b }} {{ range .b }}{{ template "addr" . }},{{ end }}{{ end }}"#,
+        ))
+        .unwrap();
+        assert_eq!(t.render(&ctx).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_chunks_and_fmt() {
+        let mut t = Template::default();
+        t.parse(r#"{{ range . }}<{{ . }}>{{ end }}{{ index . 9 }}"#)
+            .unwrap();
+        let program = t.compile();
+        let ctx = Context::from(vec![1, 2, 3]);
+        let chunks: Vec<_> = program.chunks(&ctx, 4).collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].as_ref().unwrap(), "<1><");
+        assert_eq!(chunks[1].as_ref().unwrap(), "2><3");
+        assert!(matches!(chunks[2], Err(ExecError::FuncError(_))));
+
+        // Nothing is rendered after an error.
+        t.parse(r#"{{ range . }}<{{ . }}>{{ end }}{{ index . 9 }}{{ range . }}{{ . }}{{ end }}"#)
+            .unwrap();
+        let program = t.compile();
+        let mut chunks = program.chunks(&ctx, 1).skip(9);
+        assert!(matches!(chunks.next(), Some(Err(ExecError::FuncError(_)))));
+        assert!(chunks.next().is_none());
+        assert!(chunks.next().is_none());
+
+        t.parse(r#"{{ range . }}<{{ . }}>{{ end }}"#).unwrap();
+        let program = t.compile();
+        let chunks: Vec<String> = program.chunks(&ctx, 1).map(Result::unwrap).collect();
+        assert_eq!(chunks, vec!["<", "1", ">", "<", "2", ">", "<", "3", ">"]);
+        assert_eq!(program.chunks(&Context::from(vec![0; 0]), 1).count(), 0);
+
+        let mut out = String::new();
+        t.limits = Limits::default().with_max_output_bytes(5);
+        let err = t.render_to_fmt(&mut out, &ctx).unwrap_err();
+        assert!(matches!(err, ExecError::MaxOutputBytes(5)));
+        assert_eq!(out, "<1><2");
+    }
+
+    // Returns `Pending` once before it is ready.
+    struct Yield(bool);
+
+    impl Future for Yield {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    // Writes one byte at a time and is only ready every other poll.
+    #[derive(Default)]
+    struct Slow {
+        out: Vec<u8>,
+        ready: bool,
+    }
+
+    impl AsyncWrite for Slow {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if !self.ready {
+                self.ready = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            self.ready = false;
+            self.out.push(buf[0]);
+            Poll::Ready(Ok(1))
+        }
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        struct Unpark(std::thread::Thread);
+        impl std::task::Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker = std::task::Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = std::task::Context::from_waker(&waker);
+        let mut fut = Box::pin(fut);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(out) => return out,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    static COUNTED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    fn count(args: &[Value]) -> Result<Value, FuncError> {
+        COUNTED.fetch_add(1, Ordering::SeqCst);
+        Ok(args[0].clone())
+    }
+
+    fn async_template() -> Template {
+        let mut t = Template::default();
+        t.add_func("count", count);
+        t.add_async_func("fetch", |args: Vec<Value>| async move {
+            Yield(false).await;
+            match args[0] {
+                Value::Number(ref n) if n.as_i64() == Some(0) => {
+                    Err(FuncError::Generic("not found".to_string()))
+                }
+                ref v => Ok(Value::from(format!("f{}", v))),
+            }
+        });
+        t
+    }
+
+    #[test]
+    fn test_execute_async() {
+        let mut t = async_template();
+        t.parse(concat!(
+            r#"{{ $x := count 1 }}{{ range $i, $v := . }}[{{ fetch (count $v) }}]{{ end }}"#,
+            r#"{{ printf "%v-%v" (count 2) (fetch 9) }} {{ printf "%v%v" (fetch $x) (fetch 2) }}"#,
+        ))
+        .unwrap();
+        let ctx = Context::from(vec![1, 2, 3]);
+        let mut out = Slow::default();
+        let fut = t.execute_async(&mut out, &ctx);
+        fn assert_send<T: Send>(_: &T) {}
+        assert_send(&fut);
+        block_on(fut).unwrap();
+        assert_eq!(String::from_utf8(out.out).unwrap(), "[f1][f2][f3]2-f9 f1f2");
+        // Ops that awaited are run again, but sync functions are only called
+        // once per call site and the usage isn't counted twice.
+        assert_eq!(COUNTED.load(Ordering::SeqCst), 5);
+        t.limits = Limits::default().with_max_func_calls(13);
+        let mut out: Vec<u8> = vec![];
+        assert!(block_on(t.execute_async(&mut out, &ctx)).is_ok());
+        t.limits = Limits::default().with_max_func_calls(12);
+        let err = block_on(t.execute_async(&mut out, &ctx)).unwrap_err();
+        assert!(matches!(err, ExecError::MaxFuncCalls(12)));
+
+        let err = t.render(&ctx).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "async function must be rendered with execute_async"
+        );
+    }
+
+    #[test]
+    fn test_execute_async_error() {
+        let mut t = async_template();
+        t.parse(r#"a{{ fetch . }}b"#).unwrap();
+        let mut out: Vec<u8> = vec![];
+        let err = block_on(t.execute_async(&mut out, &Context::from(0))).unwrap_err();
+        assert_eq!(err.to_string(), "not found");
+        assert_eq!(out, b"a");
+
+        // Errors of async functions are caught like those of sync ones.
+        t.parse(r#"A{{ try }}x{{ fetch . }}{{ catch $e }}caught:{{ $e }}{{ end }}B"#)
+            .unwrap();
+        let mut out: Vec<u8> = vec![];
+        block_on(t.execute_async(&mut out, &Context::from(0))).unwrap();
+        assert_eq!(out, b"Acaught:not foundB");
+        let mut out: Vec<u8> = vec![];
+        block_on(t.execute_async(&mut out, &Context::from(1))).unwrap();
+        assert_eq!(out, b"Axf1B");
+
+        fn echo(args: &[Value]) -> Result<Value, FuncError> {
+            Ok(args[0].clone())
+        }
+        t.add_func("fetch", echo);
+        t.parse(r#"a{{ fetch . }}b"#).unwrap();
+        let mut out: Vec<u8> = vec![];
+        block_on(t.execute_async(&mut out, &Context::from(0))).unwrap();
+        assert_eq!(out, b"a0b");
+    }
+
+    #[test]
+    fn test_render_lenient() {
+        let mut t = Template::default();
+        t.parse(concat!(
+            "{{ define \"row\" }}<{{ .x }}>{{ end }}",
+            "{{ range . }}{{ template \"row\" . }}{{ end }}\n",
+            "{{ range .x }}r{{ else }}e{{ end }}|{{ with .x }}w{{ else }}e{{ end }}|",
+            "{{ $v := .x }}{{ $v }}{{ template \"none\" }}.",
+        ))
+        .unwrap();
+        let rendered = t.render_lenient(&Context::from(vec![1]), "#").unwrap();
+        assert_eq!(rendered.output, "<#>\n#|#|#<no value>#.");
+        let errors: Vec<String> = rendered.errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "row:1:23: only maps and objects have fields",
+                ":2:10: only maps and objects have fields",
+                ":2:45: only maps and objects have fields",
+                ":2:75: only maps and objects have fields",
+                ":2:106: template none not defined",
+            ]
+        );
+        assert_eq!(rendered.errors[0].template, "row");
+
+        t.limits = Limits::default().with_max_output_bytes(3);
+        let rendered = t.render_lenient(&Context::from(vec![1, 2]), "#").unwrap();
+        assert_eq!(rendered.output, "<#>");
+        assert_eq!(rendered.errors.len(), 2);
+        assert!(matches!(
+            rendered.errors[1].error,
+            ExecError::MaxOutputBytes(3)
+        ));
+
+        let t = Template::default();
+        assert!(t.render_lenient(&Context::empty(), "#").is_err());
+    }
+
+    #[test]
+    fn test_render_lenient_nested() {
+        fn even(args: &[Value]) -> Result<Value, FuncError> {
+            match args[0] {
+                Value::Number(ref n) if n.as_u64().map(|n| n % 2) == Some(0) => Ok("ok".into()),
+                ref v => Err(FuncError::Generic(format!("{} is odd", v))),
+            }
+        }
+
+        let mut t = Template::default();
+        t.add_func("even", even);
+        t.parse(concat!(
+            "{{ define \"row\" }}<{{ even . }}|{{ . }}>{{ end }}",
+            "{{ range . }}[{{ even . }}{{ . }}]{{ end }}\n",
+            "{{ template \"row\" 7 }}{{ range . }}{{ template \"row\" . }}{{ end }}.",
+        ))
+        .unwrap();
+        let rendered = t
+            .render_lenient(&Context::from(vec![1, 2, 3]), "#")
+            .unwrap();
+        assert_eq!(rendered.output, "[#1][ok2][#3]\n<#|7><#|1><ok|2><#|3>.");
+        let errors: Vec<(&str, usize)> = rendered
+            .errors
+            .iter()
+            .map(|e| (e.template.as_str(), e.line))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![("", 1), ("", 1), ("row", 1), ("row", 1), ("row", 1)]
+        );
+        assert!(rendered.errors[2].to_string().ends_with("7 is odd"));
+    }
+
+    #[test]
+    fn test_try_catch() {
+        fn fail(args: &[Value]) -> Result<Value, FuncError> {
+            Err(FuncError::Generic(args[0].to_string()))
+        }
+        let ctx = Context::from(vec![0, 5, 1]);
+        let render = |src: &str| {
+            let mut t = Template::default();
+            t.add_func("fail", fail);
+            t.parse(src).unwrap();
+            t.render(&ctx)
+        };
+        assert_eq!(
+            render(r#"{{ try }}a{{ index . 0 }}{{ catch }}x{{ end }}"#).unwrap(),
+            "a0"
+        );
+        assert_eq!(
+            render(r#"{{ try }}a{{ fail "oops" }}b{{ catch $err }}<{{ $err }}>{{ end }}!"#)
+                .unwrap(),
+            "<oops>!"
+        );
+        assert_eq!(render(r#"{{ try }}a{{ .x }}{{ end }}b"#).unwrap(), "b");
+        assert_eq!(
+            render(r#"{{ range . }}{{ try }}{{ index $ . }}{{ catch }}-{{ end }}{{ end }}"#)
+                .unwrap(),
+            "0-5"
+        );
+        assert_eq!(
+            render(concat!(
+                r#"{{ define "t" }}<{{ .x }}>{{ end }}"#,
+                r#"{{ try }}A{{ range . }}{{ try }}{{ template "t" . }}{{ catch }}c{{ end }}{{ end }}"#,
+                r#"{{ with 1 }}{{ .y }}{{ end }}{{ catch $e }}[{{ $e }}]{{ len . }}{{ end }}"#,
+            ))
+            .unwrap(),
+            "[only maps and objects have fields]3"
+        );
+
+        // The output of a try body is held back until it succeeded.
+        let mut t = Template::default();
+        t.parse(r#"a{{ try }}b{{ index . 9 }}{{ catch }}c{{ end }}{{ try }}d{{ end }}"#)
+            .unwrap();
+        let mut out: Vec<u8> = vec![];
+        t.execute(&mut out, &ctx).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "acd");
+        t.limits = Limits::default().with_max_output_bytes(3);
+        assert_eq!(t.render(&ctx).unwrap(), "acd");
+
+        // Fatal errors are not caught.
+        t.parse(r#"{{ try }}{{ range . }}{{ . }}{{ end }}{{ catch }}c{{ end }}"#)
+            .unwrap();
+        t.limits = Limits::default().with_max_range_iterations(2);
+        let err = t.render(&ctx).unwrap_err();
+        assert!(matches!(err, ExecError::MaxRangeIterations(2)));
+    }
+
+    #[test]
+    fn test_capture() {
+        let render = |src: &str, ctx: Context| {
+            let mut t = Template::default();
+            t.parse(src).unwrap();
+            t.render(&ctx).unwrap()
+        };
+        assert_eq!(
+            render(
+                r#"{{ capture $x }}Hello {{ . }}{{ end }}[{{ len $x }}] {{ $x | printf "(%s)" }}"#,
+                Context::from("World")
+            ),
+            "[11] (Hello World)"
+        );
+        assert_eq!(
+            render(
+                r#"{{ define "t" }}<{{ . }}>{{ end }}{{ capture $x }}{{ template "t" . }}{{ end }}{{ $x }}{{ $x }}"#,
+                Context::from(1)
+            ),
+            "<1><1>"
+        );
+        assert_eq!(
+            render(
+                r#"{{ try }}{{ capture $x }}a{{ index . 9 }}{{ end }}b{{ catch }}c{{ end }}d"#,
+                Context::from(vec![1, 2])
+            ),
+            "cd"
+        );
+        assert_eq!(
+            render(
+                r#"{{ capture $x }}a{{ try }}b{{ index . 9 }}{{ catch }}c{{ end }}{{ try }}e{{ end }}{{ end }}[{{ $x }}]"#,
+                Context::from(vec![1, 2])
+            ),
+            "[ace]"
+        );
+
+        let mut t = Template::default();
+        t.parse(r#"{{ define "t" }}{{ capture $x }}{{ template "t" }}{{ end }}{{ end }}{{ template "t" }}"#)
+            .unwrap();
+        t.limits = Limits::default().with_max_template_depth(10);
+        let err = t.render(&Context::empty()).unwrap_err();
+        assert!(matches!(err, ExecError::MaxTemplateDepth));
+    }
 }
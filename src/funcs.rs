@@ -1,8 +1,10 @@
 //! Builtin functions.
 use std::cmp::Ordering;
-use std::fmt::Write;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
 
 use gtmpl_value::{Func, FuncError, Value};
+use lazy_static::lazy_static;
 use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
 
 use crate::printf::sprintf;
@@ -39,6 +41,164 @@ pub static BUILTINS: &[(&str, Func)] = &[
     ("call", call as Func),
 ];
 
+lazy_static! {
+    /// Metadata for all functions in `BUILTINS`.
+    pub static ref BUILTIN_INFO: HashMap<&'static str, FuncInfo> = {
+        let mut m = HashMap::new();
+        m.insert("eq", FuncInfo::new(2, None)
+            .with_doc("Returns the boolean truth of arg1 == arg2 [== arg3 ...]."));
+        m.insert("ne", FuncInfo::new(2, Some(2))
+            .with_doc("Returns the boolean truth of arg1 != arg2."));
+        m.insert("lt", FuncInfo::new(2, Some(2))
+            .with_doc("Returns the boolean truth of arg1 < arg2."));
+        m.insert("le", FuncInfo::new(2, Some(2))
+            .with_doc("Returns the boolean truth of arg1 <= arg2."));
+        m.insert("gt", FuncInfo::new(2, Some(2))
+            .with_doc("Returns the boolean truth of arg1 > arg2."));
+        m.insert("ge", FuncInfo::new(2, Some(2))
+            .with_doc("Returns the boolean truth of arg1 >= arg2."));
+        m.insert("len", FuncInfo::new(1, Some(1))
+            .with_doc("Returns the integer length of its argument."));
+        m.insert("and", FuncInfo::new(1, None)
+            .with_doc("Returns the first empty argument or the last argument."));
+        m.insert("or", FuncInfo::new(1, None)
+            .with_doc("Returns the first non-empty argument or the last argument."));
+        m.insert("not", FuncInfo::new(1, Some(1))
+            .with_doc("Returns the boolean negation of its single argument."));
+        m.insert("urlquery", FuncInfo::new(1, Some(1))
+            .with_args(&[ArgKind::String])
+            .with_doc("Returns its argument escaped for embedding in a URL query."));
+        m.insert("print", FuncInfo::new(0, None)
+            .with_doc("Formats its arguments like golang's fmt.Sprint."));
+        m.insert("println", FuncInfo::new(0, None)
+            .with_doc("Formats its arguments like golang's fmt.Sprintln."));
+        m.insert("printf", FuncInfo::new(1, None)
+            .with_args(&[ArgKind::String, ArgKind::Any])
+            .with_doc("Formats its arguments according to a format string like golang's fmt.Sprintf."));
+        m.insert("index", FuncInfo::new(2, None)
+            .with_doc("Returns the result of indexing its first argument by the following arguments."));
+        m.insert("call", FuncInfo::new(1, None)
+            .with_args(&[ArgKind::Function, ArgKind::Any])
+            .with_doc("Calls its first argument, which must be a function, with the remaining arguments."));
+        m
+    };
+}
+
+/// The kind of value a function argument accepts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    Any,
+    Bool,
+    Number,
+    String,
+    Array,
+    Map,
+    Function,
+}
+
+impl ArgKind {
+    /// Returns whether `val` is acceptable for an argument of this kind.
+    pub fn accepts(&self, val: &Value) -> bool {
+        matches!(
+            (self, val),
+            (ArgKind::Any, _)
+                | (ArgKind::Bool, Value::Bool(_))
+                | (ArgKind::Number, Value::Number(_))
+                | (ArgKind::String, Value::String(_))
+                | (ArgKind::Array, Value::Array(_))
+                | (ArgKind::Map, Value::Map(_))
+                | (ArgKind::Map, Value::Object(_))
+                | (ArgKind::Function, Value::Function(_))
+        )
+    }
+}
+
+impl fmt::Display for ArgKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match *self {
+            ArgKind::Any => "any",
+            ArgKind::Bool => "bool",
+            ArgKind::Number => "number",
+            ArgKind::String => "string",
+            ArgKind::Array => "array",
+            ArgKind::Map => "map",
+            ArgKind::Function => "function",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Metadata describing a template function.
+///
+/// Functions registered with metadata have their arity checked when a template
+/// is parsed instead of failing when the call is executed.
+///
+/// ## Example
+///
+/// ```rust
+/// use go_template::funcs::{ArgKind, FuncInfo};
+///
+/// let info = FuncInfo::new(1, Some(2))
+///     .with_args(&[ArgKind::String, ArgKind::Number])
+///     .with_doc("Repeats a string.");
+/// assert!(info.check_arity(2).is_ok());
+/// assert!(info.check_arity(3).is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct FuncInfo {
+    /// Minimum number of arguments.
+    pub min_args: usize,
+    /// Maximum number of arguments, `None` for variadic functions.
+    pub max_args: Option<usize>,
+    /// Argument kinds by position. The last kind applies to all remaining arguments.
+    pub args: Vec<ArgKind>,
+    /// Human readable documentation.
+    pub doc: String,
+}
+
+impl FuncInfo {
+    pub fn new(min_args: usize, max_args: Option<usize>) -> FuncInfo {
+        FuncInfo {
+            min_args,
+            max_args,
+            args: vec![],
+            doc: String::new(),
+        }
+    }
+
+    pub fn with_args(mut self, args: &[ArgKind]) -> FuncInfo {
+        self.args = args.to_vec();
+        self
+    }
+
+    pub fn with_doc<T: Into<String>>(mut self, doc: T) -> FuncInfo {
+        self.doc = doc.into();
+        self
+    }
+
+    /// Returns the kind expected for the argument at position `i`.
+    pub fn arg_kind(&self, i: usize) -> ArgKind {
+        self.args
+            .get(i)
+            .or_else(|| self.args.last())
+            .cloned()
+            .unwrap_or(ArgKind::Any)
+    }
+
+    /// Checks whether the function may be called with `n` arguments.
+    pub fn check_arity(&self, n: usize) -> Result<(), String> {
+        match self.max_args {
+            Some(max) if max == self.min_args && n != max => Err(format!("want {} got {}", max, n)),
+            Some(max) if n < self.min_args || n > max => Err(format!(
+                "want between {} and {} got {}",
+                self.min_args, max, n
+            )),
+            None if n < self.min_args => Err(format!("want at least {} got {}", self.min_args, n)),
+            _ => Ok(()),
+        }
+    }
+}
+
 macro_rules! val {
     ($x:expr) => {
         Value::from($x)
@@ -142,7 +302,7 @@ macro_rules! gn {
 ///
 /// # Example
 /// ```
-/// use gtmpl::template;
+/// use go_template::template;
 /// let equal = template("{{ or 1 2.0 false . }}", "foo");
 /// assert_eq!(&equal.unwrap(), "1");
 /// ```
@@ -152,9 +312,8 @@ pub fn or(args: &[Value]) -> Result<Value, FuncError> {
             return Ok(arg.clone());
         }
     }
-    args.iter()
+    args.last()
         .cloned()
-        .last()
         .ok_or_else(|| FuncError::AtLeastXArgs("or".into(), 1))
 }
 
@@ -165,7 +324,7 @@ pub fn or(args: &[Value]) -> Result<Value, FuncError> {
 ///
 /// # Example
 /// ```
-/// use gtmpl::template;
+/// use go_template::template;
 /// let equal = template("{{ and 1 2.0 true . }}", "foo");
 /// assert_eq!(&equal.unwrap(), "foo");
 /// ```
@@ -175,9 +334,8 @@ pub fn and(args: &[Value]) -> Result<Value, FuncError> {
             return Ok(arg.clone());
         }
     }
-    args.iter()
+    args.last()
         .cloned()
-        .last()
         .ok_or_else(|| FuncError::AtLeastXArgs("and".into(), 1))
 }
 
@@ -185,7 +343,7 @@ pub fn and(args: &[Value]) -> Result<Value, FuncError> {
 ///
 /// # Example
 /// ```
-/// use gtmpl::template;
+/// use go_template::template;
 /// let equal = template("{{ not 0 }}", "");
 /// assert_eq!(&equal.unwrap(), "true");
 /// ```
@@ -201,7 +359,7 @@ pub fn not(args: &[Value]) -> Result<Value, FuncError> {
 ///
 /// # Example
 /// ```
-/// use gtmpl::template;
+/// use go_template::template;
 /// let equal = template("{{ len . }}", "foo");
 /// assert_eq!(&equal.unwrap(), "3");
 /// ```
@@ -227,7 +385,7 @@ pub fn len(args: &[Value]) -> Result<Value, FuncError> {
 ///
 /// # Example
 /// ```
-/// use gtmpl::{gtmpl_fn, template, Value};
+/// use go_template::{gtmpl_fn, template, Value};
 /// use gtmpl_value::{FuncError, Function};
 ///
 /// gtmpl_fn!(
@@ -256,7 +414,7 @@ pub fn call(args: &[Value]) -> Result<Value, FuncError> {
 ///
 /// # Example
 /// ```
-/// use gtmpl::template;
+/// use go_template::template;
 /// let equal = template(r#"{{ print "Hello " . "!" }}"#, "world");
 /// assert_eq!(&equal.unwrap(), "Hello world!");
 /// ```
@@ -271,7 +429,7 @@ pub fn print(args: &[Value]) -> Result<Value, FuncError> {
             if no_space {
                 s += &val.to_string();
             } else {
-                s += &format!(" {}", val.to_string())
+                s += &format!(" {}", val)
             }
             no_space = false;
         }
@@ -286,7 +444,7 @@ pub fn print(args: &[Value]) -> Result<Value, FuncError> {
 ///
 /// # Example
 /// ```
-/// use gtmpl::template;
+/// use go_template::template;
 /// let equal = template(r#"{{ println "Hello" . "!" }}"#, "world");
 /// assert_eq!(&equal.unwrap(), "Hello world !\n");
 /// ```
@@ -325,7 +483,7 @@ pub fn println(args: &[Value]) -> Result<Value, FuncError> {
 ///
 /// # Example
 /// ```
-/// use gtmpl::template;
+/// use go_template::template;
 /// let equal = template(r#"{{ printf "%v %s %v" "Hello" . "!" }}"#, "world");
 /// assert_eq!(&equal.unwrap(), "Hello world !");
 /// ```
@@ -347,7 +505,7 @@ pub fn printf(args: &[Value]) -> Result<Value, FuncError> {
 ///
 /// # Example
 /// ```
-/// use gtmpl::template;
+/// use go_template::template;
 /// let ctx = vec![23, 42, 7];
 /// let index = template("{{ index . 1 }}", ctx);
 /// assert_eq!(&index.unwrap(), "42");
@@ -364,9 +522,9 @@ pub fn index(args: &[Value]) -> Result<Value, FuncError> {
     Ok(col.clone())
 }
 
-fn get_item<'a>(col: &'a Value, key: &Value) -> Result<&'a Value, FuncError> {
+pub(crate) fn get_item<'a>(col: &'a Value, key: &Value) -> Result<&'a Value, FuncError> {
     let ret = match (col, key) {
-        (&Value::Array(ref a), &Value::Number(ref n)) => {
+        (Value::Array(a), Value::Number(n)) => {
             if let Some(i) = n.as_u64() {
                 a.get(i as usize)
             } else {
@@ -390,7 +548,7 @@ fn get_item<'a>(col: &'a Value, key: &Value) -> Result<&'a Value, FuncError> {
 ///
 /// # Example
 /// ```
-/// use gtmpl::template;
+/// use go_template::template;
 /// let url = template(r#"{{ urlquery "foo bar?" }}"#, 0);
 /// assert_eq!(&url.unwrap(), "foo%20bar%3F");
 /// ```
@@ -411,7 +569,7 @@ pub fn urlquery(args: &[Value]) -> Result<Value, FuncError> {
 ///
 /// # Example
 /// ```
-/// use gtmpl::template;
+/// use go_template::template;
 /// let equal = template("{{ eq 1 1 . }}", 1);
 /// assert_eq!(&equal.unwrap(), "true");
 /// ```
@@ -429,7 +587,7 @@ Returns the boolean truth of arg1 != arg2
 
 # Example
 ```
-use gtmpl::template;
+use go_template::template;
 let not_equal = template(\"{{ ne 2 . }}\", 1);
 assert_eq!(&not_equal.unwrap(), \"true\");
 ```
@@ -444,7 +602,7 @@ Returns the boolean truth of arg1 < arg2
 
 # Example
 ```
-use gtmpl::template;
+use go_template::template;
 let less_than = template(\"{{ lt 0 . }}\", 1);
 assert_eq!(&less_than.unwrap(), \"true\");
 ```
@@ -464,7 +622,7 @@ Returns the boolean truth of arg1 <= arg2
 
 # Example
 ```
-use gtmpl::template;
+use go_template::template;
 let less_or_equal = template(\"{{ le 1.4 . }}\", 1.4);
 assert_eq!(less_or_equal.unwrap(), \"true\");
 
@@ -487,7 +645,7 @@ Returns the boolean truth of arg1 > arg2
 
 # Example
 ```
-use gtmpl::template;
+use go_template::template;
 let greater_than = template(\"{{ gt 1.4 . }}\", 1.2);
 assert_eq!(&greater_than.unwrap(), \"true\");
 ```
@@ -507,7 +665,7 @@ Returns the boolean truth of arg1 >= arg2
 
 # Example
 ```
-use gtmpl::template;
+use go_template::template;
 let greater_or_equal = template(\"{{ ge 1.4 1.3 }}\", 1.2);
 assert_eq!(greater_or_equal.unwrap(), \"true\");
 
@@ -526,7 +684,7 @@ ge(a: ref Value, b: ref Value) -> Result<Value, FuncError> {
 
 fn cmp(left: &Value, right: &Value) -> Option<Ordering> {
     match (left, right) {
-        (&Value::Number(ref l), &Value::Number(ref r)) => {
+        (Value::Number(l), Value::Number(r)) => {
             if let (Some(lf), Some(rf)) = (l.as_f64(), r.as_f64()) {
                 return lf.partial_cmp(&rf);
             }
@@ -538,9 +696,9 @@ fn cmp(left: &Value, right: &Value) -> Option<Ordering> {
             }
             None
         }
-        (&Value::Bool(ref l), &Value::Bool(ref r)) => l.partial_cmp(r),
-        (&Value::String(ref l), &Value::String(ref r)) => l.partial_cmp(r),
-        (&Value::Array(ref l), &Value::Array(ref r)) => l.len().partial_cmp(&r.len()),
+        (Value::Bool(l), Value::Bool(r)) => l.partial_cmp(r),
+        (Value::String(l), Value::String(r)) => l.partial_cmp(r),
+        (Value::Array(l), Value::Array(r)) => l.len().partial_cmp(&r.len()),
         _ => None,
     }
 }
@@ -744,6 +902,24 @@ mod tests_mocked {
         assert_eq!(ret.unwrap(), Value::Bool(true));
     }
 
+    #[test]
+    fn test_builtin_info() {
+        for (name, _) in BUILTINS {
+            assert!(BUILTIN_INFO.contains_key(name), "no info for {}", name);
+        }
+        let len = &BUILTIN_INFO["len"];
+        assert!(len.check_arity(1).is_ok());
+        assert_eq!(len.check_arity(2), Err("want 1 got 2".to_owned()));
+        let eq = &BUILTIN_INFO["eq"];
+        assert_eq!(eq.check_arity(1), Err("want at least 2 got 1".to_owned()));
+        assert!(eq.check_arity(5).is_ok());
+        let printf = &BUILTIN_INFO["printf"];
+        assert_eq!(printf.arg_kind(0), ArgKind::String);
+        assert_eq!(printf.arg_kind(3), ArgKind::Any);
+        assert!(ArgKind::Map.accepts(&Value::Map(HashMap::new())));
+        assert!(!ArgKind::String.accepts(&val!(1)));
+    }
+
     #[test]
     fn test_gtmpl_fn() {
         gtmpl_fn!(
@@ -18,6 +18,8 @@ lazy_static! {
         let mut m = HashMap::new();
         m.insert(".", ItemType::ItemDot);
         m.insert("block", ItemType::ItemBlock);
+        m.insert("capture", ItemType::ItemCapture);
+        m.insert("catch", ItemType::ItemCatch);
         m.insert("define", ItemType::ItemDefine);
         m.insert("end", ItemType::ItemEnd);
         m.insert("else", ItemType::ItemElse);
@@ -25,6 +27,7 @@ lazy_static! {
         m.insert("range", ItemType::ItemRange);
         m.insert("nil", ItemType::ItemNil);
         m.insert("template", ItemType::ItemTemplate);
+        m.insert("try", ItemType::ItemTry);
         m.insert("with", ItemType::ItemWith);
         m
     };
@@ -54,9 +57,15 @@ pub enum ItemType {
     ItemString,     // quoted string (includes quotes)
     ItemText,       // plain text
     ItemVariable,   // variable starting with '$', such as '$' or  '$1' or '$hello'
+    // Trivia, only emitted if asked for.
+    ItemComment,     // comment including its delimiters and trim markers
+    ItemTrimMarker,  // trim marker next to a delimiter
+    ItemTrimmedText, // whitespace removed by a trim marker
     // Keywords, appear after all the rest.
     ItemKeyword,  // used only to delimit the keywords
     ItemBlock,    // block keyword
+    ItemCapture,  // capture keyword
+    ItemCatch,    // catch keyword
     ItemDot,      // the cursor, spelled '.'
     ItemDefine,   // define keyword
     ItemElse,     // else keyword
@@ -65,6 +74,7 @@ pub enum ItemType {
     ItemNil,      // the untyped nil constant, easiest to treat as a keyword
     ItemRange,    // range keyword
     ItemTemplate, // template keyword
+    ItemTry,      // try keyword
     ItemWith,     // with keyword
 }
 
@@ -112,6 +122,7 @@ struct LexerStateMachine {
     items_sender: Sender<Item>, // channel of scanned items
     paren_depth: usize,         // nesting depth of ( ) exprs
     line: usize,                // 1+number of newlines seen
+    trivia: bool,               // emit comments, trim markers and trimmed text
 }
 
 #[derive(Debug)]
@@ -156,28 +167,13 @@ impl Iterator for Lexer {
 }
 
 impl Lexer {
-    #[cfg(not(target_family = "wasm"))]
     pub fn new(input: String) -> Lexer {
-        let (tx, rx) = channel();
-        let mut l = LexerStateMachine {
-            input,
-            state: State::LexText,
-            pos: 0,
-            start: 0,
-            width: 0,
-            items_sender: tx,
-            paren_depth: 0,
-            line: 1,
-        };
-        thread::spawn(move || l.run());
-        Lexer {
-            last_pos: 0,
-            items_receiver: rx,
-            finished: false,
-        }
+        Lexer::with_trivia(input, false)
     }
-    #[cfg(target_family = "wasm")]
-    pub fn new(input: String) -> Lexer {
+
+    /// Creates a lexer that also emits trivia items if `trivia` is set.
+    /// Trivia never changes the other items.
+    pub fn with_trivia(input: String, trivia: bool) -> Lexer {
         let (tx, rx) = channel();
         let mut l = LexerStateMachine {
             input,
@@ -188,8 +184,11 @@ impl Lexer {
             items_sender: tx,
             paren_depth: 0,
             line: 1,
+            trivia,
         };
-        // thread::spawn(move || l.run());
+        #[cfg(not(target_family = "wasm"))]
+        thread::spawn(move || l.run());
+        #[cfg(target_family = "wasm")]
         l.run();
         Lexer {
             last_pos: 0,
@@ -254,7 +253,7 @@ impl LexerStateMachine {
     }
 
     fn backup(&mut self) {
-        self.pos -= 1;
+        self.pos -= self.width;
         if self.width == 1
             && self.input[self.pos..]
                 .chars()
@@ -292,6 +291,15 @@ impl LexerStateMachine {
         self.start = self.pos;
     }
 
+    // Emits trivia or ignores it like the parser wants.
+    fn trivia(&mut self, t: ItemType) {
+        if self.trivia && self.pos > self.start {
+            self.emit(t);
+        } else {
+            self.ignore();
+        }
+    }
+
     fn accept(&mut self, valid: &str) -> bool {
         if self.next().map(|s| valid.contains(s)).unwrap_or_default() {
             return true;
@@ -313,7 +321,7 @@ impl LexerStateMachine {
 
     fn lex_text(&mut self) -> State {
         self.width = 0;
-        let x = self.input[self.pos..].find(&LEFT_DELIM);
+        let x = self.input[self.pos..].find(LEFT_DELIM);
         match x {
             Some(x) => {
                 self.pos += x;
@@ -328,7 +336,7 @@ impl LexerStateMachine {
                     self.emit(ItemType::ItemText);
                 }
                 self.pos += trim;
-                self.ignore();
+                self.trivia(ItemType::ItemTrimmedText);
                 State::LexLeftDelim
             }
             None => {
@@ -343,7 +351,7 @@ impl LexerStateMachine {
     }
 
     fn at_right_delim(&mut self) -> (bool, bool) {
-        if self.input[self.pos..].starts_with(&RIGHT_DELIM) {
+        if self.input[self.pos..].starts_with(RIGHT_DELIM) {
             return (true, false);
         }
         if self.input[self.pos..].starts_with(&format!("{}{}", RIGHT_TRIM_MARKER, RIGHT_DELIM)) {
@@ -358,12 +366,15 @@ impl LexerStateMachine {
         let after_marker = if trim { LEFT_TRIM_MARKER.len() } else { 0 };
         if self.input[(self.pos + after_marker)..].starts_with(LEFT_COMMENT) {
             self.pos += after_marker;
-            self.ignore();
+            // The whole comment action is a single trivia item.
+            if !self.trivia {
+                self.ignore();
+            }
             State::LexComment
         } else {
             self.emit(ItemType::ItemLeftDelim);
             self.pos += after_marker;
-            self.ignore();
+            self.trivia(ItemType::ItemTrimMarker);
             self.paren_depth = 0;
             State::LexInsideAction
         }
@@ -390,12 +401,12 @@ impl LexerStateMachine {
         }
 
         self.pos += RIGHT_DELIM.len();
+        self.trivia(ItemType::ItemComment);
 
         if trim {
             self.pos += ltrim_len(&self.input[self.pos..]);
+            self.trivia(ItemType::ItemTrimmedText);
         }
-
-        self.ignore();
         State::LexText
     }
 
@@ -403,13 +414,13 @@ impl LexerStateMachine {
         let trim = self.input[self.pos..].starts_with(RIGHT_TRIM_MARKER);
         if trim {
             self.pos += RIGHT_TRIM_MARKER.len();
-            self.ignore();
+            self.trivia(ItemType::ItemTrimMarker);
         }
         self.pos += RIGHT_DELIM.len();
         self.emit(ItemType::ItemRightDelim);
         if trim {
             self.pos += ltrim_len(&self.input[self.pos..]);
-            self.ignore();
+            self.trivia(ItemType::ItemTrimmedText);
         }
         State::LexText
     }
@@ -711,4 +722,13 @@ mod tests {
         let s_ = l.map(|i| i.val).collect::<Vec<String>>().join("");
         assert_eq!(s_, r#"something2000"#);
     }
+
+    #[test]
+    fn test_unclosed_at_eof() {
+        for s in &["{{ ", "{{ foo ", "{{ 1 "] {
+            let last = Lexer::new(s.to_string()).last().unwrap();
+            assert_eq!(last.typ, ItemType::ItemError);
+            assert_eq!(&last.val, "unclosed action");
+        }
+    }
 }
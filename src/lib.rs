@@ -2,27 +2,49 @@
 //!
 //! ## Example
 //! ```rust
-//! use gtmpl;
+//! use go_template;
 //!
-//! let output = gtmpl::template("Finally! Some {{ . }} for Rust", "gtmpl");
+//! let output = go_template::template("Finally! Some {{ . }} for Rust", "gtmpl");
 //! assert_eq!(&output.unwrap(), "Finally! Some gtmpl for Rust");
 //! ```
+#[cfg(feature = "cli")]
+pub mod cli;
+mod compile;
+mod data;
+#[cfg(any(feature = "json", feature = "yaml", feature = "toml"))]
+mod document;
 pub mod error;
 mod exec;
 pub mod funcs;
 mod lexer;
+pub mod lint;
+#[cfg(feature = "lsp")]
+pub mod lsp;
 mod node;
 mod parse;
 mod print_verb;
 mod printf;
+pub mod schema;
+#[cfg(feature = "serde")]
+pub mod serde_value;
 mod template;
+pub mod token;
 mod utils;
+pub mod watch;
 
 #[doc(inline)]
-pub use crate::template::Template;
+pub use crate::template::{Sandbox, Template, Tracked};
 
 #[doc(inline)]
-pub use crate::exec::Context;
+pub use crate::compile::{Builtins, Compiled, Program};
+
+#[doc(inline)]
+pub use crate::exec::{
+    AsyncFunc, AsyncWrite, Chunks, Context, ContextFunc, FuncFuture, Limits, Rendered,
+};
+
+#[doc(inline)]
+pub use crate::data::{Data, TemplateData};
 
 #[doc(inline)]
 pub use gtmpl_value::Func;
@@ -39,7 +61,7 @@ pub use gtmpl_value::Value;
 ///
 /// ## Example
 /// ```rust
-/// let output = gtmpl::template("Finally! Some {{ . }} for Rust", "gtmpl");
+/// let output = go_template::template("Finally! Some {{ . }} for Rust", "gtmpl");
 /// assert_eq!(&output.unwrap(), "Finally! Some gtmpl for Rust");
 /// ```
 pub fn template<T: Into<Value>>(template_str: &str, context: T) -> Result<String, TemplateError> {
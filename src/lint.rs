@@ -0,0 +1,432 @@
+//! Static checks for parsed templates.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use go_template::lint::FindingKind;
+//! use go_template::Template;
+//!
+//! let mut tmpl = Template::with_name("page");
+//! tmpl.skip_func_check = true;
+//! tmpl.parse(r#"{{ $x := 1 }}{{ if true }}{{ template "nav" }}{{ upper . }}{{ end }}"#)
+//!     .unwrap();
+//! let kinds: Vec<_> = tmpl.lint().into_iter().map(|f| f.kind).collect();
+//! assert_eq!(
+//!     kinds,
+//!     vec![
+//!         FindingKind::UnusedVariable,
+//!         FindingKind::ConstantCondition,
+//!         FindingKind::UndefinedTemplate,
+//!         FindingKind::UnknownFunction,
+//!     ]
+//! );
+//! ```
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::node::*;
+use crate::parse::Tree;
+use crate::template::Template;
+
+/// The kind of a lint or schema check finding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FindingKind {
+    /// `{{template "name"}}` refers to a template that is not defined.
+    UndefinedTemplate,
+    /// A `{{define}}` block that is never called.
+    UnusedDefine,
+    /// A variable that is declared but never read.
+    UnusedVariable,
+    /// A variable declaration that hides a variable of an enclosing scope.
+    ShadowedVariable,
+    /// A call of a function the template does not know.
+    UnknownFunction,
+    /// An `if` or `with` whose condition is a constant.
+    ConstantCondition,
+    /// A field that is not in the schema of the data, see `schema::check`.
+    UnknownField,
+    /// A `range` over a value that is neither an array nor an object.
+    NotIterable,
+    /// A value used with a type the schema does not allow.
+    TypeMismatch,
+}
+
+impl FindingKind {
+    /// Stable identifier of the kind, e.g. `unused-variable`.
+    pub fn code(self) -> &'static str {
+        match self {
+            FindingKind::UndefinedTemplate => "undefined-template",
+            FindingKind::UnusedDefine => "unused-define",
+            FindingKind::UnusedVariable => "unused-variable",
+            FindingKind::ShadowedVariable => "shadowed-variable",
+            FindingKind::UnknownFunction => "unknown-function",
+            FindingKind::ConstantCondition => "constant-condition",
+            FindingKind::UnknownField => "unknown-field",
+            FindingKind::NotIterable => "not-iterable",
+            FindingKind::TypeMismatch => "type-mismatch",
+        }
+    }
+}
+
+impl fmt::Display for FindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// A single lint finding with its location.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    /// Name of the template tree the finding is in.
+    pub template: String,
+    /// 1-based line in the parsed source.
+    pub line: usize,
+    /// 1-based column in the parsed source.
+    pub column: usize,
+    pub message: String,
+}
+
+impl Finding {
+    pub(crate) fn new(tree: &Tree, pos: Pos, kind: FindingKind, message: String) -> Finding {
+        let (line, column) = tree.line_col(pos);
+        Finding {
+            kind,
+            template: tree.name().to_owned(),
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {} [{}]",
+            self.template, self.line, self.column, self.message, self.kind
+        )
+    }
+}
+
+impl Template {
+    /// Lints all templates, treating the template named like this one as the
+    /// only entry point. See `lint::lint`.
+    pub fn lint(&self) -> Vec<Finding> {
+        lint(self, &[&self.name])
+    }
+}
+
+/// Lints all trees of `tmpl`. Templates named in `entries` are rendered
+/// directly and never reported as unused. Unknown functions can only be
+/// reported if the template was parsed with `skip_func_check`.
+pub fn lint(tmpl: &Template, entries: &[&str]) -> Vec<Finding> {
+    let mut names: Vec<&String> = tmpl.tree_set.keys().collect();
+    names.sort();
+    let mut linter = Linter {
+        tmpl,
+        findings: vec![],
+        called: HashSet::new(),
+        dynamic: false,
+    };
+    for name in names {
+        let tree = &tmpl.tree_set[name];
+        if let Some(ref root) = tree.root {
+            let mut walker = Walker {
+                linter: &mut linter,
+                tree,
+                scopes: vec![vec![]],
+            };
+            walker.walk(root);
+            walker.pop_scope();
+        }
+    }
+    if !linter.dynamic {
+        let mut unused: Vec<&Tree> = tmpl
+            .tree_set
+            .iter()
+            .filter(|(name, _)| !entries.contains(&name.as_str()))
+            .filter(|(name, _)| !linter.called.contains(name.as_str()))
+            .filter(|(_, tree)| !is_empty(tree))
+            .map(|(_, tree)| tree)
+            .collect();
+        unused.sort_by_key(|t| t.name());
+        for tree in unused {
+            let pos = tree.root.as_ref().map_or(0, |r| r.pos());
+            linter.report(
+                tree,
+                pos,
+                FindingKind::UnusedDefine,
+                format!("template {} is defined but never used", tree.name()),
+            );
+        }
+    }
+    let mut findings = linter.findings;
+    sort(&mut findings);
+    findings
+}
+
+pub(crate) fn sort(findings: &mut [Finding]) {
+    findings.sort_by(|a, b| (&a.template, a.line, a.column).cmp(&(&b.template, b.line, b.column)));
+}
+
+// Files that only contain `{{define}}` blocks leave an empty tree behind.
+fn is_empty(tree: &Tree) -> bool {
+    tree.root
+        .as_ref()
+        .is_none_or(|r| r.is_empty_tree().unwrap_or(false))
+}
+
+struct Linter<'a> {
+    tmpl: &'a Template,
+    findings: Vec<Finding>,
+    called: HashSet<String>,
+    // Set if a template is called with a dynamic name.
+    dynamic: bool,
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, tree: &Tree, pos: Pos, kind: FindingKind, message: String) {
+        self.findings.push(Finding::new(tree, pos, kind, message));
+    }
+}
+
+struct Var {
+    name: String,
+    pos: Pos,
+    used: bool,
+}
+
+struct Walker<'a, 'b> {
+    linter: &'b mut Linter<'a>,
+    tree: &'a Tree,
+    scopes: Vec<Vec<Var>>,
+}
+
+impl<'a, 'b> Walker<'a, 'b> {
+    fn report(&mut self, pos: Pos, kind: FindingKind, message: String) {
+        self.linter.report(self.tree, pos, kind, message);
+    }
+
+    fn walk(&mut self, node: &'a Nodes) {
+        match *node {
+            Nodes::List(ref n) => self.walk_list(n),
+            Nodes::Action(ref n) => self.walk_pipe(&n.pipe),
+            Nodes::If(ref n) | Nodes::With(ref n) | Nodes::Range(ref n) => {
+                self.walk_branch(node, n)
+            }
+            Nodes::Template(ref n) => self.walk_template(n),
+            Nodes::Try(ref n) => self.walk_try(n),
+            Nodes::Capture(ref n) => {
+                self.scopes.push(vec![]);
+                self.walk_list(&n.list);
+                self.pop_scope();
+                self.declare(&n.var);
+            }
+            _ => {}
+        }
+    }
+
+    fn walk_list(&mut self, list: &'a ListNode) {
+        for n in &list.nodes {
+            self.walk(n);
+        }
+    }
+
+    fn walk_branch(&mut self, node: &Nodes, branch: &'a BranchNode) {
+        if let Nodes::If(_) | Nodes::With(_) = *node {
+            self.check_condition(node, &branch.pipe);
+        }
+        self.scopes.push(vec![]);
+        self.walk_pipe(&branch.pipe);
+        self.scopes.push(vec![]);
+        self.walk_list(&branch.list);
+        self.pop_scope();
+        if let Some(ref else_list) = branch.else_list {
+            self.scopes.push(vec![]);
+            self.walk_list(else_list);
+            self.pop_scope();
+        }
+        self.pop_scope();
+    }
+
+    fn walk_try(&mut self, node: &'a TryNode) {
+        self.scopes.push(vec![]);
+        self.walk_list(&node.list);
+        self.pop_scope();
+        if let Some(ref catch_list) = node.catch_list {
+            self.scopes.push(vec![]);
+            if let Some(var) = node.catch.as_ref().and_then(|c| c.var.as_ref()) {
+                self.declare(var);
+            }
+            self.walk_list(catch_list);
+            self.pop_scope();
+        }
+    }
+
+    fn check_condition(&mut self, node: &Nodes, pipe: &PipeNode) {
+        if !pipe.decl.is_empty() || pipe.cmds.len() != 1 || pipe.cmds[0].args.len() != 1 {
+            return;
+        }
+        let arg = &pipe.cmds[0].args[0];
+        if let Nodes::Bool(_) | Nodes::Number(_) | Nodes::String(_) | Nodes::Nil(_) = *arg {
+            let keyword = if let Nodes::If(_) = *node {
+                "if"
+            } else {
+                "with"
+            };
+            self.report(
+                pipe.pos(),
+                FindingKind::ConstantCondition,
+                format!("condition of {} is always {}", keyword, arg),
+            );
+        }
+    }
+
+    fn walk_template(&mut self, template: &'a TemplateNode) {
+        match template.name {
+            PipeOrString::String(ref name) => {
+                self.linter.called.insert(name.clone());
+                if !self.linter.tmpl.tree_set.contains_key(name) {
+                    self.report(
+                        template.pos(),
+                        FindingKind::UndefinedTemplate,
+                        format!("template {} is not defined", name),
+                    );
+                }
+            }
+            PipeOrString::Pipe(ref pipe) => {
+                self.linter.dynamic = true;
+                self.walk_pipe(pipe);
+            }
+        }
+        if let Some(ref pipe) = template.pipe {
+            self.walk_pipe(pipe);
+        }
+    }
+
+    fn walk_pipe(&mut self, pipe: &'a PipeNode) {
+        for cmd in &pipe.cmds {
+            for arg in &cmd.args {
+                self.walk_arg(arg);
+            }
+        }
+        if pipe.is_assign {
+            return;
+        }
+        for var in &pipe.decl {
+            self.declare(var);
+        }
+    }
+
+    fn walk_arg(&mut self, arg: &'a Nodes) {
+        match *arg {
+            Nodes::Identifier(ref n) if !self.linter.tmpl.funcs.contains_key(&n.ident) => {
+                self.report(
+                    n.pos(),
+                    FindingKind::UnknownFunction,
+                    format!("function {} is not defined", n.ident),
+                );
+            }
+            Nodes::Variable(ref n) => self.read(&n.ident[0]),
+            Nodes::Pipe(ref n) => self.walk_pipe(n),
+            Nodes::Chain(ref n) => self.walk_arg(&n.node),
+            _ => {}
+        }
+    }
+
+    fn declare(&mut self, var: &VariableNode) {
+        let name = &var.ident[0];
+        if self.lookup(name).is_some() {
+            self.report(
+                var.pos(),
+                FindingKind::ShadowedVariable,
+                format!("declaration of {} shadows an earlier declaration", name),
+            );
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push(Var {
+                name: name.clone(),
+                pos: var.pos(),
+                used: false,
+            });
+        }
+    }
+
+    fn read(&mut self, name: &str) {
+        if let Some(var) = self.lookup(name) {
+            var.used = true;
+        }
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Var> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|s| s.iter_mut().rev())
+            .find(|v| v.name == name)
+    }
+
+    // Variables starting with `$_` are meant to be unused.
+    fn pop_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        for var in scope {
+            if !var.used && !var.name.starts_with("$_") {
+                self.report(
+                    var.pos,
+                    FindingKind::UnusedVariable,
+                    format!("{} is declared but never used", var.name),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_mocked {
+    use super::*;
+
+    fn lint_str(text: &str) -> Vec<String> {
+        let mut tmpl = Template::with_name("t");
+        tmpl.skip_func_check = true;
+        tmpl.parse(text).unwrap();
+        tmpl.lint().iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn test_lint_clean() {
+        let findings = lint_str(
+            r#"{{ define "a" }}{{ . }}{{ end }}{{ range $_, $v := . }}{{ template "a" $v }}{{ end }}{{ block "b" . }}{{ end }}"#,
+        );
+        assert!(findings.is_empty(), "{:?}", findings);
+    }
+
+    #[test]
+    fn test_lint_findings() {
+        let findings = lint_str(
+            "{{ define \"unused\" }}x{{ end }}{{ $a := 1 }}\n{{ with $b := . }}{{ $a := $b }}{{ end }}{{ if 0 }}{{ nope . }}{{ end }}{{ template \"missing\" }}",
+        );
+        assert_eq!(
+            findings,
+            vec![
+                "t:1:35: $a is declared but never used [unused-variable]",
+                "t:2:22: declaration of $a shadows an earlier declaration [shadowed-variable]",
+                "t:2:22: $a is declared but never used [unused-variable]",
+                "t:2:48: condition of if is always 0 [constant-condition]",
+                "t:2:55: function nope is not defined [unknown-function]",
+                "t:2:85: template missing is not defined [undefined-template]",
+                "unused:1:22: template unused is defined but never used [unused-define]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_assign() {
+        assert!(lint_str("{{ $a := 1 }}{{ $a = 2 }}{{ $a }}").is_empty());
+        assert_eq!(
+            lint_str("{{ $a := 1 }}{{ if . }}{{ $a = 2 }}{{ end }}"),
+            vec!["t:1:4: $a is declared but never used [unused-variable]"]
+        );
+    }
+}
@@ -0,0 +1,743 @@
+//! A language server for template files.
+//!
+//! `Server` speaks the Language Server Protocol over any reader and writer, the
+//! `gtmpl-lsp` binary connects it to stdin and stdout. Open documents are
+//! treated as one set of templates, each named after its file name like the
+//! templates passed to `gtmpl --define`. The server offers
+//!
+//! - diagnostics for parse errors and lint findings,
+//! - go to definition from `{{template "name"}}` to `{{define "name"}}`,
+//! - completion and hover docs for functions,
+//! - document symbols for `define` and `block`.
+//!
+//! Functions the embedding application registers can be announced with the
+//! `functions` array of the `initializationOptions`.
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Read, Write};
+
+use serde_json::{json, Value as Json};
+
+use crate::error::ParseError;
+use crate::funcs::FuncInfo;
+use crate::lexer::{Item, ItemType, Lexer};
+use crate::lint;
+use crate::template::Template;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+const SEVERITY_ERROR: i64 = 1;
+const SEVERITY_WARNING: i64 = 2;
+const COMPLETION_FUNCTION: i64 = 3;
+const SYMBOL_FUNCTION: i64 = 12;
+
+// Larger messages are skipped instead of allocating what the client claims.
+const MAX_MESSAGE_BYTES: usize = 8 << 20;
+
+/// Language server for a set of open template documents.
+///
+/// ## Example
+///
+/// ```rust
+/// use go_template::lsp::Server;
+/// use go_template::Template;
+///
+/// let body = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+/// let input = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+/// let mut output = vec![];
+/// let code = Server::new(Template::default())
+///     .run(&mut input.as_bytes(), &mut output)
+///     .unwrap();
+/// // Exiting without a shutdown request.
+/// assert_eq!(code, 1);
+/// ```
+pub struct Server {
+    template: Template,
+    // Open documents by uri.
+    documents: BTreeMap<String, String>,
+    shutdown: bool,
+}
+
+type RpcError = (i64, String);
+
+impl Server {
+    /// Creates a server that knows the functions of `template`.
+    pub fn new(template: Template) -> Server {
+        Server {
+            template,
+            documents: BTreeMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// Serves requests from `input` until the client sends `exit` or closes
+    /// `input`. Returns the exit code, 0 if the client requested a shutdown
+    /// first.
+    pub fn run(&mut self, input: &mut dyn BufRead, output: &mut dyn Write) -> io::Result<i32> {
+        while let Some(body) = read_message(input)? {
+            let msg = body.and_then(|body| {
+                serde_json::from_slice::<Json>(&body).map_err(|e| (PARSE_ERROR, e.to_string()))
+            });
+            let msg = match msg {
+                Ok(msg) => msg,
+                Err(e) => {
+                    write_message(output, &error_response(Json::Null, e))?;
+                    continue;
+                }
+            };
+            let method = match msg["method"].as_str() {
+                Some(method) => method,
+                // A response to a request of the server, we send none.
+                None => continue,
+            };
+            if method == "exit" {
+                return Ok(if self.shutdown { 0 } else { 1 });
+            }
+            let params = &msg["params"];
+            match msg.get("id") {
+                Some(id) => {
+                    let response = match self.request(method, params) {
+                        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                        Err(e) => error_response(id.clone(), e),
+                    };
+                    write_message(output, &response)?;
+                }
+                None => {
+                    for notification in self.notify(method, params) {
+                        write_message(output, &notification)?;
+                    }
+                }
+            }
+        }
+        Ok(if self.shutdown { 0 } else { 1 })
+    }
+
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, RpcError> {
+        match method {
+            "initialize" => Ok(self.initialize(params)),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/completion" => Ok(self.completion()),
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/documentSymbol" => Ok(self.document_symbols(params)),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {}", method))),
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_owned(), text.to_owned());
+            }
+            "textDocument/didChange" => {
+                // Only full document sync is announced.
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()).map(|c| &c["text"]) {
+                    self.documents
+                        .insert(uri.to_owned(), text.as_str().unwrap_or_default().to_owned());
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                let mut notifications = self.diagnostics();
+                notifications.push(publish(uri, vec![]));
+                return notifications;
+            }
+            _ => return vec![],
+        }
+        self.diagnostics()
+    }
+
+    fn initialize(&mut self, params: &Json) -> Json {
+        let functions = params["initializationOptions"]["functions"].as_array();
+        for name in functions.into_iter().flatten().filter_map(Json::as_str) {
+            if !self.template.funcs.contains_key(name) {
+                self.template.declare_func(name);
+            }
+        }
+        json!({
+            "capabilities": {
+                "textDocumentSync": 1,
+                "definitionProvider": true,
+                "completionProvider": {},
+                "hoverProvider": true,
+                "documentSymbolProvider": true,
+            },
+            "serverInfo": {"name": "gtmpl-lsp", "version": env!("CARGO_PKG_VERSION")},
+        })
+    }
+
+    // Diagnostics for all open documents. Lint findings need all documents
+    // to resolve templates defined in other files.
+    fn diagnostics(&self) -> Vec<Json> {
+        let mut tmpl = Template {
+            funcs: self.template.funcs.clone(),
+            func_info: self.template.func_info.clone(),
+            skip_func_check: true,
+            ..Default::default()
+        };
+        let mut diagnostics: BTreeMap<&str, Vec<Json>> = BTreeMap::new();
+        let mut uris = HashMap::new();
+        let mut entries = vec![];
+        for (uri, text) in &self.documents {
+            diagnostics.insert(uri, vec![]);
+            let name = document_name(uri);
+            match tmpl.parse_trees(name.clone(), text.clone()) {
+                Ok(tree_set) => {
+                    for tree in tree_set.keys() {
+                        uris.entry(tree.clone()).or_insert(uri.as_str());
+                    }
+                    tmpl.tree_set.extend(tree_set);
+                    entries.push(name);
+                }
+                Err(e) => {
+                    if let Some(d) = diagnostics.get_mut(uri.as_str()) {
+                        d.push(parse_diagnostic(text, &e));
+                    }
+                }
+            }
+        }
+        let entries: Vec<&str> = entries.iter().map(String::as_str).collect();
+        for finding in lint::lint(&tmpl, &entries) {
+            let uri = match uris.get(&finding.template) {
+                Some(uri) => *uri,
+                None => continue,
+            };
+            let text = &self.documents[uri];
+            let start = line_col_offset(text, finding.line, finding.column);
+            if let Some(d) = diagnostics.get_mut(uri) {
+                d.push(json!({
+                    "range": range(text, start, word_end(text, start)),
+                    "severity": SEVERITY_WARNING,
+                    "code": finding.kind.code(),
+                    "source": "gtmpl",
+                    "message": finding.message,
+                }));
+            }
+        }
+        diagnostics
+            .into_iter()
+            .map(|(uri, d)| publish(uri, d))
+            .collect()
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let (uri, text, offset) = match self.position(params) {
+            Some(p) => p,
+            None => return Json::Null,
+        };
+        let items = tokens(text);
+        let i = match items.iter().position(|t| contains(t, offset)) {
+            Some(i) => i,
+            None => return Json::Null,
+        };
+        let name = match string_after_keyword(&items, i) {
+            Some(name) => name,
+            None => return Json::Null,
+        };
+        // Prefer a definition in the same document.
+        let documents = self
+            .documents
+            .iter()
+            .filter(|(u, _)| *u == uri)
+            .chain(self.documents.iter().filter(|(u, _)| *u != uri));
+        for (u, t) in documents.clone() {
+            if let Some(s) = symbols(t).into_iter().find(|s| s.name == name) {
+                return json!({"uri": u, "range": range(t, s.name_start, s.name_end)});
+            }
+        }
+        // Templates are also named after their files.
+        for (u, t) in documents {
+            if document_name(u) == name {
+                return json!({"uri": u, "range": range(t, 0, 0)});
+            }
+        }
+        Json::Null
+    }
+
+    fn completion(&self) -> Json {
+        let items: Vec<Json> = self
+            .template
+            .list_funcs()
+            .into_iter()
+            .map(|(name, info)| {
+                let mut item = json!({"label": name, "kind": COMPLETION_FUNCTION});
+                if let Some(info) = info {
+                    item["detail"] = json!(signature(name, info));
+                    item["documentation"] = json!(info.doc);
+                }
+                item
+            })
+            .collect();
+        Json::Array(items)
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let (_, text, offset) = match self.position(params) {
+            Some(p) => p,
+            None => return Json::Null,
+        };
+        let items = tokens(text);
+        let item = match items.iter().find(|t| contains(t, offset)) {
+            Some(item) if item.typ == ItemType::ItemIdentifier => item,
+            _ => return Json::Null,
+        };
+        if !self.template.funcs.contains_key(&item.val) {
+            return Json::Null;
+        }
+        let value = match self.template.func_info.get(&item.val) {
+            Some(info) => format!("```\n{}\n```\n{}", signature(&item.val, info), info.doc),
+            None => format!("```\n{}\n```", item.val),
+        };
+        json!({
+            "contents": {"kind": "markdown", "value": value},
+            "range": range(text, item.pos, item.pos + item.val.len()),
+        })
+    }
+
+    fn document_symbols(&self, params: &Json) -> Json {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = match self.documents.get(uri) {
+            Some(text) => text,
+            None => return Json::Null,
+        };
+        let symbols: Vec<Json> = symbols(text)
+            .into_iter()
+            .map(|s| {
+                json!({
+                    "name": s.name,
+                    "detail": s.keyword,
+                    "kind": SYMBOL_FUNCTION,
+                    "range": range(text, s.start, s.name_end),
+                    "selectionRange": range(text, s.name_start, s.name_end),
+                })
+            })
+            .collect();
+        Json::Array(symbols)
+    }
+
+    // The document and byte offset of a `TextDocumentPositionParams`.
+    fn position<'a>(&'a self, params: &Json) -> Option<(&'a str, &'a str, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let (uri, text) = self.documents.get_key_value(uri)?;
+        let line = params["position"]["line"].as_u64()? as usize;
+        let character = params["position"]["character"].as_u64()? as usize;
+        Some((uri, text, offset(text, line, character)))
+    }
+}
+
+struct Symbol {
+    name: String,
+    keyword: &'static str,
+    // Offset of the left delimiter.
+    start: usize,
+    name_start: usize,
+    name_end: usize,
+}
+
+// All `define` and `block` actions in `text`, also if parsing fails later on.
+fn symbols(text: &str) -> Vec<Symbol> {
+    let items = tokens(text);
+    let mut symbols = vec![];
+    for (i, item) in items.iter().enumerate() {
+        let keyword = match item.typ {
+            ItemType::ItemDefine => "define",
+            ItemType::ItemBlock => "block",
+            _ => continue,
+        };
+        let start = match items[..i]
+            .iter()
+            .rev()
+            .find(|t| t.typ != ItemType::ItemSpace)
+        {
+            Some(t) if t.typ == ItemType::ItemLeftDelim => t.pos,
+            _ => continue,
+        };
+        let name = items[i + 1..].iter().find(|t| t.typ != ItemType::ItemSpace);
+        if let Some(name) = name.filter(|t| is_string(t)) {
+            symbols.push(Symbol {
+                name: unquote(&name.val),
+                keyword,
+                start,
+                name_start: name.pos,
+                name_end: name.pos + name.val.len(),
+            });
+        }
+    }
+    symbols
+}
+
+// The template name at `items[i]` if it follows `template`, `define` or
+// `block`.
+fn string_after_keyword(items: &[Item], i: usize) -> Option<String> {
+    if !is_string(&items[i]) {
+        return None;
+    }
+    let keyword = items[..i]
+        .iter()
+        .rev()
+        .find(|t| t.typ != ItemType::ItemSpace)?;
+    match keyword.typ {
+        ItemType::ItemTemplate | ItemType::ItemDefine | ItemType::ItemBlock => {
+            Some(unquote(&items[i].val))
+        }
+        _ => None,
+    }
+}
+
+fn tokens(text: &str) -> Vec<Item> {
+    Lexer::new(text.to_owned())
+        .take_while(|t| t.typ != ItemType::ItemError && t.typ != ItemType::ItemEOF)
+        .collect()
+}
+
+fn contains(item: &Item, offset: usize) -> bool {
+    item.pos <= offset && offset <= item.pos + item.val.len()
+}
+
+fn is_string(item: &Item) -> bool {
+    item.typ == ItemType::ItemString || item.typ == ItemType::ItemRawString
+}
+
+fn unquote(s: &str) -> String {
+    if s.starts_with('`') {
+        return s.trim_matches('`').to_owned();
+    }
+    serde_json::from_str(s).unwrap_or_else(|_| s.trim_matches('"').to_owned())
+}
+
+fn signature(name: &str, info: &FuncInfo) -> String {
+    let plural = |n| if n == 1 { "" } else { "s" };
+    let args = match info.max_args {
+        Some(max) if max == info.min_args => format!("{} argument{}", max, plural(max)),
+        Some(max) => format!("{} to {} arguments", info.min_args, max),
+        None => format!(
+            "at least {} argument{}",
+            info.min_args,
+            plural(info.min_args)
+        ),
+    };
+    format!("{} ({})", name, args)
+}
+
+fn document_name(uri: &str) -> String {
+    uri.rsplit('/').next().unwrap_or(uri).to_owned()
+}
+
+fn parse_diagnostic(text: &str, e: &ParseError) -> Json {
+    let (line, message) = match e {
+        ParseError::WithContext(ctx, msg) => (ctx.line.saturating_sub(1), msg.clone()),
+        _ => (0, e.to_string()),
+    };
+    // The parser may report the line after the last one.
+    let line = line.min(text.split('\n').count() - 1);
+    let start = offset(text, line, 0);
+    let end = start + text[start..].find('\n').unwrap_or(text.len() - start);
+    json!({
+        "range": range(text, start, end),
+        "severity": SEVERITY_ERROR,
+        "source": "gtmpl",
+        "message": message,
+    })
+}
+
+fn publish(uri: &str, diagnostics: Vec<Json>) -> Json {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics},
+    })
+}
+
+fn error_response(id: Json, (code, message): RpcError) -> Json {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+// Positions in LSP are 0-based lines and UTF-16 code units.
+fn offset(text: &str, line: usize, character: usize) -> usize {
+    let start = match line {
+        0 => 0,
+        _ => match text.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        },
+    };
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn position(text: &str, offset: usize) -> Json {
+    let before = &text[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    json!({"line": line, "character": character})
+}
+
+fn range(text: &str, start: usize, end: usize) -> Json {
+    json!({"start": position(text, start), "end": position(text, end)})
+}
+
+// Byte offset of a 1-based line and column in characters as used by `lint`.
+fn line_col_offset(text: &str, line: usize, column: usize) -> usize {
+    let start = offset(text, line.saturating_sub(1), 0);
+    text[start..]
+        .char_indices()
+        .nth(column.saturating_sub(1))
+        .map_or(text.len(), |(i, _)| start + i)
+}
+
+fn word_end(text: &str, start: usize) -> usize {
+    let len = text[start..]
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .map_or(text.len() - start, |(i, _)| i);
+    start + len
+}
+
+// Reads the body of the next message. A body too large to read is skipped and
+// returned as the error to reply with.
+fn read_message(input: &mut dyn BufRead) -> io::Result<Option<Result<Vec<u8>, RpcError>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    if length > MAX_MESSAGE_BYTES {
+        io::copy(&mut Read::take(input, length as u64), &mut io::sink())?;
+        return Ok(Some(Err((
+            INVALID_REQUEST,
+            format!(
+                "Content-Length {} exceeds {} bytes",
+                length, MAX_MESSAGE_BYTES
+            ),
+        ))));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(Ok(body)))
+}
+
+fn write_message(output: &mut dyn Write, msg: &Json) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests_mocked {
+    use super::*;
+
+    struct Client {
+        input: String,
+        next_id: u64,
+    }
+
+    impl Client {
+        fn new() -> Client {
+            Client {
+                input: String::new(),
+                next_id: 1,
+            }
+        }
+
+        fn send(&mut self, msg: Json) {
+            let body = msg.to_string();
+            self.input += &format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        }
+
+        fn request(&mut self, method: &str, params: Json) -> u64 {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}));
+            id
+        }
+
+        fn notify(&mut self, method: &str, params: Json) {
+            self.send(json!({"jsonrpc": "2.0", "method": method, "params": params}));
+        }
+
+        fn run(&self) -> (i32, Vec<Json>) {
+            let mut output = vec![];
+            let code = Server::new(Template::default())
+                .run(&mut self.input.as_bytes(), &mut output)
+                .unwrap();
+            let mut output = output.as_slice();
+            let mut messages = vec![];
+            while let Some(body) = read_message(&mut output).unwrap() {
+                messages.push(serde_json::from_slice(&body.unwrap()).unwrap());
+            }
+            (code, messages)
+        }
+    }
+
+    fn response(messages: &[Json], id: u64) -> &Json {
+        messages.iter().find(|m| m["id"] == id).unwrap()
+    }
+
+    fn at(uri: &str, line: u64, character: u64) -> Json {
+        json!({"textDocument": {"uri": uri}, "position": {"line": line, "character": character}})
+    }
+
+    #[test]
+    fn test_session() {
+        let main = "file:///t/main.tmpl";
+        let nav = "file:///t/nav.tmpl";
+        let mut c = Client::new();
+        let init = c.request(
+            "initialize",
+            json!({"initializationOptions": {"functions": ["upper"]}}),
+        );
+        c.notify("initialized", json!({}));
+        c.notify(
+            "textDocument/didOpen",
+            json!({"textDocument": {"uri": nav, "text": "{{ define \"nav\" }}{{ upper . }}{{ end }}"}}),
+        );
+        c.notify(
+            "textDocument/didOpen",
+            json!({"textDocument": {"uri": main, "text": "ä{{ template \"nav\" . }}\n{{ if }}"}}),
+        );
+        c.notify(
+            "textDocument/didChange",
+            json!({
+                "textDocument": {"uri": main},
+                "contentChanges": [{"text": "ä{{ template \"nav\" . }}\n{{ $x := len . }}"}],
+            }),
+        );
+        let definition = c.request("textDocument/definition", at(main, 0, 15));
+        let hover = c.request("textDocument/hover", at(main, 1, 10));
+        let completion = c.request("textDocument/completion", at(main, 1, 3));
+        let symbols = c.request(
+            "textDocument/documentSymbol",
+            json!({"textDocument": {"uri": nav}}),
+        );
+        let unknown = c.request("textDocument/formatting", json!({}));
+        c.request("shutdown", Json::Null);
+        c.notify("exit", Json::Null);
+        let (code, messages) = c.run();
+        assert_eq!(code, 0);
+
+        assert_eq!(
+            response(&messages, init)["result"]["capabilities"]["hoverProvider"],
+            true
+        );
+
+        let diagnostics: Vec<&Json> = messages
+            .iter()
+            .filter(|m| m["params"]["uri"] == main)
+            .map(|m| &m["params"]["diagnostics"])
+            .collect();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0][0]["severity"], SEVERITY_ERROR);
+        assert_eq!(diagnostics[0][0]["message"], "missing value for if");
+        assert_eq!(
+            diagnostics[0][0]["range"]["start"],
+            json!({"line": 1, "character": 0})
+        );
+        assert_eq!(diagnostics[1][0]["code"], "unused-variable");
+        assert_eq!(
+            diagnostics[1][0]["range"],
+            json!({"start": {"line": 1, "character": 3}, "end": {"line": 1, "character": 5}})
+        );
+
+        assert_eq!(
+            response(&messages, definition)["result"],
+            json!({"uri": nav, "range": {
+                "start": {"line": 0, "character": 10},
+                "end": {"line": 0, "character": 15},
+            }})
+        );
+        let hover = &response(&messages, hover)["result"];
+        assert_eq!(
+            hover["contents"]["value"],
+            "```\nlen (1 argument)\n```\nReturns the integer length of its argument."
+        );
+        let labels: Vec<&Json> = response(&messages, completion)["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| &i["label"])
+            .collect();
+        assert!(labels.contains(&&json!("printf")));
+        assert!(labels.contains(&&json!("upper")));
+        let symbols = &response(&messages, symbols)["result"];
+        assert_eq!(symbols[0]["name"], "nav");
+        assert_eq!(symbols[0]["detail"], "define");
+        assert_eq!(
+            response(&messages, unknown)["error"]["code"],
+            METHOD_NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_oversized_message() {
+        let mut c = Client::new();
+        c.input += &format!(
+            "Content-Length: {}\r\n\r\n{}",
+            MAX_MESSAGE_BYTES + 1,
+            " ".repeat(MAX_MESSAGE_BYTES + 1)
+        );
+        let shutdown = c.request("shutdown", Json::Null);
+        c.notify("exit", Json::Null);
+        let (code, messages) = c.run();
+        assert_eq!(code, 0);
+        assert_eq!(messages[0]["id"], Json::Null);
+        assert_eq!(messages[0]["error"]["code"], INVALID_REQUEST);
+        assert_eq!(response(&messages, shutdown)["result"], Json::Null);
+    }
+
+    #[test]
+    fn test_positions() {
+        let text = "a€\n𝄞b";
+        assert_eq!(offset(text, 0, 2), 4);
+        assert_eq!(offset(text, 1, 2), 9);
+        assert_eq!(offset(text, 5, 0), text.len());
+        assert_eq!(position(text, 9), json!({"line": 1, "character": 2}));
+        assert_eq!(line_col_offset(text, 2, 2), 9);
+    }
+
+    #[test]
+    fn test_read_message() {
+        let mut input = "Content-Length: 2\r\n\r\n{}".as_bytes();
+        assert_eq!(read_message(&mut input).unwrap().unwrap().unwrap(), b"{}");
+        assert!(read_message(&mut input).unwrap().is_none());
+
+        let huge = format!(
+            "Content-Length: {}\r\n\r\n{}Content-Length: 2\r\n\r\n{{}}",
+            MAX_MESSAGE_BYTES + 1,
+            " ".repeat(MAX_MESSAGE_BYTES + 1)
+        );
+        let mut input = huge.as_bytes();
+        let err = read_message(&mut input).unwrap().unwrap().unwrap_err();
+        assert_eq!(err.0, INVALID_REQUEST);
+        assert_eq!(read_message(&mut input).unwrap().unwrap().unwrap(), b"{}");
+        let err = read_message(&mut "\r\n".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -89,7 +89,13 @@ nodes!(
     RangeNode,
     Range,
     TemplateNode,
-    Template
+    Template,
+    CatchNode,
+    Catch,
+    TryNode,
+    Try,
+    CaptureNode,
+    Capture
 );
 
 pub type Pos = usize;
@@ -137,6 +143,8 @@ impl Nodes {
             | Nodes::If(_)
             | Nodes::Range(_)
             | Nodes::Template(_)
+            | Nodes::Try(_)
+            | Nodes::Capture(_)
             | Nodes::With(_) => Ok(false),
             _ => Err(NodeError::NaTN),
         }
@@ -176,9 +184,7 @@ impl ListNode {
 impl Display for ListNode {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         for n in &self.nodes {
-            if let Err(e) = n.fmt(f) {
-                return Err(e);
-            }
+            n.fmt(f)?;
         }
         Ok(())
     }
@@ -218,7 +224,7 @@ impl PipeNode {
             tr,
             pos,
             decl,
-            is_assign: is_assign,
+            is_assign,
             cmds: vec![],
         }
     }
@@ -342,7 +348,8 @@ impl Display for IdentifierNode {
 
 node!(
     VariableNode {
-        ident: Vec<String>
+        ident: Vec<String>,
+        slot: usize
     }
 );
 
@@ -353,8 +360,15 @@ impl VariableNode {
             tr,
             pos,
             ident: ident.split('.').map(|s| s.to_owned()).collect(),
+            slot: 0,
         }
     }
+
+    /// Sets the slot of the variable in its tree, see `Tree::slots`.
+    pub fn with_slot(mut self, slot: usize) -> VariableNode {
+        self.slot = slot;
+        self
+    }
 }
 
 impl Display for VariableNode {
@@ -457,16 +471,10 @@ impl ChainNode {
 
 impl Display for ChainNode {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        if let Err(e) = {
-            // Handle PipeNode.
-            write!(f, "{}", self.node)
-        } {
-            return Err(e);
-        }
+        // Handle PipeNode.
+        write!(f, "{}", self.node)?;
         for field in &self.field {
-            if let Err(e) = write!(f, ".{}", field) {
-                return Err(e);
-            }
+            write!(f, ".{}", field)?;
         }
         Ok(())
     }
@@ -509,7 +517,7 @@ node!(NumberNode {
 });
 
 impl NumberNode {
-    #[cfg_attr(feature = "cargo-clippy", allow(clippy::float_cmp))]
+    #[allow(clippy::float_cmp)]
     pub fn new(
         tr: TreeId,
         pos: Pos,
@@ -561,10 +569,7 @@ impl NumberNode {
                 let (as_f64, is_f64) = match text.parse::<f64>() {
                     Err(_) => (0.0_f64, false),
                     Ok(f) => {
-                        let frac = text.contains(|c| match c {
-                            '.' | 'e' | 'E' => true,
-                            _ => false,
-                        });
+                        let frac = text.contains(['.', 'e', 'E']);
                         if frac {
                             (f, true)
                         } else {
@@ -673,6 +678,90 @@ impl Display for ElseNode {
     }
 }
 
+node!(CatchNode {
+    var: Option<VariableNode>
+});
+
+impl CatchNode {
+    pub fn new(tr: TreeId, pos: Pos, var: Option<VariableNode>) -> CatchNode {
+        CatchNode {
+            typ: NodeType::Catch,
+            tr,
+            pos,
+            var,
+        }
+    }
+}
+
+impl Display for CatchNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self.var {
+            Some(ref var) => write!(f, "{{{{catch {}}}}}", var),
+            None => write!(f, "{{{{catch}}}}"),
+        }
+    }
+}
+
+node!(
+    TryNode {
+        list: ListNode,
+        catch: Option<CatchNode>,
+        catch_list: Option<ListNode>
+    }
+);
+
+impl TryNode {
+    pub fn new(
+        tr: TreeId,
+        pos: Pos,
+        list: ListNode,
+        catch: Option<CatchNode>,
+        catch_list: Option<ListNode>,
+    ) -> TryNode {
+        TryNode {
+            typ: NodeType::Try,
+            tr,
+            pos,
+            list,
+            catch,
+            catch_list,
+        }
+    }
+}
+
+impl Display for TryNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{{{{try}}}}{}", self.list)?;
+        if let (Some(ref catch), Some(ref catch_list)) = (&self.catch, &self.catch_list) {
+            write!(f, "{}{}", catch, catch_list)?;
+        }
+        write!(f, "{{{{end}}}}")
+    }
+}
+
+node!(CaptureNode {
+    var: VariableNode,
+    list: ListNode
+});
+
+impl CaptureNode {
+    pub fn new(tr: TreeId, pos: Pos, var: VariableNode, list: ListNode) -> CaptureNode {
+        CaptureNode {
+            typ: NodeType::Capture,
+            tr,
+            pos,
+            var,
+            list,
+        }
+    }
+}
+
+impl Display for CaptureNode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{{{{capture {}}}}}{}{{{{end}}}}", self.var, self.list)
+    }
+}
+
 node!(
     BranchNode {
         pipe: PipeNode,
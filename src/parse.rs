@@ -1,13 +1,21 @@
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
 
 use crate::error::ParseError;
+use crate::funcs::FuncInfo;
 use crate::lexer::{Item, ItemType, Lexer};
 use crate::node::*;
+use crate::template::Sandbox;
 use crate::utils::*;
 
 pub struct Parser {
     name: String,
     pub funcs: HashSet<String>,
+    pub func_info: HashMap<String, FuncInfo>,
+    pub skip_func_check: bool,
+    pub sandbox: Option<Sandbox>,
+    pub max_nesting_depth: Option<usize>,
+    text: Arc<str>,
     lex: Option<Lexer>,
     line: usize,
     token: VecDeque<Item>,
@@ -17,13 +25,16 @@ pub struct Parser {
     tree: Option<Tree>,
     tree_stack: VecDeque<Tree>,
     max_tree_id: TreeId,
+    nesting: usize,
 }
 #[derive(Clone)]
 pub struct Tree {
     name: String,
     id: TreeId,
     pub root: Option<Nodes>,
-    vars: Vec<String>,
+    vars: Vec<(String, usize)>,
+    slots: usize,
+    text: Arc<str>,
 }
 
 impl Parser {
@@ -31,6 +42,11 @@ impl Parser {
         Parser {
             name,
             funcs: HashSet::new(),
+            func_info: HashMap::new(),
+            skip_func_check: false,
+            sandbox: None,
+            max_nesting_depth: None,
+            text: Arc::from(""),
             lex: None,
             line: 0,
             token: VecDeque::new(),
@@ -40,20 +56,42 @@ impl Parser {
             tree: None,
             tree_stack: VecDeque::new(),
             max_tree_id: 0,
+            nesting: 0,
         }
     }
 }
 
 impl Tree {
-    fn new(name: String, id: TreeId) -> Tree {
+    fn new(name: String, id: TreeId, text: Arc<str>) -> Tree {
         Tree {
             name,
             id,
             root: None,
             vars: vec![],
+            slots: 1,
+            text,
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the 1-based line and column of the byte offset `pos` in the
+    /// source the tree was parsed from.
+    pub fn line_col(&self, pos: Pos) -> (usize, usize) {
+        let before = &self.text[..pos.min(self.text.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+        (line, col)
+    }
+
+    /// Returns the number of variable slots used by the tree. Each declaration
+    /// gets its own slot, slot 0 holds `$`.
+    pub fn slots(&self) -> usize {
+        self.slots
+    }
+
     pub fn pop_vars(&mut self, n: usize) {
         self.vars.truncate(n);
     }
@@ -63,9 +101,18 @@ pub fn parse(
     name: String,
     text: String,
     funcs: HashSet<String>,
+    func_info: HashMap<String, FuncInfo>,
+    skip_func_check: bool,
+    sandbox: Option<Sandbox>,
+    max_nesting_depth: Option<usize>,
 ) -> Result<HashMap<String, Tree>, ParseError> {
     let mut p = Parser::new(name);
+    p.max_nesting_depth = max_nesting_depth;
     p.funcs = funcs;
+    p.func_info = func_info;
+    p.skip_func_check = skip_func_check;
+    p.sandbox = sandbox;
+    p.text = Arc::from(text.as_str());
     p.lex = Some(Lexer::new(text));
     p.parse_tree()?;
     Ok(p.tree_set)
@@ -140,7 +187,7 @@ impl Parser {
             self.tree_stack.push_back(t);
         }
         self.tree_id = id;
-        let t = Tree::new(name, id);
+        let t = Tree::new(name, id, self.text.clone());
         self.tree = Some(t);
     }
 
@@ -193,11 +240,13 @@ impl Parser {
         self.error_msg(&format!("unexpected {} in {}", token, context))
     }
 
-    fn add_var(&mut self, name: String) -> Result<(), ParseError> {
+    fn add_var(&mut self, name: String) -> Result<usize, ParseError> {
         let mut tree = self.tree.take().ok_or_else(|| self.error_msg("no tree"))?;
-        tree.vars.push(name);
+        let slot = tree.slots;
+        tree.slots += 1;
+        tree.vars.push((name, slot));
         self.tree = Some(tree);
-        Ok(())
+        Ok(slot)
     }
 
     fn add_to_tree_set(&mut self) -> Result<(), ParseError> {
@@ -223,6 +272,13 @@ impl Parser {
         self.funcs.contains(name)
     }
 
+    fn check_template_call(&self) -> Result<(), ParseError> {
+        if self.sandbox.as_ref().is_some_and(|s| !s.templates) {
+            return self.error("template calls are not allowed");
+        }
+        Ok(())
+    }
+
     fn parse(&mut self) -> Result<(), ParseError> {
         if self.tree.is_none() {
             return self.error("no tree");
@@ -260,6 +316,7 @@ impl Parser {
             let node = match self.text_or_action() {
                 Ok(Nodes::Else(node)) => return self.error(&format!("unexpected {}", node)),
                 Ok(Nodes::End(node)) => return self.error(&format!("unexpected {}", node)),
+                Ok(Nodes::Catch(node)) => return self.error(&format!("unexpected {}", node)),
                 Ok(node) => node,
                 Err(e) => return Err(e),
             };
@@ -302,13 +359,27 @@ impl Parser {
         self.stop_parse()
     }
 
+    fn nest(&mut self) -> Result<(), ParseError> {
+        if let Some(max) = self.max_nesting_depth {
+            if self.nesting >= max {
+                return self.error("max nesting depth exceeded");
+            }
+        }
+        self.nesting += 1;
+        Ok(())
+    }
+
     fn item_list(&mut self) -> Result<(ListNode, Nodes), ParseError> {
+        self.nest()?;
         let pos = self.peek_non_space_must("item list")?.pos;
         let mut list = ListNode::new(self.tree_id, pos);
         while self.peek_non_space_must("item list")?.typ != ItemType::ItemEOF {
             let node = self.text_or_action()?;
             match *node.typ() {
-                NodeType::End | NodeType::Else => return Ok((list, node)),
+                NodeType::End | NodeType::Else | NodeType::Catch => {
+                    self.nesting -= 1;
+                    return Ok((list, node));
+                }
                 _ => list.append(node),
             }
         }
@@ -332,11 +403,14 @@ impl Parser {
         let token = self.next_non_space_must("action")?;
         match token.typ {
             ItemType::ItemBlock => return self.block_control(),
+            ItemType::ItemCapture => return self.capture_control(),
+            ItemType::ItemCatch => return self.catch_control(),
             ItemType::ItemElse => return self.else_control(),
             ItemType::ItemEnd => return self.end_control(),
             ItemType::ItemIf => return self.if_control(),
             ItemType::ItemRange => return self.range_control(),
             ItemType::ItemTemplate => return self.template_control(),
+            ItemType::ItemTry => return self.try_control(token.pos),
             ItemType::ItemWith => return self.with_control(),
             _ => {}
         }
@@ -349,6 +423,8 @@ impl Parser {
         )))
     }
 
+    // The links of an `else if` chain are parsed in a loop and nested
+    // afterwards, so long chains don't recurse while parsing.
     fn parse_control(
         &mut self,
         allow_else_if: bool,
@@ -359,29 +435,43 @@ impl Parser {
             .as_ref()
             .map(|t| t.vars.len())
             .ok_or(ParseError::NoTree)?;
-        let pipe = self.pipeline(context)?;
-        let (list, next) = self.item_list()?;
-        let else_list = match *next.typ() {
-            NodeType::End => None,
-            NodeType::Else => {
-                if allow_else_if && self.peek_must("else if")?.typ == ItemType::ItemIf {
-                    self.next_must("else if")?;
-                    let mut else_list = ListNode::new(self.tree_id, next.pos());
-                    else_list.append(self.if_control()?);
-                    Some(else_list)
-                } else {
+        let mut links = vec![];
+        let (mut pipe, mut list, mut else_list) = loop {
+            let pipe = self.pipeline(context)?;
+            let (list, next) = self.item_list()?;
+            match *next.typ() {
+                NodeType::End => break (pipe, list, None),
+                NodeType::Else => {
+                    if allow_else_if && self.peek_must("else if")?.typ == ItemType::ItemIf {
+                        self.next_must("else if")?;
+                        links.push((pipe, list, next.pos()));
+                        continue;
+                    }
                     let (else_list, next) = self.item_list()?;
                     if *next.typ() != NodeType::End {
                         return self.error(&format!("expected end; found {}", next));
                     }
-                    Some(else_list)
+                    break (pipe, list, Some(else_list));
                 }
+                _ => return self.error(&format!("expected end; found {}", next)),
             }
-            _ => return self.error(&format!("expected end; found {}", next)),
         };
         if let Some(t) = self.tree.as_mut() {
             t.pop_vars(vars_len);
         }
+        while let Some((outer_pipe, outer_list, else_pos)) = links.pop() {
+            let mut outer_else = ListNode::new(self.tree_id, else_pos);
+            outer_else.append(Nodes::If(IfNode::new_if(
+                self.tree_id,
+                pipe.pos(),
+                pipe,
+                list,
+                else_list,
+            )));
+            pipe = outer_pipe;
+            list = outer_list;
+            else_list = Some(outer_else);
+        }
         Ok((pipe.pos(), pipe, list, else_list))
     }
 
@@ -434,8 +524,90 @@ impl Parser {
         Ok(Nodes::Else(ElseNode::new(token.pos, token.line)))
     }
 
+    // Variables declared in the body are not visible in the catch list.
+    fn try_control(&mut self, pos: Pos) -> Result<Nodes, ParseError> {
+        self.expect(&ItemType::ItemRightDelim, "try")?;
+        let vars_len = self
+            .tree
+            .as_ref()
+            .map(|t| t.vars.len())
+            .ok_or(ParseError::NoTree)?;
+        let (list, next) = self.item_list()?;
+        if let Some(t) = self.tree.as_mut() {
+            t.pop_vars(vars_len);
+        }
+        let (catch, catch_list) = match next {
+            Nodes::End(_) => (None, None),
+            Nodes::Catch(mut catch) => {
+                if let Some(ref mut var) = catch.var {
+                    var.slot = self.add_var(var.ident[0].clone())?;
+                }
+                let (catch_list, next) = self.item_list()?;
+                if *next.typ() != NodeType::End {
+                    return self.error(&format!("expected end; found {}", next));
+                }
+                if let Some(t) = self.tree.as_mut() {
+                    t.pop_vars(vars_len);
+                }
+                (Some(catch), Some(catch_list))
+            }
+            _ => return self.error(&format!("expected end; found {}", next)),
+        };
+        Ok(Nodes::Try(TryNode::new(
+            self.tree_id,
+            pos,
+            list,
+            catch,
+            catch_list,
+        )))
+    }
+
+    fn catch_control(&mut self) -> Result<Nodes, ParseError> {
+        let token = self.next_non_space_must("catch")?;
+        let var = match token.typ {
+            ItemType::ItemRightDelim => None,
+            ItemType::ItemVariable if token.val != "$" => {
+                self.expect(&ItemType::ItemRightDelim, "catch")?;
+                Some(VariableNode::new(self.tree_id, token.pos, &token.val))
+            }
+            _ => return Err(self.unexpected(&token, "catch")),
+        };
+        Ok(Nodes::Catch(CatchNode::new(self.tree_id, token.pos, var)))
+    }
+
+    // The variable is declared after the body, like a declaration after its
+    // pipeline.
+    fn capture_control(&mut self) -> Result<Nodes, ParseError> {
+        let token = self.next_non_space_must("capture")?;
+        if token.typ != ItemType::ItemVariable || token.val == "$" {
+            return Err(self.unexpected(&token, "capture"));
+        }
+        self.expect(&ItemType::ItemRightDelim, "capture")?;
+        let mut var = VariableNode::new(self.tree_id, token.pos, &token.val);
+        let vars_len = self
+            .tree
+            .as_ref()
+            .map(|t| t.vars.len())
+            .ok_or(ParseError::NoTree)?;
+        let (list, next) = self.item_list()?;
+        if *next.typ() != NodeType::End {
+            return self.error(&format!("expected end; found {}", next));
+        }
+        if let Some(t) = self.tree.as_mut() {
+            t.pop_vars(vars_len);
+        }
+        var.slot = self.add_var(var.ident[0].clone())?;
+        Ok(Nodes::Capture(CaptureNode::new(
+            self.tree_id,
+            token.pos,
+            var,
+            list,
+        )))
+    }
+
     fn block_control(&mut self) -> Result<Nodes, ParseError> {
         let context = "block clause";
+        self.check_template_call()?;
         let token = self.next_non_space_must(context)?;
         let name = self.parse_template_name(&token, context)?;
         let pipe = self.pipeline(context)?;
@@ -461,6 +633,7 @@ impl Parser {
 
     fn template_control(&mut self) -> Result<Nodes, ParseError> {
         let context = "template clause";
+        self.check_template_call()?;
         let token = self.next_non_space().ok_or(ParseError::UnexpectedEnd)?;
         let name = if let ItemType::ItemLeftParen = token.typ {
             #[cfg(feature = "gtmpl_dynamic_template")]
@@ -470,7 +643,7 @@ impl Parser {
                 PipeOrString::Pipe(pipe)
             }
             #[cfg(not(feature = "gtmpl_dynamic_template"))]
-            return Err(ParseError::NoDynamicTemplate.into());
+            return Err(ParseError::NoDynamicTemplate);
         } else {
             PipeOrString::String(self.parse_template_name(&token, context)?)
         };
@@ -522,9 +695,7 @@ impl Parser {
                     if next.typ == ItemType::ItemAssign {
                         is_assign = true;
                     }
-                    let variable = VariableNode::new(self.tree_id, token.pos, &token.val);
-                    self.add_var(token.val.clone())?;
-                    decl.push(variable);
+                    decl.push(VariableNode::new(self.tree_id, token.pos, &token.val));
                     if next.typ == ItemType::ItemChar && next.val == "," {
                         if context == "range" && decl.len() < 2 {
                             token = self.next_non_space_must("variable")?;
@@ -540,12 +711,24 @@ impl Parser {
         } else {
             self.backup(token);
         }
+        // Assignments need a declared variable, declarations only start after
+        // the pipeline.
+        if is_assign {
+            for var in decl.iter_mut() {
+                var.slot = self.use_var(self.tree_id, var.pos(), &var.ident[0])?.slot;
+            }
+        }
         let mut pipe = PipeNode::new(self.tree_id, pos, decl, is_assign);
         let mut token = self.next_non_space_must("pipeline")?;
         loop {
             match token.typ {
                 ItemType::ItemRightDelim | ItemType::ItemRightParen => {
                     self.check_pipeline(&mut pipe, context)?;
+                    if !is_assign {
+                        for var in pipe.decl.iter_mut() {
+                            var.slot = self.add_var(var.ident[0].clone())?;
+                        }
+                    }
                     if token.typ == ItemType::ItemRightParen {
                         self.backup(token);
                     }
@@ -598,6 +781,60 @@ impl Parser {
                 }
             }
         }
+        for (i, c) in pipe.cmds.iter().enumerate() {
+            self.check_call(c, i > 0)?;
+        }
+        Ok(())
+    }
+
+    // Checks calls of functions with known metadata for the right number and kind
+    // of arguments. Commands after the first pipeline stage receive the result of
+    // the previous stage as additional final argument.
+    fn check_call(&self, cmd: &CommandNode, piped: bool) -> Result<(), ParseError> {
+        for arg in cmd.args.iter().skip(1) {
+            let ident = match *arg {
+                Nodes::Identifier(ref n) => n,
+                Nodes::Chain(ref n) => match *n.node {
+                    Nodes::Identifier(ref n) => n,
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            self.check_args(&ident.ident, &[], false)?;
+        }
+        if let Some(Nodes::Identifier(ref ident)) = cmd.args.first() {
+            self.check_args(&ident.ident, &cmd.args[1..], piped)?;
+        }
+        Ok(())
+    }
+
+    fn check_args(&self, name: &str, args: &[Nodes], piped: bool) -> Result<(), ParseError> {
+        let info = match self.func_info.get(name) {
+            Some(info) => info,
+            None => return Ok(()),
+        };
+        let n = args.len() + if piped { 1 } else { 0 };
+        if let Err(e) = info.check_arity(n) {
+            return self.error(&format!("wrong number of args for {}: {}", name, e));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            let val = match *arg {
+                Nodes::Bool(ref n) => &n.value,
+                Nodes::Number(ref n) => &n.value,
+                Nodes::String(ref n) => &n.value,
+                _ => continue,
+            };
+            let kind = info.arg_kind(i);
+            if !kind.accepts(val) {
+                return self.error(&format!(
+                    "wrong type for argument {} of {}: want {} got {}",
+                    i + 1,
+                    name,
+                    kind,
+                    arg
+                ));
+            }
+        }
         Ok(())
     }
 
@@ -638,8 +875,7 @@ impl Parser {
                         | NodeType::Number
                         | NodeType::Nil
                         | NodeType::Dot => {
-                            return self
-                                .error(&format!("unexpected . after term {}", n.to_string()));
+                            return self.error(&format!("unexpected . after term {}", n));
                         }
                         _ => {}
                     };
@@ -659,11 +895,16 @@ impl Parser {
                             chain.pos(),
                             &chain.to_string(),
                         )),
-                        NodeType::Variable => Nodes::Variable(VariableNode::new(
-                            self.tree_id,
-                            chain.pos(),
-                            &chain.to_string(),
-                        )),
+                        NodeType::Variable => {
+                            let slot = match *chain.node {
+                                Nodes::Variable(ref var) => var.slot,
+                                _ => 0,
+                            };
+                            Nodes::Variable(
+                                VariableNode::new(self.tree_id, chain.pos(), &chain.to_string())
+                                    .with_slot(slot),
+                            )
+                        }
                         _ => Nodes::Chain(chain),
                     };
                     Ok(Some(n))
@@ -680,9 +921,16 @@ impl Parser {
         let node = match token.typ {
             ItemType::ItemError => return self.error(&token.val),
             ItemType::ItemIdentifier => {
-                if !self.has_func(&token.val) {
+                if !self.skip_func_check && !self.has_func(&token.val) {
                     return self.error(&format!("function {} not defined", token.val));
                 }
+                if self
+                    .sandbox
+                    .as_ref()
+                    .is_some_and(|s| !s.allows_func(&token.val))
+                {
+                    return self.error(&format!("function {} is not allowed", token.val));
+                }
                 let mut node = IdentifierNode::new(token.val);
                 node.set_pos(token.pos);
                 node.set_tree(self.tree_id);
@@ -706,7 +954,9 @@ impl Parser {
                 }
             }
             ItemType::ItemLeftParen => {
+                self.nest()?;
                 let pipe = self.pipeline("parenthesized pipeline")?;
+                self.nesting -= 1;
                 let next = self.next_must("parenthesized pipeline")?;
                 if next.typ != ItemType::ItemRightParen {
                     return self.error(&format!("unclosed right paren: unexpected {}", next));
@@ -738,8 +988,9 @@ impl Parser {
             .and_then(|t| {
                 t.vars
                     .iter()
-                    .find(|&v| v == name)
-                    .map(|_| VariableNode::new(tree_id, pos, name))
+                    .rev()
+                    .find(|(var, _)| var == name)
+                    .map(|&(_, slot)| VariableNode::new(tree_id, pos, name).with_slot(slot))
             })
             .ok_or_else(|| self.error_msg(&format!("undefined variable {}", name)))
     }
@@ -803,11 +1054,16 @@ mod tests_mocked {
         make_parser_with_funcs(s, &[])
     }
 
-    fn make_parser_with_funcs<'a>(s: &str, funcs: &[&'a str]) -> Parser {
+    fn make_parser_with_funcs(s: &str, funcs: &[&str]) -> Parser {
         let lex = Lexer::new(s.to_owned());
         Parser {
             name: String::from("foo"),
             funcs: funcs.iter().map(|&k| k.to_owned()).collect(),
+            func_info: HashMap::new(),
+            skip_func_check: false,
+            sandbox: None,
+            max_nesting_depth: None,
+            text: Arc::from(s),
             lex: Some(lex),
             line: 0,
             token: VecDeque::new(),
@@ -817,6 +1073,7 @@ mod tests_mocked {
             tree: None,
             tree_stack: VecDeque::new(),
             max_tree_id: 0,
+            nesting: 0,
         }
     }
 
@@ -886,7 +1143,16 @@ mod tests_mocked {
     #[test]
     fn test_display() {
         let raw = r#"{{if .}}2000{{else}} 3000 {{end}}"#;
-        let mut ts = parse(String::default(), String::from(raw), HashSet::default()).unwrap();
+        let mut ts = parse(
+            String::default(),
+            String::from(raw),
+            HashSet::default(),
+            HashMap::default(),
+            false,
+            None,
+            None,
+        )
+        .unwrap();
         let tree = ts.get_mut("").unwrap();
         if let Some(ref root) = tree.root {
             assert_eq!(raw, format!("{}", root))
@@ -913,6 +1179,40 @@ mod tests_mocked {
         assert!(r.is_ok());
     }
 
+    #[test]
+    fn test_check_arity() {
+        let funcs = &["len", "urlquery"];
+        let info = |p: &mut Parser| {
+            p.func_info = crate::funcs::BUILTIN_INFO
+                .iter()
+                .map(|(&k, v)| (k.to_owned(), v.clone()))
+                .collect();
+        };
+        let mut p = make_parser_with_funcs(r#"{{ len . . }}"#, funcs);
+        info(&mut p);
+        assert_eq!(
+            p.parse_tree().err().unwrap().to_string(),
+            "template: foo:2:wrong number of args for len: want 1 got 2"
+        );
+        let mut p = make_parser_with_funcs(r#"{{ . | len }}"#, funcs);
+        info(&mut p);
+        assert!(p.parse_tree().is_ok());
+        let mut p = make_parser_with_funcs(r#"{{ . | len . }}"#, funcs);
+        info(&mut p);
+        assert!(p.parse_tree().is_err());
+        let mut p = make_parser_with_funcs(r#"{{ if true }}{{ (len) }}{{ end }}"#, funcs);
+        info(&mut p);
+        assert!(p.parse_tree().is_err());
+        let mut p = make_parser_with_funcs(r#"{{ urlquery 1 }}"#, funcs);
+        info(&mut p);
+        assert_eq!(
+            p.parse_tree().err().unwrap().to_string(),
+            "template: foo:2:wrong type for argument 1 of urlquery: want string got 1"
+        );
+        let mut p = make_parser_with_funcs(r#"{{ len . . }}"#, funcs);
+        assert!(p.parse_tree().is_ok());
+    }
+
     #[test]
     fn test_pipeline_simple() {
         let mut p = make_parser_with(r#" $foo, $bar := yay | blub "2000" }}"#);
@@ -961,4 +1261,173 @@ mod tests_mocked {
             panic!()
         }
     }
+
+    #[test]
+    fn test_max_nesting() {
+        let parse = |s: String| {
+            let mut p = make_parser_with(&s);
+            p.max_nesting_depth = Some(16);
+            p.parse_tree()
+        };
+        let ifs = |n: usize| format!("{}{}", "{{ if 1 }}".repeat(n), "{{ end }}".repeat(n));
+        assert!(parse(ifs(16)).is_ok());
+        let err = parse(ifs(17)).unwrap_err();
+        assert!(err.to_string().ends_with("max nesting depth exceeded"));
+        let parens = |n: usize| format!("{{{{ {}1{} }}}}", "(".repeat(n), ")".repeat(n));
+        assert!(parse(parens(16)).is_ok());
+        assert!(parse(parens(17)).is_err());
+
+        let blocks = |n: usize| {
+            let open: String = (0..n)
+                .map(|i| format!(r#"{{{{ block "b{}" . }}}}"#, i))
+                .collect();
+            format!("{}{}", open, "{{ end }}".repeat(n))
+        };
+        assert!(parse(blocks(15)).is_ok());
+        let err = parse(blocks(100)).unwrap_err();
+        assert!(err.to_string().ends_with("max nesting depth exceeded"));
+
+        // The links of an `else if` chain don't nest while parsing.
+        let chain = format!(
+            "{{{{ if 0 }}}}{}{{{{ end }}}}",
+            "{{ else if 0 }}".repeat(1000)
+        );
+        assert!(parse(chain).is_ok());
+    }
+
+    #[test]
+    fn test_var_slots() {
+        let mut p = make_parser_with(
+            r#"{{ $x := 1 }}{{ range $i, $v := . }}{{ $x = $v }}{{ end }}{{ with $x := 2 }}{{ $x }}{{ end }}{{ $x.a }}"#,
+        );
+        p.parse_tree().unwrap();
+        let tree = &p.tree_set["foo"];
+        assert_eq!(tree.slots(), 5);
+        let mut slots = vec![];
+        collect_var_slots(tree.root.as_ref().unwrap(), &mut slots);
+        assert_eq!(
+            slots,
+            vec![
+                ("$x", 1),
+                ("$i", 2),
+                ("$v", 3),
+                ("$x", 1),
+                ("$v", 3),
+                ("$x", 4),
+                ("$x", 4),
+                ("$x", 1)
+            ]
+        );
+
+        for &(s, var) in &[
+            ("{{ $x = 1 }}", "$x"),
+            ("{{ $x := $x }}", "$x"),
+            ("{{ with $x := 1 }}{{ end }}{{ $x }}", "$x"),
+            ("{{ range $i, $v := . }}{{ end }}{{ $v }}", "$v"),
+        ] {
+            let mut p = make_parser_with(s);
+            let err = p.parse_tree().unwrap_err();
+            assert!(err
+                .to_string()
+                .ends_with(&format!("undefined variable {}", var)));
+        }
+    }
+
+    #[test]
+    fn test_try() {
+        let mut p =
+            make_parser_with(r#"{{ try }}a{{ $x := 1 }}{{ catch $err }}{{ $err }}{{ end }}"#);
+        p.parse_tree().unwrap();
+        let tree = &p.tree_set["foo"];
+        assert_eq!(
+            tree.root.as_ref().unwrap().to_string(),
+            r#"{{try}}a{{$x := 1}}{{catch $err}}{{$err}}{{end}}"#
+        );
+        assert_eq!(tree.slots(), 3);
+
+        let mut p = make_parser_with(r#"{{ try }}{{ end }}{{ try }}{{ catch }}b{{ end }}"#);
+        p.parse_tree().unwrap();
+
+        for &(s, err) in &[
+            ("{{ catch }}", "unexpected {{catch}}"),
+            (
+                "{{ try }}{{ else }}{{ end }}",
+                "expected end; found {{else}}",
+            ),
+            (
+                "{{ if 1 }}{{ catch }}{{ end }}",
+                "expected end; found {{catch}}",
+            ),
+            ("{{ try }}{{ catch 1 }}{{ end }}", "unexpected 1 in catch"),
+            ("{{ try }}{{ catch $ }}{{ end }}", "unexpected $ in catch"),
+            (
+                "{{ try }}{{ $x := 1 }}{{ catch }}{{ $x }}{{ end }}",
+                "undefined variable $x",
+            ),
+            (
+                "{{ try }}{{ catch $e }}{{ end }}{{ $e }}",
+                "undefined variable $e",
+            ),
+        ] {
+            let mut p = make_parser_with(s);
+            let e = p.parse_tree().unwrap_err().to_string();
+            assert!(e.ends_with(err), "{}: {}", s, e);
+        }
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut p = make_parser_with(r#"{{ capture $x }}a{{ $y := 1 }}{{ end }}{{ $x }}"#);
+        p.parse_tree().unwrap();
+        let tree = &p.tree_set["foo"];
+        assert_eq!(
+            tree.root.as_ref().unwrap().to_string(),
+            r#"{{capture $x}}a{{$y := 1}}{{end}}{{$x}}"#
+        );
+        assert_eq!(tree.slots(), 3);
+
+        for &(s, err) in &[
+            ("{{ capture }}{{ end }}", "unexpected }} in capture"),
+            ("{{ capture $ }}{{ end }}", "unexpected $ in capture"),
+            (
+                "{{ capture $x }}{{ else }}{{ end }}",
+                "expected end; found {{else}}",
+            ),
+            ("{{ capture $x }}{{ $x }}{{ end }}", "undefined variable $x"),
+            (
+                "{{ capture $x }}{{ $y := 1 }}{{ end }}{{ $y }}",
+                "undefined variable $y",
+            ),
+        ] {
+            let mut p = make_parser_with(s);
+            let e = p.parse_tree().unwrap_err().to_string();
+            assert!(e.ends_with(err), "{}: {}", s, e);
+        }
+    }
+
+    fn collect_var_slots<'a>(node: &'a Nodes, slots: &mut Vec<(&'a str, usize)>) {
+        let pipe = |pipe: &'a PipeNode, slots: &mut Vec<(&'a str, usize)>| {
+            for var in &pipe.decl {
+                slots.push((var.ident[0].as_str(), var.slot));
+            }
+            for cmd in &pipe.cmds {
+                for arg in &cmd.args {
+                    collect_var_slots(arg, slots);
+                }
+            }
+        };
+        match *node {
+            Nodes::List(ref n) => n.nodes.iter().for_each(|n| collect_var_slots(n, slots)),
+            Nodes::Action(ref n) => pipe(&n.pipe, slots),
+            Nodes::If(ref n) | Nodes::With(ref n) | Nodes::Range(ref n) => {
+                pipe(&n.pipe, slots);
+                n.list
+                    .nodes
+                    .iter()
+                    .for_each(|n| collect_var_slots(n, slots));
+            }
+            Nodes::Variable(ref n) => slots.push((n.ident[0].as_str(), n.slot)),
+            _ => {}
+        }
+    }
 }
@@ -5,6 +5,10 @@ use gtmpl_value::{FromValue, Value};
 use crate::error::PrintError;
 use crate::print_verb::print;
 
+// Like Golang, refuse widths and precisions that would pad a single value to
+// megabytes of output.
+const MAX_WIDTH: usize = 1_000_000;
+
 pub fn sprintf(s: &str, args: &[Value]) -> Result<String, PrintError> {
     let tokens = tokenize(s)?;
     let mut fmt = String::new();
@@ -82,13 +86,13 @@ fn process_verb(
                 index += 1;
                 i
             });
-            if let Some(width) = args.get(arg_num).and_then(|v| i64::from_value(v)) {
+            if let Some(width) = args.get(arg_num).and_then(i64::from_value) {
                 if width < 0 {
                     params.minus = true;
                     // Golang does not pad with zeros to the right.
                     params.zero = false;
                 }
-                params.width = width.abs() as usize;
+                params.width = width.unsigned_abs() as usize;
             }
             after_index = false;
         } else if let Some((width, till)) = parse_num(&s[pos..])? {
@@ -118,11 +122,11 @@ fn process_verb(
                     index += 1;
                     i
                 });
-                if let Some(prec) = args.get(arg_num).and_then(|v| i64::from_value(v)) {
+                if let Some(prec) = args.get(arg_num).and_then(i64::from_value) {
                     if prec < 0 {
                         params.precision = None;
                     }
-                    params.precision = Some(prec.abs() as usize);
+                    params.precision = Some(prec.unsigned_abs() as usize);
                 }
             } else if let Some((prec, till)) = parse_num(&s[pos..])? {
                 if after_index {
@@ -143,6 +147,13 @@ fn process_verb(
         i
     };
 
+    if let Some(n) = Some(params.width)
+        .into_iter()
+        .chain(params.precision)
+        .find(|&n| n > MAX_WIDTH)
+    {
+        return Err(PrintError::TooLarge(n));
+    }
     if arg_num < args.len() {
         return print(&params, typ, &args[arg_num]).map(|s| (s, index));
     }
@@ -164,7 +175,7 @@ fn parse_index(s: &str) -> Result<Option<(usize, usize)>, PrintError> {
 }
 
 fn parse_num(s: &str) -> Result<Option<(usize, usize)>, PrintError> {
-    let till = s.find(|c: char| !c.is_digit(10)).unwrap_or_else(|| s.len());
+    let till = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
     if till > 0 {
         s[..till]
             .parse()
@@ -275,6 +286,18 @@ mod test {
         assert_eq!(s, r"foobar");
     }
 
+    #[test]
+    fn test_sprintf_too_large() {
+        let s = sprintf("%1000001d", &[1.into()]);
+        assert_eq!(
+            s.unwrap_err().to_string(),
+            "width or precision 1000001 is too large"
+        );
+        let s = sprintf("%.*f", &[2_000_000.into(), 1.5.into()]);
+        assert!(s.is_err());
+        assert_eq!(sprintf("%5d", &[1.into()]).unwrap(), "    1");
+    }
+
     #[test]
     fn test_sprintf_index() {
         let s = sprintf("%[1]v %v", &["foo".into(), "bar".into(), 2000.into()]);
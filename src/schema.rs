@@ -0,0 +1,1000 @@
+//! Infers the shape of the data a template reads and checks templates against
+//! a declared shape.
+//!
+//! Both analyses follow every field access on the dot, on variables and on
+//! `index` calls with constant keys, into `range`, `with` and `{{template}}`
+//! calls with their dot. Inferred ranges are arrays.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use go_template::schema::{Schema, Type};
+//! use go_template::Template;
+//!
+//! let mut tmpl = Template::default();
+//! tmpl.parse(r#"{{ .title }}{{ range .items }}{{ index . "name" }}{{ end }}"#)
+//!     .unwrap();
+//! let schema = tmpl.infer_schema();
+//! assert_eq!(
+//!     schema.properties["items"].items.as_ref().unwrap().properties["name"],
+//!     Schema::default()
+//! );
+//! assert!(schema.to_json().contains(r#""title": {}"#));
+//!
+//! let declared = Schema::new(Type::Object)
+//!     .with_property("title", Schema::new(Type::String))
+//!     .with_property("items", Schema::new(Type::Integer));
+//! let findings = tmpl.check_schema(&declared);
+//! assert_eq!(findings[0].message, "range over integer");
+//! ```
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::mem;
+
+#[cfg(feature = "json")]
+use crate::error::SchemaError;
+use crate::lint::{self, Finding, FindingKind};
+use crate::node::*;
+use crate::parse::Tree;
+use crate::template::Template;
+
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// A JSON type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Type {
+    Null,
+    Boolean,
+    Integer,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl Type {
+    /// The name of the type in JSON Schema, e.g. `boolean`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::Null => "null",
+            Type::Boolean => "boolean",
+            Type::Integer => "integer",
+            Type::Number => "number",
+            Type::String => "string",
+            Type::Array => "array",
+            Type::Object => "object",
+        }
+    }
+
+    #[cfg(feature = "json")]
+    fn from_name(name: &str) -> Option<Type> {
+        [
+            Type::Null,
+            Type::Boolean,
+            Type::Integer,
+            Type::Number,
+            Type::String,
+            Type::Array,
+            Type::Object,
+        ]
+        .iter()
+        .copied()
+        .find(|t| t.name() == name)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The subset of JSON Schema describing the shape of template data.
+///
+/// Unlike in JSON Schema, an object with `properties` has no other
+/// properties unless `additional_properties` is set. `Schema::never()` is the
+/// `false` schema, it allows no value.
+///
+/// ## Example
+///
+/// ```rust
+/// use go_template::schema::{Schema, Type};
+///
+/// let user = Schema::new(Type::Object)
+///     .with_property("name", Schema::new(Type::String))
+///     .with_property("age", Schema::new(Type::Integer).with_type(Type::Null));
+/// let users = Schema::new(Type::Array).with_items(user);
+/// assert!(users.to_json().contains(r#""type": ["null", "integer"]"#));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Schema {
+    /// Allowed types, empty if any type is allowed.
+    pub types: BTreeSet<Type>,
+    /// Known properties of objects.
+    pub properties: BTreeMap<String, Schema>,
+    /// Schema of other properties of objects.
+    pub additional_properties: Option<Box<Schema>>,
+    /// Schema of array items.
+    pub items: Option<Box<Schema>>,
+    /// Allows no value at all, the other fields are ignored.
+    pub never: bool,
+}
+
+impl Schema {
+    /// A schema for values of type `typ`.
+    pub fn new(typ: Type) -> Schema {
+        Schema::default().with_type(typ)
+    }
+
+    /// A schema that allows no value, `false` in JSON Schema.
+    pub fn never() -> Schema {
+        Schema {
+            never: true,
+            ..Schema::default()
+        }
+    }
+
+    /// Allows values of type `typ` as well.
+    pub fn with_type(mut self, typ: Type) -> Schema {
+        self.types.insert(typ);
+        self
+    }
+
+    pub fn with_property<N: Into<String>>(mut self, name: N, schema: Schema) -> Schema {
+        self.properties.insert(name.into(), schema);
+        self
+    }
+
+    pub fn with_additional_properties(mut self, schema: Schema) -> Schema {
+        self.additional_properties = Some(Box::new(schema));
+        self
+    }
+
+    pub fn with_items(mut self, schema: Schema) -> Schema {
+        self.items = Some(Box::new(schema));
+        self
+    }
+
+    /// Reads a schema from a JSON Schema document. Supported keywords are
+    /// `type`, `properties`, `additionalProperties` and `items`, others are
+    /// ignored, which only makes checks less strict.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use go_template::schema::{Schema, Type};
+    ///
+    /// let schema = Schema::from_json_str(r#"{"type": "object", "properties": {"a": true}}"#)
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     schema,
+    ///     Schema::new(Type::Object).with_property("a", Schema::default())
+    /// );
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn from_json_str(s: &str) -> Result<Schema, SchemaError> {
+        let json: serde_json::Value = serde_json::from_str(s)?;
+        Schema::from_json(&json, "")
+    }
+
+    #[cfg(feature = "json")]
+    fn from_json(json: &serde_json::Value, at: &str) -> Result<Schema, SchemaError> {
+        use serde_json::Value as Json;
+
+        let invalid = |at: &str, msg: &str| SchemaError::Invalid(format!("#{}", at), msg.into());
+        let json = match *json {
+            Json::Bool(true) => return Ok(Schema::default()),
+            Json::Bool(false) => return Ok(Schema::never()),
+            Json::Object(ref o) => o,
+            _ => return Err(invalid(at, "expected an object or a boolean")),
+        };
+        let mut schema = Schema::default();
+        let names = match json.get("type") {
+            None => vec![],
+            Some(Json::String(name)) => vec![name.as_str()],
+            Some(Json::Array(names)) => names.iter().filter_map(Json::as_str).collect(),
+            Some(_) => return Err(invalid(at, "type must be a string or an array")),
+        };
+        for name in names {
+            let typ = Type::from_name(name)
+                .ok_or_else(|| invalid(at, &format!("unknown type {}", name)))?;
+            schema.types.insert(typ);
+        }
+        match json.get("properties") {
+            None => {}
+            Some(Json::Object(props)) => {
+                for (name, prop) in props {
+                    let at = format!("{}/properties/{}", at, name);
+                    schema
+                        .properties
+                        .insert(name.clone(), Schema::from_json(prop, &at)?);
+                }
+            }
+            Some(_) => return Err(invalid(at, "properties must be an object")),
+        }
+        if let Some(additional) = json.get("additionalProperties") {
+            let at = format!("{}/additionalProperties", at);
+            schema.additional_properties = Some(Box::new(Schema::from_json(additional, &at)?));
+        }
+        if let Some(items) = json.get("items") {
+            let at = format!("{}/items", at);
+            schema.items = Some(Box::new(Schema::from_json(items, &at)?));
+        }
+        Ok(schema)
+    }
+
+    /// The schema at `path`, created as objects and arrays on the way.
+    fn at(&mut self, path: &[Seg]) -> &mut Schema {
+        let mut schema = self;
+        for seg in path {
+            schema = match *seg {
+                Seg::Field(ref name) => {
+                    schema.types.insert(Type::Object);
+                    schema.properties.entry(name.clone()).or_default()
+                }
+                Seg::Items => {
+                    schema.types.insert(Type::Array);
+                    schema.items.get_or_insert_with(Box::default)
+                }
+            };
+        }
+        schema
+    }
+
+    fn allows(&self, typ: Type) -> bool {
+        !self.never && (self.types.is_empty() || self.types.contains(&typ))
+    }
+
+    fn describe(&self) -> String {
+        if self.never {
+            return "no value".into();
+        }
+        let types: Vec<&str> = self.types.iter().map(|t| t.name()).collect();
+        types.join(" or ")
+    }
+
+    /// Formats the schema as pretty printed JSON Schema document with sorted
+    /// keys, so it can be diffed.
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.write_json(&mut out, 0, true);
+        out.push('\n');
+        out
+    }
+
+    fn write_json(&self, out: &mut String, indent: usize, root: bool) {
+        if self.never {
+            out.push_str("false");
+            return;
+        }
+        let mut entries = vec![];
+        if root {
+            entries.push(("$schema", quote(DIALECT)));
+        }
+        let mut types: Vec<String> = self.types.iter().map(|t| quote(t.name())).collect();
+        match types.len() {
+            0 => {}
+            1 => entries.push(("type", types.remove(0))),
+            _ => entries.push(("type", format!("[{}]", types.join(", ")))),
+        }
+        if !self.properties.is_empty() {
+            let mut props = String::from("{\n");
+            for (i, (name, schema)) in self.properties.iter().enumerate() {
+                props += &"  ".repeat(indent + 2);
+                props += &quote(name);
+                props += ": ";
+                schema.write_json(&mut props, indent + 2, false);
+                props += if i + 1 < self.properties.len() {
+                    ",\n"
+                } else {
+                    "\n"
+                };
+            }
+            props += &"  ".repeat(indent + 1);
+            props.push('}');
+            entries.push(("properties", props));
+        }
+        if let Some(ref additional) = self.additional_properties {
+            let mut json = String::new();
+            additional.write_json(&mut json, indent + 1, false);
+            entries.push(("additionalProperties", json));
+        }
+        if let Some(ref items) = self.items {
+            let mut json = String::new();
+            items.write_json(&mut json, indent + 1, false);
+            entries.push(("items", json));
+        }
+        if entries.is_empty() {
+            out.push_str("{}");
+            return;
+        }
+        out.push_str("{\n");
+        for (i, (key, value)) in entries.iter().enumerate() {
+            out.push_str(&"  ".repeat(indent + 1));
+            out.push_str(&format!("{}: {}", quote(key), value));
+            out.push_str(if i + 1 < entries.len() { ",\n" } else { "\n" });
+        }
+        out.push_str(&"  ".repeat(indent));
+        out.push('}');
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl Template {
+    /// Infers the schema of the data this template reads when rendered. See
+    /// `schema::infer`.
+    pub fn infer_schema(&self) -> Schema {
+        infer(self, &self.name)
+    }
+
+    /// Checks this template against the `schema` of its data. See
+    /// `schema::check`.
+    pub fn check_schema(&self, schema: &Schema) -> Vec<Finding> {
+        check(self, &self.name, schema)
+    }
+}
+
+/// Infers the schema of the dot of the template `name` in `tmpl`. Calls of
+/// undefined templates are skipped and recursive calls are followed once.
+pub fn infer(tmpl: &Template, name: &str) -> Schema {
+    let mut walker = Walker::new(tmpl, Inference::default());
+    walker.call(name, Some(vec![]));
+    walker.analysis.schema
+}
+
+/// Checks the template `name` in `tmpl` against the `schema` of its dot.
+/// Reports fields that are not in the schema, fields of values that are no
+/// objects, ranges over values that are neither arrays nor objects and
+/// comparisons of incompatible types. Values computed by functions other
+/// than the builtins are not checked.
+pub fn check(tmpl: &Template, name: &str, schema: &Schema) -> Vec<Finding> {
+    let mut walker = Walker::new(tmpl, Check::default());
+    walker.call(name, Some(schema.clone()));
+    let mut findings = walker.analysis.findings;
+    // Templates called more than once report the same findings.
+    lint::sort(&mut findings);
+    findings.dedup();
+    findings
+}
+
+// Where a value is used.
+#[derive(Clone, Copy)]
+struct Site<'a> {
+    tree: &'a Tree,
+    pos: Pos,
+}
+
+// An abstract interpretation of templates. `None` stands for unknown values.
+trait Analysis {
+    type Value: Clone;
+
+    // Reads field `name` of `value`, for `.name` or `index value "name"`.
+    fn field(&mut self, at: Site, value: Self::Value, name: &str) -> Option<Self::Value>;
+
+    // An element of `value`, for `range` or `index value 0`.
+    fn element(&mut self, at: Site, value: Self::Value, range: bool) -> Option<Self::Value>;
+
+    fn literal(&mut self, _node: &Nodes) -> Option<Self::Value> {
+        None
+    }
+
+    // Calls function `name`, the value of a pipeline is the last argument.
+    fn call(
+        &mut self,
+        _at: Site,
+        _name: &str,
+        _args: &[Option<Self::Value>],
+    ) -> Option<Self::Value> {
+        None
+    }
+}
+
+struct Walker<'a, A: Analysis> {
+    tmpl: &'a Template,
+    analysis: A,
+    tree: Option<&'a Tree>,
+    // Templates being analysed.
+    stack: Vec<&'a str>,
+    scopes: Vec<Vec<(String, Option<A::Value>)>>,
+}
+
+impl<'a, A: Analysis> Walker<'a, A> {
+    fn new(tmpl: &'a Template, analysis: A) -> Self {
+        Walker {
+            tmpl,
+            analysis,
+            tree: None,
+            stack: vec![],
+            scopes: vec![],
+        }
+    }
+
+    fn call(&mut self, name: &'a str, dot: Option<A::Value>) {
+        if self.stack.contains(&name) {
+            return;
+        }
+        let tree = match self.tmpl.tree_set.get(name) {
+            Some(tree) => tree,
+            None => return,
+        };
+        let root = match tree.root {
+            Some(ref root) => root,
+            None => return,
+        };
+        self.stack.push(name);
+        let outer = self.tree.replace(tree);
+        let scopes = mem::replace(&mut self.scopes, vec![vec![("$".to_owned(), dot.clone())]]);
+        self.walk(root, &dot);
+        self.scopes = scopes;
+        self.tree = outer;
+        self.stack.pop();
+    }
+
+    fn site(&self, pos: Pos) -> Option<Site<'a>> {
+        self.tree.map(|tree| Site { tree, pos })
+    }
+
+    fn walk(&mut self, node: &'a Nodes, dot: &Option<A::Value>) {
+        match *node {
+            Nodes::List(ref n) => self.walk_list(n, dot),
+            Nodes::Action(ref n) => {
+                self.eval_pipe(&n.pipe, dot);
+            }
+            Nodes::If(ref n) => {
+                self.scopes.push(vec![]);
+                self.eval_pipe(&n.pipe, dot);
+                self.walk_scoped(&n.list, dot);
+                if let Some(ref else_list) = n.else_list {
+                    self.walk_scoped(else_list, dot);
+                }
+                self.scopes.pop();
+            }
+            Nodes::With(ref n) => {
+                self.scopes.push(vec![]);
+                let value = self.eval_pipe(&n.pipe, dot);
+                self.walk_scoped(&n.list, &value);
+                if let Some(ref else_list) = n.else_list {
+                    self.walk_scoped(else_list, dot);
+                }
+                self.scopes.pop();
+            }
+            Nodes::Range(ref n) => self.walk_range(n, dot),
+            Nodes::Capture(ref n) => {
+                self.walk_scoped(&n.list, dot);
+                self.declare(&n.var.ident[0], None);
+            }
+            Nodes::Try(ref n) => {
+                self.walk_scoped(&n.list, dot);
+                if let Some(ref catch_list) = n.catch_list {
+                    self.scopes.push(vec![]);
+                    if let Some(var) = n.catch.as_ref().and_then(|c| c.var.as_ref()) {
+                        self.declare(&var.ident[0], None);
+                    }
+                    self.walk_scoped(catch_list, dot);
+                    self.scopes.pop();
+                }
+            }
+            Nodes::Template(ref n) => {
+                let value = match n.pipe {
+                    Some(ref pipe) => self.eval_pipe(pipe, dot),
+                    None => None,
+                };
+                match n.name {
+                    PipeOrString::String(ref name) => self.call(name, value),
+                    PipeOrString::Pipe(ref pipe) => {
+                        self.eval_pipe(pipe, dot);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn walk_list(&mut self, list: &'a ListNode, dot: &Option<A::Value>) {
+        for node in &list.nodes {
+            self.walk(node, dot);
+        }
+    }
+
+    fn walk_scoped(&mut self, list: &'a ListNode, dot: &Option<A::Value>) {
+        self.scopes.push(vec![]);
+        self.walk_list(list, dot);
+        self.scopes.pop();
+    }
+
+    fn walk_range(&mut self, range: &'a RangeNode, dot: &Option<A::Value>) {
+        self.scopes.push(vec![]);
+        let value = self.eval_cmds(&range.pipe, dot);
+        let elem = match (value, self.site(range.pipe.pos())) {
+            (Some(value), Some(at)) => self.analysis.element(at, value, true),
+            _ => None,
+        };
+        // The last variable holds the element, the one before the index.
+        let mut decl = range.pipe.decl.iter().rev();
+        if let Some(var) = decl.next() {
+            self.declare(&var.ident[0], elem.clone());
+        }
+        if let Some(var) = decl.next() {
+            self.declare(&var.ident[0], None);
+        }
+        self.walk_scoped(&range.list, &elem);
+        if let Some(ref else_list) = range.else_list {
+            self.walk_scoped(else_list, dot);
+        }
+        self.scopes.pop();
+    }
+
+    fn eval_pipe(&mut self, pipe: &'a PipeNode, dot: &Option<A::Value>) -> Option<A::Value> {
+        let value = self.eval_cmds(pipe, dot);
+        for var in &pipe.decl {
+            let name = &var.ident[0];
+            if pipe.is_assign {
+                if let Some(v) = self.lookup(name) {
+                    *v = value.clone();
+                }
+            } else {
+                self.declare(name, value.clone());
+            }
+        }
+        value
+    }
+
+    fn eval_cmds(&mut self, pipe: &'a PipeNode, dot: &Option<A::Value>) -> Option<A::Value> {
+        let mut value = None;
+        for (i, cmd) in pipe.cmds.iter().enumerate() {
+            // Only commands after the first get the value piped in.
+            let fin = if i == 0 { None } else { Some(value) };
+            value = self.eval_cmd(cmd, dot, fin);
+        }
+        value
+    }
+
+    fn eval_cmd(
+        &mut self,
+        cmd: &'a CommandNode,
+        dot: &Option<A::Value>,
+        fin: Option<Option<A::Value>>,
+    ) -> Option<A::Value> {
+        let func = match cmd.args.first() {
+            Some(Nodes::Identifier(ref f)) => &f.ident,
+            Some(arg) => return self.eval_arg(arg, dot),
+            None => return None,
+        };
+        let mut nodes: Vec<Option<&Nodes>> = cmd.args[1..].iter().map(Some).collect();
+        let mut args: Vec<Option<A::Value>> = cmd.args[1..]
+            .iter()
+            .map(|a| self.eval_arg(a, dot))
+            .collect();
+        if let Some(fin) = fin {
+            nodes.push(None);
+            args.push(fin);
+        }
+        let at = self.site(cmd.pos())?;
+        if func == "index" && !args.is_empty() {
+            return self.index(at, &nodes[1..], args.into_iter().next().flatten());
+        }
+        self.analysis.call(at, func, &args)
+    }
+
+    // `index` with constant keys reads fields and elements.
+    fn index(
+        &mut self,
+        at: Site,
+        keys: &[Option<&Nodes>],
+        value: Option<A::Value>,
+    ) -> Option<A::Value> {
+        let mut value = value;
+        for key in keys {
+            let v = value?;
+            value = match *key {
+                Some(Nodes::String(ref s)) => match s.value {
+                    gtmpl_value::Value::String(ref s) => self.analysis.field(at, v, s),
+                    _ => None,
+                },
+                Some(Nodes::Number(ref n)) if n.is_i64 || n.is_u64 => {
+                    self.analysis.element(at, v, false)
+                }
+                _ => None,
+            };
+        }
+        value
+    }
+
+    fn eval_arg(&mut self, arg: &'a Nodes, dot: &Option<A::Value>) -> Option<A::Value> {
+        match *arg {
+            Nodes::Dot(_) => dot.clone(),
+            Nodes::Field(ref n) => self.fields(n.pos(), dot.clone(), &n.ident),
+            Nodes::Variable(ref n) => {
+                let value = self.lookup(&n.ident[0]).and_then(|v| v.clone());
+                self.fields(n.pos(), value, &n.ident[1..])
+            }
+            Nodes::Chain(ref n) => {
+                let value = self.eval_arg(&n.node, dot);
+                self.fields(n.pos(), value, &n.field)
+            }
+            Nodes::Pipe(ref n) => self.eval_pipe(n, dot),
+            _ => self.analysis.literal(arg),
+        }
+    }
+
+    fn fields(&mut self, pos: Pos, value: Option<A::Value>, fields: &[String]) -> Option<A::Value> {
+        let at = self.site(pos)?;
+        let mut value = value;
+        for field in fields {
+            value = self.analysis.field(at, value?, field);
+        }
+        value
+    }
+
+    fn declare(&mut self, name: &str, value: Option<A::Value>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name.to_owned(), value));
+        }
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Option<A::Value>> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|s| s.iter_mut().rev())
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+}
+
+// A step from the dot to a value.
+#[derive(Clone, Debug)]
+enum Seg {
+    Field(String),
+    Items,
+}
+
+// Records the paths read from the dot.
+#[derive(Default)]
+struct Inference {
+    schema: Schema,
+}
+
+impl Analysis for Inference {
+    type Value = Vec<Seg>;
+
+    fn field(&mut self, _: Site, mut path: Vec<Seg>, name: &str) -> Option<Vec<Seg>> {
+        path.push(Seg::Field(name.to_owned()));
+        self.schema.at(&path);
+        Some(path)
+    }
+
+    fn element(&mut self, _: Site, mut path: Vec<Seg>, _: bool) -> Option<Vec<Seg>> {
+        path.push(Seg::Items);
+        self.schema.at(&path);
+        Some(path)
+    }
+}
+
+// Tracks the schema of values and reports invalid uses.
+#[derive(Default)]
+struct Check {
+    findings: Vec<Finding>,
+}
+
+impl Check {
+    fn report(&mut self, at: Site, kind: FindingKind, message: String) {
+        self.findings
+            .push(Finding::new(at.tree, at.pos, kind, message));
+    }
+}
+
+impl Analysis for Check {
+    type Value = Schema;
+
+    fn field(&mut self, at: Site, schema: Schema, name: &str) -> Option<Schema> {
+        if !schema.allows(Type::Object) {
+            let message = format!("can't read field {} of {}", name, schema.describe());
+            self.report(at, FindingKind::TypeMismatch, message);
+            return None;
+        }
+        let prop = match schema.properties.get(name) {
+            Some(prop) => Some(prop.clone()),
+            None => schema.additional_properties.map(|a| *a),
+        };
+        match prop {
+            Some(prop) if !prop.never => Some(prop),
+            None if schema.properties.is_empty() => None,
+            _ => {
+                let message = format!("field {} is not in the schema", name);
+                self.report(at, FindingKind::UnknownField, message);
+                None
+            }
+        }
+    }
+
+    fn element(&mut self, at: Site, schema: Schema, range: bool) -> Option<Schema> {
+        let object = schema.allows(Type::Object);
+        let array = schema.allows(Type::Array);
+        if range && !schema.allows(Type::Array) && !object {
+            let message = format!("range over {}", schema.describe());
+            self.report(at, FindingKind::NotIterable, message);
+            return None;
+        }
+        if !range && !schema.allows(Type::Array) && !object {
+            let message = format!("can't index {} with a number", schema.describe());
+            self.report(at, FindingKind::TypeMismatch, message);
+            return None;
+        }
+        // An element of `false` items can't exist, there is nothing to check.
+        match (schema.items, schema.additional_properties) {
+            (Some(items), _) if array => Some(*items),
+            (_, Some(additional)) if object => Some(*additional),
+            _ => None,
+        }
+        .filter(|element| !element.never)
+    }
+
+    fn literal(&mut self, node: &Nodes) -> Option<Schema> {
+        let typ = match *node {
+            Nodes::Bool(_) => Type::Boolean,
+            Nodes::String(_) => Type::String,
+            Nodes::Number(ref n) if n.is_i64 || n.is_u64 => Type::Integer,
+            Nodes::Number(_) => Type::Number,
+            Nodes::Nil(_) => Type::Null,
+            _ => return None,
+        };
+        Some(Schema::new(typ))
+    }
+
+    fn call(&mut self, at: Site, name: &str, args: &[Option<Schema>]) -> Option<Schema> {
+        let ordered = match name {
+            "eq" | "ne" => false,
+            "lt" | "le" | "gt" | "ge" => true,
+            "len" => return Some(Schema::new(Type::Integer)),
+            "not" => return Some(Schema::new(Type::Boolean)),
+            "print" | "printf" | "println" | "urlquery" => return Some(Schema::new(Type::String)),
+            _ => return None,
+        };
+        let known: Vec<&Schema> = args
+            .iter()
+            .flatten()
+            .filter(|s| !s.types.is_empty())
+            .collect();
+        if ordered {
+            for schema in &known {
+                if ![Type::Integer, Type::Number, Type::String]
+                    .iter()
+                    .any(|t| schema.types.contains(t))
+                {
+                    let message = format!("{} can't order {}", name, schema.describe());
+                    self.report(at, FindingKind::TypeMismatch, message);
+                }
+            }
+        }
+        if let Some(Some(first)) = args.first() {
+            for other in args[1..].iter().flatten() {
+                if !first.types.is_empty() && !other.types.is_empty() && !comparable(first, other) {
+                    let message = format!(
+                        "{} compares {} with {}",
+                        name,
+                        first.describe(),
+                        other.describe()
+                    );
+                    self.report(at, FindingKind::TypeMismatch, message);
+                }
+            }
+        }
+        Some(Schema::new(Type::Boolean))
+    }
+}
+
+fn comparable(a: &Schema, b: &Schema) -> bool {
+    let numeric = |s: &Schema| s.types.contains(&Type::Integer) || s.types.contains(&Type::Number);
+    !a.types.is_disjoint(&b.types) || (numeric(a) && numeric(b))
+}
+
+#[cfg(test)]
+mod tests_mocked {
+    use super::*;
+
+    fn infer_str(text: &str) -> String {
+        let mut tmpl = Template::with_name("t");
+        tmpl.skip_func_check = true;
+        tmpl.parse(text).unwrap();
+        tmpl.infer_schema().to_json()
+    }
+
+    #[test]
+    fn test_infer() {
+        let json = infer_str(
+            r#"{{ define "user" }}{{ .name }}{{ template "user" .boss }}{{ end }}
+{{- $c := .config -}}
+{{ range $i, $u := .users }}{{ template "user" $u }}{{ $c.debug }}{{ end }}
+{{- with .page }}{{ index . "title" 0 | upper }}{{ (.meta).lang }}{{ end }}
+{{- if eq .mode "x" }}{{ $.footer }}{{ end }}"#,
+        );
+        assert_eq!(
+            json,
+            r#"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "type": "object",
+  "properties": {
+    "config": {
+      "type": "object",
+      "properties": {
+        "debug": {}
+      }
+    },
+    "footer": {},
+    "mode": {},
+    "page": {
+      "type": "object",
+      "properties": {
+        "meta": {
+          "type": "object",
+          "properties": {
+            "lang": {}
+          }
+        },
+        "title": {
+          "type": "array",
+          "items": {}
+        }
+      }
+    },
+    "users": {
+      "type": "array",
+      "items": {
+        "type": "object",
+        "properties": {
+          "boss": {},
+          "name": {}
+        }
+      }
+    }
+  }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_infer_empty() {
+        assert_eq!(
+            infer_str("{{ . }}{{ len (print .) }}{{ $x := 1 }}{{ $x.y }}"),
+            "{\n  \"$schema\": \"https://json-schema.org/draft/2020-12/schema\"\n}\n"
+        );
+    }
+
+    fn check_str(text: &str, schema: &Schema) -> Vec<String> {
+        let mut tmpl = Template::with_name("t");
+        tmpl.skip_func_check = true;
+        tmpl.parse(text).unwrap();
+        tmpl.check_schema(schema)
+            .iter()
+            .map(|f| f.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let user = Schema::new(Type::Object)
+            .with_property("name", Schema::new(Type::String))
+            .with_property("age", Schema::new(Type::Integer));
+        let schema = Schema::new(Type::Object)
+            .with_property("users", Schema::new(Type::Array).with_items(user))
+            .with_property("count", Schema::new(Type::Number))
+            .with_property(
+                "tags",
+                Schema::new(Type::Object).with_additional_properties(Schema::new(Type::String)),
+            )
+            .with_property("free", Schema::default());
+        let findings = check_str(
+            r#"{{ define "user" }}{{ .name }}{{ .email }}{{ if lt .age 18 }}{{ end }}{{ end }}
+{{- range .users }}{{ template "user" . }}{{ end }}{{ range .count }}{{ end }}
+{{ $t := .tags }}{{ range $t }}{{ .x }}{{ end }}{{ if eq $t.a 1 }}{{ end }}{{ .count.x }}
+{{ if eq .count 1 }}{{ end }}{{ .free.x.y }}{{ index .users 0 "name" "z" }}{{ .missing }}"#,
+            &schema,
+        );
+        assert_eq!(
+            findings,
+            vec![
+                "t:2:61: range over number [not-iterable]",
+                "t:3:35: can't read field x of string [type-mismatch]",
+                "t:3:55: eq compares string with integer [type-mismatch]",
+                "t:3:85: can't read field x of number [type-mismatch]",
+                "t:4:48: can't read field z of string [type-mismatch]",
+                "t:4:79: field missing is not in the schema [unknown-field]",
+                "user:1:34: field email is not in the schema [unknown-field]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_never() {
+        let schema = Schema::new(Type::Object)
+            .with_additional_properties(Schema::never())
+            .with_property(
+                "obj",
+                Schema::new(Type::Object).with_additional_properties(Schema::never()),
+            )
+            .with_property("gone", Schema::never())
+            .with_property("list", Schema::new(Type::Array).with_items(Schema::never()));
+        let findings = check_str(
+            "{{ .obj.a }}{{ .gone }}{{ range .list }}{{ .x }}{{ end }}{{ .other }}",
+            &schema,
+        );
+        assert_eq!(
+            findings,
+            vec![
+                "t:1:8: field a is not in the schema [unknown-field]",
+                "t:1:16: field gone is not in the schema [unknown-field]",
+                "t:1:61: field other is not in the schema [unknown-field]",
+            ]
+        );
+        assert_eq!(
+            check_str("{{ .a }}", &Schema::never()),
+            vec!["t:1:4: can't read field a of no value [type-mismatch]"]
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_from_json() {
+        let schema = Schema::from_json_str(
+            r#"{
+                "type": "object",
+                "properties": {
+                    "a": {"type": ["array", "null"], "items": {"type": "string"}},
+                    "b": {"additionalProperties": true, "description": "ignored"}
+                }
+            }"#,
+        )
+        .unwrap();
+        let expected = Schema::new(Type::Object)
+            .with_property(
+                "a",
+                Schema::new(Type::Array)
+                    .with_type(Type::Null)
+                    .with_items(Schema::new(Type::String)),
+            )
+            .with_property(
+                "b",
+                Schema::default().with_additional_properties(Schema::default()),
+            );
+        assert_eq!(schema, expected);
+        assert_eq!(Schema::from_json_str(&schema.to_json()).unwrap(), schema);
+
+        let schema = Schema::from_json_str(
+            r#"{
+                "additionalProperties": false,
+                "properties": {"a": false, "b": {"items": false}}
+            }"#,
+        )
+        .unwrap();
+        let expected = Schema::default()
+            .with_additional_properties(Schema::never())
+            .with_property("a", Schema::never())
+            .with_property("b", Schema::default().with_items(Schema::never()));
+        assert_eq!(schema, expected);
+        assert!(schema.to_json().contains(r#""a": false"#));
+        assert_eq!(Schema::from_json_str(&schema.to_json()).unwrap(), schema);
+
+        let err = Schema::from_json_str(r#"{"properties": {"a": {"type": "text"}}}"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid schema at #/properties/a: unknown type text"
+        );
+    }
+}
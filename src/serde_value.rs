@@ -0,0 +1,705 @@
+//! Conversion between `Value` and serde data types.
+//!
+//! Structs become objects, maps become maps, `None` and `()` become nil and
+//! byte sequences become arrays of numbers. Map keys have to serialize to a
+//! string, number or bool.
+use std::collections::HashMap;
+use std::fmt;
+
+use ::serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use ::serde::forward_to_deserialize_any;
+use ::serde::ser::{self, Serialize};
+use gtmpl_value::{FuncError, Value};
+
+use crate::error::SerdeError;
+
+/// Converts any `T: Serialize` into a `Value`.
+///
+/// ## Example
+///
+/// ```rust
+/// use go_template::serde_value::to_value;
+/// use go_template::Value;
+///
+/// let value = to_value(&vec![Some(1), None]).unwrap();
+/// assert_eq!(value, Value::Array(vec![Value::from(1), Value::Nil]));
+/// ```
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, SerdeError> {
+    value.serialize(Serializer)
+}
+
+/// Converts a `Value` into any `T: Deserialize`. Useful to read the arguments
+/// of a template function.
+///
+/// ## Example
+///
+/// ```rust
+/// use go_template::serde_value::from_value;
+/// use go_template::{Context, FuncError, Template, Value};
+///
+/// fn repeat(args: &[Value]) -> Result<Value, FuncError> {
+///     let (s, n): (String, usize) = from_value(&Value::Array(args.to_vec()))?;
+///     Ok(Value::from(s.repeat(n)))
+/// }
+///
+/// let mut tmpl = Template::default();
+/// tmpl.add_func("repeat", repeat);
+/// tmpl.parse(r#"{{ repeat "ab" 3 }}"#).unwrap();
+/// assert_eq!(&tmpl.render(&Context::empty()).unwrap(), "ababab");
+/// ```
+pub fn from_value<T: DeserializeOwned>(value: &Value) -> Result<T, SerdeError> {
+    T::deserialize(Deserializer(value))
+}
+
+impl ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError::Custom(msg.to_string())
+    }
+}
+
+impl de::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        SerdeError::Custom(msg.to_string())
+    }
+}
+
+impl From<SerdeError> for FuncError {
+    fn from(err: SerdeError) -> Self {
+        FuncError::Other(err.into())
+    }
+}
+
+struct Serializer;
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeVariant<SerializeVec>;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeStruct;
+    type SerializeStructVariant = SerializeVariant<SerializeStruct>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, SerdeError> {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, SerdeError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, SerdeError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, SerdeError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, SerdeError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, SerdeError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, SerdeError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, SerdeError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, SerdeError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, SerdeError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, SerdeError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, SerdeError> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, SerdeError> {
+        Ok(Value::String(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, SerdeError> {
+        Ok(Value::Array(v.iter().map(|&b| Value::from(b)).collect()))
+    }
+
+    fn serialize_none(self) -> Result<Value, SerdeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, SerdeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, SerdeError> {
+        Ok(Value::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value, SerdeError> {
+        Ok(Value::String(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, SerdeError> {
+        let mut o = HashMap::new();
+        o.insert(variant.to_owned(), value.serialize(self)?);
+        Ok(Value::Object(o))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeVec, SerdeError> {
+        Ok(SerializeVec(Vec::with_capacity(len.unwrap_or(0))))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeVec, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeVec, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeVariant<SerializeVec>, SerdeError> {
+        Ok(SerializeVariant(variant, self.serialize_seq(Some(len))?))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeMap, SerdeError> {
+        Ok(SerializeMap(HashMap::new(), None))
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<SerializeStruct, SerdeError> {
+        Ok(SerializeStruct(HashMap::new()))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<SerializeVariant<SerializeStruct>, SerdeError> {
+        Ok(SerializeVariant(variant, SerializeStruct(HashMap::new())))
+    }
+}
+
+struct SerializeVec(Vec<Value>);
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.0.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        Ok(Value::Array(self.0))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct SerializeMap(HashMap<String, Value>, Option<String>);
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        let key = match to_value(key)? {
+            Value::String(s) => s,
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            other => return Err(SerdeError::InvalidKey(other)),
+        };
+        self.1 = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let key = self
+            .1
+            .take()
+            .ok_or_else(|| SerdeError::Custom("map value without key".into()))?;
+        self.0.insert(key, to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        Ok(Value::Map(self.0))
+    }
+}
+
+struct SerializeStruct(HashMap<String, Value>);
+
+impl ser::SerializeStruct for SerializeStruct {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        self.0.insert(key.to_owned(), to_value(value)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        Ok(Value::Object(self.0))
+    }
+}
+
+// Wraps the content of an enum variant into a single entry object like
+// `{"Variant": content}`.
+struct SerializeVariant<S>(&'static str, S);
+
+impl<S> SerializeVariant<S> {
+    fn wrap(variant: &str, value: Value) -> Value {
+        let mut o = HashMap::new();
+        o.insert(variant.to_owned(), value);
+        Value::Object(o)
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeVariant<SerializeVec> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        ser::SerializeSeq::serialize_element(&mut self.1, value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        Ok(Self::wrap(self.0, ser::SerializeSeq::end(self.1)?))
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeVariant<SerializeStruct> {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        ser::SerializeStruct::serialize_field(&mut self.1, key, value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        Ok(Self::wrap(self.0, ser::SerializeStruct::end(self.1)?))
+    }
+}
+
+struct Deserializer<'de>(&'de Value);
+
+impl<'de> IntoDeserializer<'de, SerdeError> for Deserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match *self.0 {
+            Value::NoValue | Value::Nil => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::String(ref s) => visitor.visit_borrowed_str(s),
+            Value::Number(ref n) => {
+                if let Some(u) = n.as_u64() {
+                    visitor.visit_u64(u)
+                } else if let Some(i) = n.as_i64() {
+                    visitor.visit_i64(i)
+                } else if let Some(f) = n.as_f64() {
+                    visitor.visit_f64(f)
+                } else {
+                    Err(SerdeError::Custom(format!("invalid number {}", n)))
+                }
+            }
+            Value::Array(ref a) => visitor.visit_seq(Seq(a.iter())),
+            Value::Object(ref o) | Value::Map(ref o) => visitor.visit_map(Map(o.iter(), None)),
+            Value::Function(_) => Err(SerdeError::Function),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match *self.0 {
+            Value::NoValue | Value::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        match *self.0 {
+            Value::String(ref s) => visitor.visit_enum(Enum(s, None)),
+            Value::Object(ref o) | Value::Map(ref o) if o.len() == 1 => {
+                let (k, v) = o.iter().next().unwrap();
+                visitor.visit_enum(Enum(k, Some(v)))
+            }
+            ref other => Err(SerdeError::Custom(format!(
+                "expected an enum, got {}",
+                other
+            ))),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct Seq<'de>(std::slice::Iter<'de, Value>);
+
+impl<'de> SeqAccess<'de> for Seq<'de> {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, SerdeError> {
+        self.0
+            .next()
+            .map(|v| seed.deserialize(Deserializer(v)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct Map<'de>(
+    std::collections::hash_map::Iter<'de, String, Value>,
+    Option<&'de Value>,
+);
+
+impl<'de> MapAccess<'de> for Map<'de> {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, SerdeError> {
+        match self.0.next() {
+            Some((k, v)) => {
+                self.1 = Some(v);
+                seed.deserialize(Key(k)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, SerdeError> {
+        let value = self
+            .1
+            .take()
+            .ok_or_else(|| SerdeError::Custom("map value without key".into()))?;
+        seed.deserialize(Deserializer(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+// Map keys are always strings. Numeric and bool keys are parsed on request.
+struct Key<'de>(&'de str);
+
+macro_rules! deserialize_parsed_key {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+                match self.0.parse() {
+                    Ok(v) => visitor.$visit(v),
+                    Err(_) => visitor.visit_borrowed_str(self.0),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Key<'de> {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    deserialize_parsed_key! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_enum(Enum(self.0, None))
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf option unit unit_struct seq
+        tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+// An enum variant name with its optional content.
+struct Enum<'de>(&'de str, Option<&'de Value>);
+
+impl<'de> EnumAccess<'de> for Enum<'de> {
+    type Error = SerdeError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, Self), SerdeError> {
+        let variant = seed.deserialize(self.0.into_deserializer())?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Enum<'de> {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        match self.1 {
+            None | Some(Value::Nil) => Ok(()),
+            Some(other) => Err(SerdeError::Custom(format!(
+                "unexpected content {} for unit variant {}",
+                other, self.0
+            ))),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, SerdeError> {
+        seed.deserialize(Deserializer(self.1.unwrap_or(&Value::Nil)))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        de::Deserializer::deserialize_any(Deserializer(self.1.unwrap_or(&Value::Nil)), visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        de::Deserializer::deserialize_any(Deserializer(self.1.unwrap_or(&Value::Nil)), visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests_mocked {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Kind {
+        Plain,
+        Sized(u32),
+        Pair(i8, i8),
+        Named { name: String },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Item {
+        id: u64,
+        delta: i32,
+        ratio: f64,
+        label: Option<String>,
+        #[serde(with = "bytes")]
+        raw: Vec<u8>,
+        tags: BTreeMap<u16, bool>,
+        kinds: Vec<Kind>,
+    }
+
+    mod bytes {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(v: &[u8], s: S) -> Result<S::Ok, S::Error> {
+            s.serialize_bytes(v)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+            Vec::deserialize(d)
+        }
+    }
+
+    fn item() -> Item {
+        let mut tags = BTreeMap::new();
+        tags.insert(7, true);
+        Item {
+            id: u64::MAX,
+            delta: -3,
+            ratio: 0.5,
+            label: None,
+            raw: vec![1, 2],
+            tags,
+            kinds: vec![
+                Kind::Plain,
+                Kind::Sized(4),
+                Kind::Pair(-1, 1),
+                Kind::Named { name: "x".into() },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_to_value() {
+        let value = to_value(&item()).unwrap();
+        let o = match value {
+            Value::Object(ref o) => o,
+            _ => panic!("expected an object, got {:?}", value),
+        };
+        assert_eq!(o["id"], Value::from(u64::MAX));
+        assert_eq!(o["delta"], Value::from(-3));
+        assert_eq!(o["ratio"], Value::from(0.5));
+        assert_eq!(o["label"], Value::Nil);
+        assert_eq!(o["raw"], Value::from(vec![1u8, 2]));
+        assert!(matches!(o["tags"], Value::Map(ref m) if m["7"] == Value::Bool(true)));
+        assert!(matches!(o["kinds"], Value::Array(ref a) if a[0] == Value::from("Plain")));
+
+        let key = to_value(&vec![(vec![1], 1)].into_iter().collect::<HashMap<_, _>>());
+        assert!(matches!(key, Err(SerdeError::InvalidKey(_))));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let value = to_value(&item()).unwrap();
+        let back: Item = from_value(&value).unwrap();
+        assert_eq!(back, item());
+
+        let err = from_value::<Item>(&Value::from("nope"));
+        assert!(err.is_err());
+    }
+}
@@ -1,27 +1,149 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
+use crate::compile::{Builtins, Compiled};
 use crate::error::{ParseError, TemplateError};
-use crate::funcs::BUILTINS;
+use crate::exec::{AsyncFunc, Limits};
+use crate::funcs::{FuncInfo, BUILTINS, BUILTIN_INFO};
 use crate::parse::{parse, Tree};
 
-use gtmpl_value::Func;
+use gtmpl_value::{Func, FuncError, Value};
 
 /// The main template structure.
 #[derive(Clone)]
 pub struct Template {
     pub name: String,
     pub text: String,
-    pub funcs: HashMap<String, Func>,
-    pub tree_set: HashMap<String, Tree>,
+    pub funcs: Tracked<HashMap<String, Func>>,
+    pub func_info: HashMap<String, FuncInfo>,
+    /// Functions awaited by `execute_async`, see `add_async_func`.
+    pub async_funcs: Tracked<HashMap<String, AsyncFunc>>,
+    /// Parse calls of unknown functions instead of rejecting them, e.g. to
+    /// report them with `lint`.
+    pub skip_func_check: bool,
+    /// Rejects templates that nest control structures and parenthesized
+    /// pipelines deeper than this. Parsing and compiling recurse along the
+    /// nesting, 64 levels fit into a 2 MB thread stack in debug builds.
+    /// `None`, the default, parses any nesting.
+    pub max_nesting_depth: Option<usize>,
+    /// Resource limits enforced by `execute` and `render`.
+    pub limits: Limits,
+    /// Restricts what the template may do, `None` allows everything.
+    pub sandbox: Option<Sandbox>,
+    pub tree_set: Tracked<HashMap<String, Tree>>,
+    /// The functions of `funcs` that are still builtins.
+    pub builtins: Builtins,
+    /// The cached program rendered by `render` and friends.
+    pub compiled: Compiled,
+}
+
+/// A value that notices when it is changed. Dereferencing it mutably gives it
+/// a new generation, which tells `Template` to compile its program again.
+pub struct Tracked<T> {
+    value: T,
+    generation: u64,
+}
+
+impl<T> Tracked<T> {
+    pub fn new(value: T) -> Tracked<T> {
+        Tracked {
+            value,
+            generation: next_generation(),
+        }
+    }
+
+    /// Changes with every mutable access, unique among all tracked values.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+fn next_generation() -> u64 {
+    static GENERATION: AtomicU64 = AtomicU64::new(0);
+    GENERATION.fetch_add(1, Ordering::Relaxed)
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Tracked<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.generation = next_generation();
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for Tracked<T> {
+    fn from(value: T) -> Tracked<T> {
+        Tracked::new(value)
+    }
+}
+
+impl<T: Default> Default for Tracked<T> {
+    fn default() -> Tracked<T> {
+        Tracked::new(T::default())
+    }
+}
+
+impl<T: Clone> Clone for Tracked<T> {
+    fn clone(&self) -> Tracked<T> {
+        Tracked {
+            value: self.value.clone(),
+            generation: self.generation,
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Tracked<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Tracked<T>
+where
+    &'a T: IntoIterator,
+{
+    type Item = <&'a T as IntoIterator>::Item;
+    type IntoIter = <&'a T as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.value.into_iter()
+    }
 }
 
 impl Default for Template {
     fn default() -> Template {
+        let funcs = Tracked::new(BUILTINS.iter().map(|&(k, v)| (k.to_owned(), v)).collect());
         Template {
             name: String::default(),
             text: String::from(""),
-            funcs: BUILTINS.iter().map(|&(k, v)| (k.to_owned(), v)).collect(),
-            tree_set: HashMap::default(),
+            builtins: Builtins::new(&funcs),
+            funcs,
+            func_info: BUILTIN_INFO
+                .iter()
+                .map(|(&k, v)| (k.to_owned(), v.clone()))
+                .collect(),
+            async_funcs: Tracked::default(),
+            skip_func_check: false,
+            max_nesting_depth: None,
+            limits: Limits::default(),
+            sandbox: None,
+            tree_set: Tracked::default(),
+            compiled: Compiled::default(),
         }
     }
 }
@@ -40,20 +162,54 @@ impl Template {
     /// ## Example
     ///
     /// ```rust
-    /// use gtmpl::{Context, Func, FuncError, Value};
+    /// use go_template::{Context, Func, FuncError, Value};
     ///
     /// fn hello_world(_args: &[Value]) -> Result<Value, FuncError> {
     ///   Ok(Value::from("Hello World!"))
     /// }
     ///
-    /// let mut tmpl = gtmpl::Template::default();
+    /// let mut tmpl = go_template::Template::default();
     /// tmpl.add_func("helloWorld", hello_world);
     /// tmpl.parse("{{ helloWorld }}").unwrap();
     /// let output = tmpl.render(&Context::empty());
     /// assert_eq!(&output.unwrap(), "Hello World!");
     /// ```
     pub fn add_func(&mut self, name: &str, func: Func) {
+        self.func_info.remove(name);
+        self.insert_func(name, func);
+    }
+
+    /// Adds a single custom function together with its metadata. Calls with a
+    /// wrong number of arguments are rejected when the template is parsed.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use go_template::funcs::FuncInfo;
+    /// use go_template::{Context, Func, FuncError, Value};
+    ///
+    /// fn hello(args: &[Value]) -> Result<Value, FuncError> {
+    ///   Ok(Value::from(format!("Hello {}!", args[0])))
+    /// }
+    ///
+    /// let mut tmpl = go_template::Template::default();
+    /// tmpl.add_func_with_info("hello", hello, FuncInfo::new(1, Some(1)).with_doc("Greets."));
+    /// assert!(tmpl.parse("{{ hello }}").is_err());
+    /// tmpl.parse(r#"{{ hello "World" }}"#).unwrap();
+    /// let output = tmpl.render(&Context::empty());
+    /// assert_eq!(&output.unwrap(), "Hello World!");
+    /// ```
+    pub fn add_func_with_info(&mut self, name: &str, func: Func, info: FuncInfo) {
+        self.insert_func(name, func);
+        self.func_info.insert(name.to_owned(), info);
+    }
+
+    fn insert_func(&mut self, name: &str, func: Func) {
+        self.async_funcs.remove(name);
+        let before = self.funcs.generation();
         self.funcs.insert(name.to_owned(), func);
+        self.builtins
+            .replaced(name, before, self.funcs.generation());
     }
 
     /// Adds custom functions to the template.
@@ -63,38 +219,76 @@ impl Template {
     /// ```rust
     /// use std::collections::HashMap;
     ///
-    /// use gtmpl::{Context, Func, FuncError, Value};
+    /// use go_template::{Context, Func, FuncError, Value};
     ///
     /// fn hello_world(_args: &[Value]) -> Result<Value, FuncError> {
     ///   Ok(Value::from("Hello World!"))
     /// }
     ///
     /// let funcs = vec![("helloWorld", hello_world as Func)];
-    /// let mut tmpl = gtmpl::Template::default();
+    /// let mut tmpl = go_template::Template::default();
     /// tmpl.add_funcs(&funcs);
     /// tmpl.parse("{{ helloWorld }}").unwrap();
     /// let output = tmpl.render(&Context::empty());
     /// assert_eq!(&output.unwrap(), "Hello World!");
     /// ```
     pub fn add_funcs<T: Into<String> + Clone>(&mut self, funcs: &[(T, Func)]) {
-        self.funcs
-            .extend(funcs.iter().cloned().map(|(k, v)| (k.into(), v)));
+        for (k, v) in funcs.iter().cloned() {
+            self.add_func(&k.into(), v);
+        }
     }
 
-    /// Parse the given `text` as template body.
+    /// Declares a function that is provided by the `Context` at render time, see
+    /// `Context::add_func`. Rendering fails if the context does not provide it.
+    pub fn declare_func(&mut self, name: &str) {
+        self.add_func(name, unbound);
+    }
+
+    /// Adds a function returning a future. It is awaited when the template is
+    /// rendered with `execute_async`, the sync renderers fail when calling it.
+    /// See `Template::execute_async` for an example.
+    pub fn add_async_func<F, R>(&mut self, name: &str, func: F)
+    where
+        F: Fn(Vec<Value>) -> R + Send + Sync + 'static,
+        R: Future<Output = Result<Value, FuncError>> + Send + 'static,
+    {
+        self.add_func(name, needs_async);
+        self.async_funcs
+            .insert(name.to_owned(), Arc::new(move |args| Box::pin(func(args))));
+    }
+
+    /// Lists all functions available to the template sorted by name together
+    /// with their metadata if known.
     ///
     /// ## Example
     ///
     /// ```rust
-    /// let mut tmpl = gtmpl::Template::default();
+    /// let tmpl = go_template::Template::default();
+    /// let (name, info) = tmpl.list_funcs()[0];
+    /// assert_eq!(name, "and");
+    /// assert_eq!(info.unwrap().min_args, 1);
+    /// ```
+    pub fn list_funcs(&self) -> Vec<(&str, Option<&FuncInfo>)> {
+        let mut funcs: Vec<_> = self
+            .funcs
+            .keys()
+            .map(|k| (k.as_str(), self.func_info.get(k)))
+            .collect();
+        funcs.sort_by_key(|&(k, _)| k);
+        funcs
+    }
+
+    /// Parse the given `text` as template body. Set `max_nesting_depth` before
+    /// parsing untrusted templates.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// let mut tmpl = go_template::Template::default();
     /// tmpl.parse("Hello World!").unwrap();
     /// ```
     pub fn parse<T: Into<String>>(&mut self, text: T) -> Result<(), ParseError> {
-        let tree_set = parse(
-            self.name.clone(),
-            text.into(),
-            self.funcs.keys().cloned().collect(),
-        )?;
+        let tree_set = self.parse_trees(self.name.clone(), text.into())?;
         self.tree_set.extend(tree_set);
         Ok(())
     }
@@ -104,9 +298,9 @@ impl Template {
     /// ## Example
     ///
     /// ```rust
-    /// use gtmpl::Context;
+    /// use go_template::Context;
     ///
-    /// let mut tmpl = gtmpl::Template::default();
+    /// let mut tmpl = go_template::Template::default();
     /// tmpl.add_template("fancy", "{{ . }}");
     /// tmpl.parse(r#"{{ template "fancy" . }}!"#).unwrap();
     /// let output = tmpl.render(&Context::from("Hello World"));
@@ -117,19 +311,97 @@ impl Template {
         name: N,
         text: T,
     ) -> Result<(), TemplateError> {
-        let tree_set = parse(
-            name.into(),
-            text.into(),
-            self.funcs.keys().cloned().collect(),
-        )?;
+        let tree_set = self.parse_trees(name.into(), text.into())?;
         self.tree_set.extend(tree_set);
         Ok(())
     }
+
+    /// Parses `text` as template `name` without adding the trees.
+    pub(crate) fn parse_trees(
+        &self,
+        name: String,
+        text: String,
+    ) -> Result<HashMap<String, Tree>, ParseError> {
+        parse(
+            name,
+            text,
+            self.funcs.keys().cloned().collect(),
+            self.func_info.clone(),
+            self.skip_func_check,
+            self.sandbox.clone(),
+            self.max_nesting_depth,
+        )
+    }
+}
+
+/// A sandbox profile for untrusted templates. By default it allows no
+/// functions, no `{{template}}` or `{{block}}` calls and no calls of functions
+/// stored in the data.
+///
+/// Violations are rejected when the template is parsed and again at render
+/// time, e.g. for trees parsed before the sandbox was set.
+///
+/// ## Example
+///
+/// ```rust
+/// use go_template::{Context, Sandbox, Template};
+///
+/// let mut tmpl = Template::default();
+/// tmpl.sandbox = Some(Sandbox::default().with_funcs(&["len", "eq"]));
+/// assert!(tmpl.parse(r#"{{ printf "%v" . }}"#).is_err());
+/// assert!(tmpl.parse(r#"{{ template "x" }}"#).is_err());
+/// tmpl.parse("{{ if eq (len .) 2 }}two{{ end }}").unwrap();
+/// assert_eq!(&tmpl.render(&Context::from("ab")).unwrap(), "two");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    /// The functions templates may call.
+    pub funcs: HashSet<String>,
+    /// Allow `{{template}}` and `{{block}}` to call other trees.
+    pub templates: bool,
+    /// Allow invoking functions from the data, with `call` or as methods.
+    pub data_funcs: bool,
+}
+
+impl Sandbox {
+    pub fn with_funcs(mut self, funcs: &[&str]) -> Sandbox {
+        self.funcs.extend(funcs.iter().map(|&f| f.to_owned()));
+        self
+    }
+
+    pub fn with_templates(mut self, allow: bool) -> Sandbox {
+        self.templates = allow;
+        self
+    }
+
+    pub fn with_data_funcs(mut self, allow: bool) -> Sandbox {
+        self.data_funcs = allow;
+        self
+    }
+
+    /// Returns `true` if templates may call the function `name`. `call` also
+    /// needs `data_funcs`.
+    pub fn allows_func(&self, name: &str) -> bool {
+        self.funcs.contains(name) && (name != "call" || self.data_funcs)
+    }
+}
+
+fn needs_async(_: &[Value]) -> Result<Value, FuncError> {
+    Err(FuncError::Generic(
+        "async function must be rendered with execute_async".into(),
+    ))
+}
+
+fn unbound(_: &[Value]) -> Result<Value, FuncError> {
+    Err(FuncError::Generic(
+        "function must be provided by the render context".into(),
+    ))
 }
 
 #[cfg(test)]
 mod tests_mocked {
     use super::*;
+    use crate::exec::Context;
 
     #[test]
     fn test_parse() {
@@ -137,4 +409,124 @@ mod tests_mocked {
         assert!(t.parse(r#"{{ if eq "bar" "bar" }} 2000 {{ end }}"#).is_ok());
         assert!(t.tree_set.contains_key("foo"));
     }
+
+    #[test]
+    fn test_parse_arity() {
+        let mut t = Template::default();
+        assert!(t.parse(r#"{{ if eq "bar" }} 2000 {{ end }}"#).is_err());
+        assert!(t.parse(r#"{{ not 1 2 }}"#).is_err());
+        assert!(t.parse(r#"{{ "foo" | printf "%s" | len }}"#).is_ok());
+
+        fn not(_: &[gtmpl_value::Value]) -> Result<gtmpl_value::Value, gtmpl_value::FuncError> {
+            Ok(true.into())
+        }
+        t.add_func("not", not);
+        assert!(!t.func_info.contains_key("not"));
+        assert!(t.parse(r#"{{ not 1 2 }}"#).is_ok());
+    }
+
+    #[test]
+    fn test_parse_nesting() {
+        let ifs = |n: usize| format!("{}x{}", "{{ if 1 }}".repeat(n), "{{ end }}".repeat(n));
+        let chain = |n: usize| {
+            format!(
+                "{{{{ if 0 }}}}{}x{{{{ end }}}}",
+                "{{ else if 0 }}".repeat(n)
+            )
+        };
+        let mut t = Template {
+            max_nesting_depth: Some(4),
+            ..Default::default()
+        };
+        assert!(t.parse(ifs(4)).is_ok());
+        assert_eq!(t.render(&Context::empty()).unwrap(), "x");
+        let err = t.parse(ifs(5)).unwrap_err();
+        assert!(err.to_string().ends_with("max nesting depth exceeded"));
+        assert!(t.parse(chain(400)).is_ok());
+        assert_eq!(t.render(&Context::empty()).unwrap(), "");
+
+        let mut t = Template::default();
+        assert!(t.parse(ifs(64)).is_ok());
+        assert_eq!(t.render(&Context::empty()).unwrap(), "x");
+    }
+
+    #[test]
+    fn test_sandbox() {
+        use crate::error::ExecError;
+        use crate::Context;
+        use std::collections::HashMap;
+
+        fn hello(_: &[Value]) -> Result<Value, FuncError> {
+            Ok("hello".into())
+        }
+        let data = || {
+            let mut m = HashMap::new();
+            m.insert("f".to_owned(), Value::from(hello as Func));
+            Context::from(m)
+        };
+
+        let sandbox = Sandbox::default().with_funcs(&["call", "index", "len"]);
+        let mut t = Template {
+            sandbox: Some(sandbox.clone()),
+            ..Default::default()
+        };
+        let err = t.parse("{{ len . }}{{ print 1 }}").unwrap_err();
+        assert!(err.to_string().ends_with("function print is not allowed"));
+        assert!(t.parse("{{ call .f }}").is_err());
+        assert!(t.parse(r#"{{ block "b" . }}{{ end }}"#).is_err());
+        assert!(t.add_template("x", "{{ len . }}").is_ok());
+
+        // Trees parsed before the sandbox was set are checked at render time.
+        let mut t = Template::default();
+        t.parse(r#"{{ template "x" }}"#).unwrap();
+        t.add_template("x", "x").unwrap();
+        t.add_template("p", "{{ print 1 }}").unwrap();
+        t.add_template("c", r#"{{ call (index . "f") }}"#).unwrap();
+        t.add_template("m", "{{ .f }}").unwrap();
+        t.add_template(
+            "try",
+            r#"{{ try }}{{ call (index . "f") }}{{ catch }}caught{{ end }}"#,
+        )
+        .unwrap();
+        assert_eq!(t.render(&data()).unwrap(), "x");
+        let run = |t: &mut Template, name: &str| {
+            t.name = name.to_owned();
+            t.render(&data())
+        };
+        assert_eq!(run(&mut t, "c").unwrap(), "hello");
+        t.sandbox = Some(sandbox.with_funcs(&["print"]));
+        assert!(matches!(
+            run(&mut t, ""),
+            Err(ExecError::TemplateCallNotAllowed(ref n)) if n == "x"
+        ));
+        assert!(matches!(
+            run(&mut t, "c"),
+            Err(ExecError::FuncNotAllowed(ref n)) if n == "call"
+        ));
+        assert!(matches!(
+            run(&mut t, "m"),
+            Err(ExecError::DataFuncNotAllowed)
+        ));
+        assert_eq!(run(&mut t, "p").unwrap(), "1");
+
+        // Violations can be neither caught nor skipped.
+        assert!(matches!(
+            run(&mut t, "try"),
+            Err(ExecError::FuncNotAllowed(ref n)) if n == "call"
+        ));
+        for name in &["", "c", "m"] {
+            t.name = name.to_string();
+            let rendered = t.render_lenient(&data(), "#").unwrap();
+            let err = &rendered.errors.last().unwrap().error;
+            assert!(err.is_fatal(), "{}", err);
+        }
+
+        t.sandbox = t
+            .sandbox
+            .take()
+            .map(|s| s.with_templates(true).with_data_funcs(true));
+        assert_eq!(run(&mut t, "").unwrap(), "x");
+        assert_eq!(run(&mut t, "c").unwrap(), "hello");
+        assert_eq!(run(&mut t, "m").unwrap(), "hello");
+    }
 }
@@ -0,0 +1,228 @@
+//! The token stream of the template lexer, e.g. for syntax highlighting.
+//!
+//! `tokenize` runs the same lexer the parser uses, so the tokens never diverge
+//! from real parsing. In addition it reports trivia the parser skips:
+//! comments, trim markers and the whitespace they remove. Together the tokens
+//! cover the source without gaps.
+//!
+//! ## Example
+//!
+//! ```rust
+//! use go_template::token::{tokenize, TokenKind};
+//!
+//! let src = "Hi {{- /* name */ -}} {{ .name }}";
+//! let tokens = tokenize(src);
+//! let kinds: Vec<_> = tokens.iter().map(|t| t.kind.clone()).collect();
+//! assert_eq!(
+//!     kinds,
+//!     vec![
+//!         TokenKind::Text,
+//!         TokenKind::TrimmedText,
+//!         TokenKind::Comment,
+//!         TokenKind::TrimmedText,
+//!         TokenKind::LeftDelim,
+//!         TokenKind::Space,
+//!         TokenKind::Field,
+//!         TokenKind::Space,
+//!         TokenKind::RightDelim,
+//!     ]
+//! );
+//! assert_eq!(&src[tokens[6].range.clone()], ".name");
+//! ```
+use std::ops::Range;
+
+use crate::lexer::{ItemType, Lexer};
+
+/// The kind of a token.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    /// Plain text outside of actions.
+    Text,
+    /// Left action delimiter.
+    LeftDelim,
+    /// Right action delimiter.
+    RightDelim,
+    LeftParen,
+    RightParen,
+    /// Whitespace separating arguments.
+    Space,
+    /// A comment action including its delimiters and trim markers.
+    Comment,
+    /// A trim marker next to a delimiter, e.g. `- ` in `{{- .x}}`.
+    TrimMarker,
+    /// Whitespace around an action that is removed by a trim marker.
+    TrimmedText,
+    Bool,
+    /// A character grab bag, e.g. `,`.
+    Char,
+    /// A character constant like `'a'`.
+    CharConstant,
+    Number,
+    /// A quoted string including the quotes.
+    String,
+    /// A raw string including the backquotes.
+    RawString,
+    /// `:=`
+    ColonEquals,
+    /// `=`
+    Assign,
+    /// `|`
+    Pipe,
+    /// A field like `.name`.
+    Field,
+    /// A function name.
+    Identifier,
+    /// A variable like `$x` or `$`.
+    Variable,
+    /// `.`
+    Dot,
+    Nil,
+    /// A keyword like `if`, `range` or `define`.
+    Keyword,
+    /// The lexer stopped with an error, the token covers the rest of the
+    /// source.
+    Error(String),
+}
+
+impl TokenKind {
+    /// Returns `true` for whitespace, comments and trim markers.
+    pub fn is_trivia(&self) -> bool {
+        matches!(
+            self,
+            TokenKind::Space | TokenKind::Comment | TokenKind::TrimMarker | TokenKind::TrimmedText
+        )
+    }
+}
+
+/// A token with its byte range in the source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub range: Range<usize>,
+}
+
+/// Splits `src` into tokens. If the lexer fails, the last token is a
+/// `TokenKind::Error`.
+pub fn tokenize(src: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    for item in Lexer::with_trivia(src.to_owned(), true) {
+        let kind = match item.typ {
+            ItemType::ItemText => TokenKind::Text,
+            ItemType::ItemLeftDelim => TokenKind::LeftDelim,
+            ItemType::ItemRightDelim => TokenKind::RightDelim,
+            ItemType::ItemLeftParen => TokenKind::LeftParen,
+            ItemType::ItemRightParen => TokenKind::RightParen,
+            ItemType::ItemSpace => TokenKind::Space,
+            ItemType::ItemComment => TokenKind::Comment,
+            ItemType::ItemTrimMarker => TokenKind::TrimMarker,
+            ItemType::ItemTrimmedText => TokenKind::TrimmedText,
+            ItemType::ItemBool => TokenKind::Bool,
+            ItemType::ItemChar => TokenKind::Char,
+            ItemType::ItemCharConstant => TokenKind::CharConstant,
+            ItemType::ItemComplex | ItemType::ItemNumber => TokenKind::Number,
+            ItemType::ItemString => TokenKind::String,
+            ItemType::ItemRawString => TokenKind::RawString,
+            ItemType::ItemColonEquals => TokenKind::ColonEquals,
+            ItemType::ItemAssign => TokenKind::Assign,
+            ItemType::ItemPipe => TokenKind::Pipe,
+            ItemType::ItemField => TokenKind::Field,
+            ItemType::ItemIdentifier => TokenKind::Identifier,
+            ItemType::ItemVariable => TokenKind::Variable,
+            ItemType::ItemDot => TokenKind::Dot,
+            ItemType::ItemNil => TokenKind::Nil,
+            ItemType::ItemKeyword
+            | ItemType::ItemBlock
+            | ItemType::ItemCapture
+            | ItemType::ItemCatch
+            | ItemType::ItemDefine
+            | ItemType::ItemElse
+            | ItemType::ItemEnd
+            | ItemType::ItemIf
+            | ItemType::ItemRange
+            | ItemType::ItemTemplate
+            | ItemType::ItemTry
+            | ItemType::ItemWith => TokenKind::Keyword,
+            ItemType::ItemEOF => break,
+            ItemType::ItemError => {
+                tokens.push(Token {
+                    kind: TokenKind::Error(item.val),
+                    range: item.pos..src.len(),
+                });
+                break;
+            }
+        };
+        tokens.push(Token {
+            kind,
+            range: item.pos..item.pos + item.val.len(),
+        });
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests_mocked {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(src)
+            .into_iter()
+            .map(|t| (t.kind, &src[t.range]))
+            .collect()
+    }
+
+    #[test]
+    fn test_tokens_cover_source() {
+        let srcs = [
+            "a {{- if eq .x 1 -}}\n b {{ else }}{{ $y := `r` }}{{ end }}",
+            "{{/* c */}} \t{{- /* d */ -}}\n\n x {{- 'c' | printf \"%c\" -}} ",
+            "{{ (len .) }}{{ $ }}{{ nil }}{{ .a.b }}",
+        ];
+        for src in &srcs {
+            let tokens = tokenize(src);
+            let mut pos = 0;
+            for t in &tokens {
+                assert_eq!(t.range.start, pos, "{:?} in {:?}", t, src);
+                pos = t.range.end;
+            }
+            assert_eq!(pos, src.len());
+        }
+    }
+
+    #[test]
+    fn test_trivia() {
+        assert_eq!(
+            kinds(" {{- 1 -}} {{/**/}}"),
+            vec![
+                (TokenKind::TrimmedText, " "),
+                (TokenKind::LeftDelim, "{{"),
+                (TokenKind::TrimMarker, "- "),
+                (TokenKind::Number, "1"),
+                (TokenKind::TrimMarker, " -"),
+                (TokenKind::RightDelim, "}}"),
+                (TokenKind::TrimmedText, " "),
+                (TokenKind::Comment, "{{/**/}}"),
+            ]
+        );
+        // Without trivia the lexer emits the items the parser sees.
+        let src = "a {{- .b -}} {{/* c */}}{{ d }}";
+        let parser: Vec<String> = Lexer::new(src.to_owned()).map(|i| i.val).collect();
+        let tokens: Vec<&str> = tokenize(src)
+            .into_iter()
+            .filter(|t| !t.kind.is_trivia() || t.kind == TokenKind::Space)
+            .map(|t| &src[t.range])
+            .collect();
+        assert_eq!(parser[..parser.len() - 1], tokens[..]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let src = "{{ .a }}{{ \"b }}";
+        let tokens = tokenize(src);
+        let last = tokens.last().unwrap();
+        assert_eq!(
+            last.kind,
+            TokenKind::Error("unterminated quoted string".into())
+        );
+        assert_eq!(&src[last.range.clone()], "\"b }}");
+    }
+}
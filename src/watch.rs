@@ -0,0 +1,282 @@
+//! Polling file watcher that keeps a `Template` in sync with its files.
+//!
+//! The watcher only compares modification times and sizes, so it works on any
+//! filesystem without notification services.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::template::Template;
+
+type Stamp = (SystemTime, u64);
+
+struct WatchedTemplate {
+    stamp: Option<Stamp>,
+    // Trees parsed from the file. Removed again when the file changes.
+    trees: Vec<String>,
+}
+
+/// What changed since the last `Watcher::poll`.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Template files that were parsed again.
+    pub templates: Vec<PathBuf>,
+    /// Template files that disappeared. Their templates were removed.
+    pub removed: Vec<PathBuf>,
+    /// Other watched files, e.g. data, that changed.
+    pub files: Vec<PathBuf>,
+    /// Files that could not be read or parsed.
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+            && self.removed.is_empty()
+            && self.files.is_empty()
+            && self.errors.is_empty()
+    }
+}
+
+/// Watches template files, directories of templates and other files.
+///
+/// Every template file is parsed as template named after its file name, like
+/// the templates passed to `gtmpl --define`.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use std::time::Duration;
+///
+/// use go_template::watch::Watcher;
+/// use go_template::{Context, Template};
+///
+/// let mut watcher = Watcher::new(Template::with_name("page.tmpl"));
+/// watcher.add_template_file("templates/page.tmpl");
+/// watcher.add_dir("templates/partials", Some("tmpl"));
+/// watcher.watch(Duration::from_millis(500), |tmpl, changes| {
+///     for (path, err) in &changes.errors {
+///         eprintln!("{}: {}", path.display(), err);
+///     }
+///     if let Ok(output) = tmpl.render(&Context::empty()) {
+///         std::fs::write("page.html", output).ok();
+///     }
+///     true
+/// });
+/// ```
+pub struct Watcher {
+    template: Template,
+    template_files: Vec<PathBuf>,
+    dirs: Vec<(PathBuf, Option<String>)>,
+    files: Vec<PathBuf>,
+    templates: HashMap<PathBuf, WatchedTemplate>,
+    stamps: HashMap<PathBuf, Option<Stamp>>,
+}
+
+impl Watcher {
+    /// Creates a watcher that parses files into `template`.
+    pub fn new(template: Template) -> Watcher {
+        Watcher {
+            template,
+            template_files: vec![],
+            dirs: vec![],
+            files: vec![],
+            templates: HashMap::new(),
+            stamps: HashMap::new(),
+        }
+    }
+
+    /// The template with all successfully parsed files.
+    pub fn template(&self) -> &Template {
+        &self.template
+    }
+
+    /// Watches a single template file.
+    pub fn add_template_file<P: Into<PathBuf>>(&mut self, path: P) {
+        self.template_files.push(path.into());
+    }
+
+    /// Watches all files in `dir` and its subdirectories with the given
+    /// `extension` (or all files) as templates.
+    pub fn add_dir<P: Into<PathBuf>>(&mut self, dir: P, extension: Option<&str>) {
+        self.dirs.push((
+            dir.into(),
+            extension.map(|e| e.trim_start_matches('.').to_owned()),
+        ));
+    }
+
+    /// Watches a file that is not a template, e.g. the data to render.
+    pub fn add_file<P: Into<PathBuf>>(&mut self, path: P) {
+        self.files.push(path.into());
+    }
+
+    /// Checks all watched files once. Changed templates are parsed again, all
+    /// other templates are kept. The first poll reports every file.
+    pub fn poll(&mut self) -> Changes {
+        let mut changes = Changes::default();
+        let mut seen = HashSet::new();
+        for path in self.template_paths(&mut changes) {
+            let stamp = stamp(&path);
+            seen.insert(path.clone());
+            match self.templates.get(&path) {
+                Some(w) if w.stamp == stamp => continue,
+                _ => {}
+            }
+            self.reload(&path, stamp, &mut changes);
+        }
+        let removed: Vec<PathBuf> = self
+            .templates
+            .keys()
+            .filter(|p| !seen.contains(*p))
+            .cloned()
+            .collect();
+        for path in removed {
+            if let Some(w) = self.templates.remove(&path) {
+                self.remove_trees(&w.trees);
+            }
+            changes.removed.push(path);
+        }
+        for path in &self.files {
+            let stamp = stamp(path);
+            if self.stamps.get(path) != Some(&stamp) {
+                self.stamps.insert(path.clone(), stamp);
+                if stamp.is_none() {
+                    changes
+                        .errors
+                        .push((path.clone(), "file does not exist".into()));
+                }
+                changes.files.push(path.clone());
+            }
+        }
+        changes
+    }
+
+    /// Polls every `interval` and calls `on_change` with the template whenever
+    /// something changed. Stops when `on_change` returns `false`.
+    pub fn watch<F>(&mut self, interval: Duration, mut on_change: F)
+    where
+        F: FnMut(&Template, &Changes) -> bool,
+    {
+        loop {
+            let changes = self.poll();
+            if !changes.is_empty() && !on_change(&self.template, &changes) {
+                return;
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    fn template_paths(&self, changes: &mut Changes) -> Vec<PathBuf> {
+        let mut paths = self.template_files.clone();
+        for (dir, ext) in &self.dirs {
+            if let Err(e) = walk_dir(dir, ext.as_deref(), &mut paths) {
+                changes.errors.push((dir.clone(), e.to_string()));
+            }
+        }
+        let mut unique = HashSet::new();
+        paths.retain(|p| unique.insert(fs::canonicalize(p).unwrap_or_else(|_| p.clone())));
+        paths
+    }
+
+    fn reload(&mut self, path: &Path, stamp: Option<Stamp>, changes: &mut Changes) {
+        if let Some(w) = self.templates.remove(path) {
+            self.remove_trees(&w.trees);
+        }
+        let mut trees = vec![];
+        match fs::read_to_string(path) {
+            Ok(text) => match self.template.parse_trees(file_name(path), text) {
+                Ok(tree_set) => {
+                    trees = tree_set.keys().cloned().collect();
+                    self.template.tree_set.extend(tree_set);
+                    changes.templates.push(path.to_owned());
+                }
+                Err(e) => changes.errors.push((path.to_owned(), e.to_string())),
+            },
+            Err(e) => changes.errors.push((path.to_owned(), e.to_string())),
+        }
+        self.templates
+            .insert(path.to_owned(), WatchedTemplate { stamp, trees });
+    }
+
+    fn remove_trees(&mut self, trees: &[String]) {
+        for name in trees {
+            self.template.tree_set.remove(name);
+        }
+    }
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn walk_dir(dir: &Path, ext: Option<&str>, paths: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            walk_dir(&path, ext, paths)?;
+        } else if ext.is_none() || path.extension().and_then(|e| e.to_str()) == ext {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests_mocked {
+    use super::*;
+    use crate::Context;
+
+    #[test]
+    fn test_poll() {
+        let dir = std::env::temp_dir().join(format!("gtmpl-watch-{}", std::process::id()));
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        let main = dir.join("main.tmpl");
+        let nav = dir.join("partials").join("nav.tmpl");
+        let data = dir.join("data.json");
+        fs::write(&main, r#"{{ template "nav" }}!"#).unwrap();
+        fs::write(&nav, r#"{{ define "nav" }}a{{ end }}"#).unwrap();
+        fs::write(dir.join("partials").join("notes.txt"), "{{").unwrap();
+
+        let mut w = Watcher::new(Template::with_name("main.tmpl"));
+        w.add_template_file(&main);
+        w.add_dir(&dir, Some("tmpl"));
+        w.add_file(&data);
+        let changes = w.poll();
+        assert_eq!(changes.templates, vec![main.clone(), nav.clone()]);
+        assert_eq!(changes.files, vec![data.clone()]);
+        assert_eq!(changes.errors.len(), 1);
+        assert_eq!(w.template().render(&Context::empty()).unwrap(), "a!");
+        assert!(w.poll().is_empty());
+
+        fs::write(&nav, r#"{{ define "nav" }}bb{{ end }}"#).unwrap();
+        fs::write(&data, "{}").unwrap();
+        let changes = w.poll();
+        assert_eq!(changes.templates, vec![nav.clone()]);
+        assert_eq!(changes.files, vec![data.clone()]);
+        assert!(changes.errors.is_empty());
+        assert_eq!(w.template().render(&Context::empty()).unwrap(), "bb!");
+
+        fs::write(&nav, r#"{{ define "nav" }}{{ end"#).unwrap();
+        let changes = w.poll();
+        assert_eq!(changes.errors.len(), 1);
+        assert!(w.template().render(&Context::empty()).is_err());
+
+        fs::remove_file(&nav).unwrap();
+        let changes = w.poll();
+        assert_eq!(changes.removed, vec![nav]);
+        assert!(!w.template().tree_set.contains_key("nav"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}